@@ -0,0 +1,83 @@
+use serde::{Deserialize, Serialize};
+
+// Stable, machine readable reasons a request was refused. Clients should branch
+// on these rather than the human readable message, which may change.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ErrorCode {
+    InvalidRequest,
+    InvalidConfig,
+    ImproperRound,
+    GameNotFound,
+    GameAlreadyStarted,
+    GameNotStarted,
+    GameNotInTaskRound,
+    GameNotInVoteRound,
+    GameAlreadyEnded,
+    GameAlreadyPaused,
+    GameNotPaused,
+    NotEnoughBuildings,
+    NotEnoughPlayers,
+    PlayerNotFound,
+    PlayerNotRegistered,
+    PlayerNotInGame,
+    PlayerAlreadyJoined,
+    PlayerInAnotherGame,
+    PlayerAlreadyRegistered,
+    LobbyFull,
+    ActionNotAllowed,
+    AlreadyActed,
+    TargetOutOfRange,
+    TargetIsBug,
+    TargetImmune,
+    AlreadyVoted,
+    CandidateNotInRunoff,
+    TaskAlreadyComplete,
+    TaskAlreadyUpdated,
+    NotNearTaskBuilding,
+    NotNearAnyBuilding,
+    BuildingNotFound,
+    HistoryNotFound,
+    MissingSession,
+    InvalidSession,
+    SessionMismatch,
+    InvalidSecret,
+    MissingAdminKey,
+    InvalidAdminKey,
+    AdminDisabled,
+    Conflict,
+    RateLimited,
+    InternalError,
+}
+
+impl ErrorCode {
+    // the HTTP status each code is served with
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorCode::InvalidRequest
+            | ErrorCode::InvalidConfig
+            | ErrorCode::ImproperRound => 400,
+            ErrorCode::MissingSession
+            | ErrorCode::InvalidSession
+            | ErrorCode::InvalidSecret
+            | ErrorCode::MissingAdminKey => 401,
+            ErrorCode::GameNotFound
+            | ErrorCode::PlayerNotFound
+            | ErrorCode::BuildingNotFound
+            | ErrorCode::HistoryNotFound => 404,
+            ErrorCode::PlayerAlreadyRegistered
+            | ErrorCode::TaskAlreadyUpdated
+            | ErrorCode::Conflict => 409,
+            ErrorCode::RateLimited => 429,
+            ErrorCode::InternalError => 500,
+            _ => 403,
+        }
+    }
+}
+
+// The JSON body of every error response
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ErrorBody {
+    pub code: ErrorCode,
+    pub message: String,
+}
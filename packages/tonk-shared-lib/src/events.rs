@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use crate::{AbilityUse, Action, ActionStatus, Elimination, Game, GameStatus, Player, Role, RoundResult, Task, Time, Vote, WinResult};
+use crate::redis_helper::RedisHelperError;
+use crate::store::{StateStore, StateStoreExt};
+
+// Every state change made to a game, in the order it happened. The log is kept
+// after the game is torn down so a finished game can be replayed.
+pub fn events_key(game_id: &str) -> String {
+    format!("game:{}:events", game_id)
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+pub enum Actor {
+    Player(String),
+    Admin,
+    StateService,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+pub enum GameEventKind {
+    // payloads that carry a whole game or player are boxed to keep the small events small
+    Created { game: Box<Game> },
+    PlayerJoined { player: Box<Player> },
+    PlayerLeft { player_id: String },
+    PlayerKicked { player_id: String },
+    PlayerReady { ready: bool },
+    PlayerRejoined { player_id: String },
+    Started { roles: Vec<(String, Role)>, time: Time, demo_play: bool },
+    TaskAssigned { task: Box<Task> },
+    TaskDroppedOff { round: u32, second: bool },
+    TaskCompleted { round: u32 },
+    Poisoned { action: Box<Action> },
+    ActionConfirmed { round: u32 },
+    AbilityUsed { ability_use: AbilityUse },
+    Voted { round: u32, vote: Box<Vote> },
+    RunoffCalled { round: u32, candidates: Vec<String> },
+    RoundResolved { round: u32, result: Box<RoundResult> },
+    RoundReset { round: u32 },
+    Eliminated { elimination: Box<Elimination> },
+    PhaseChanged { status: GameStatus, time: Time, win_result: Option<WinResult> },
+    Paused,
+    Resumed,
+    TimerSkipped,
+    Reset,
+    SeedChanged { seed: u64 },
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+pub struct GameEvent {
+    // milliseconds since the unix epoch
+    pub timestamp: u64,
+    pub actor: Actor,
+    pub kind: GameEventKind,
+}
+
+impl GameEvent {
+    pub fn new(actor: Actor, kind: GameEventKind) -> Self {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+        Self { timestamp, actor, kind }
+    }
+}
+
+pub async fn record_event(store: &dyn StateStore, game_id: &str, actor: Actor, kind: GameEventKind) -> Result<(), RedisHelperError> {
+    store.push_to_list(&events_key(game_id), &GameEvent::new(actor, kind)).await
+}
+
+// The state a game's event log rebuilds. The clock isn't logged, so timers
+// are the ones each phase started with.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug, Default)]
+pub struct Replay {
+    pub game: Option<Game>,
+    pub results: BTreeMap<u32, RoundResult>,
+    pub players: BTreeMap<String, Player>,
+}
+
+impl Replay {
+    pub fn apply(&mut self, event: &GameEvent) {
+        let actor_id = match &event.actor {
+            Actor::Player(id) => Some(id.clone()),
+            _ => None
+        };
+        match &event.kind {
+            GameEventKind::Created { game } => {
+                self.game = Some(game.as_ref().clone());
+            }
+            GameEventKind::PlayerJoined { player } => {
+                self.players.insert(player.id.clone(), player.as_ref().clone());
+            }
+            GameEventKind::PlayerLeft { player_id } | GameEventKind::PlayerKicked { player_id } => {
+                self.players.remove(player_id);
+            }
+            // readiness only matters while the lobby is open
+            GameEventKind::PlayerReady { .. } => {}
+            // a reconnect doesn't change the game
+            GameEventKind::PlayerRejoined { .. } => {}
+            GameEventKind::Started { roles, time, demo_play } => {
+                if let Some(game) = self.game.as_mut() {
+                    game.status = GameStatus::Tasks;
+                    game.time = Some(time.clone());
+                    game.demo_play = *demo_play;
+                }
+                for (player_id, role) in roles {
+                    if let Some(player) = self.players.get_mut(player_id) {
+                        player.role = Some(role.clone());
+                    }
+                }
+            }
+            // tasks are handed out without changing the player
+            GameEventKind::TaskAssigned { .. } => {}
+            GameEventKind::TaskDroppedOff { second, .. } => {
+                let status = if *second { ActionStatus::ReturnToTower } else { ActionStatus::NextDepot };
+                self.set_action(actor_id, status);
+            }
+            GameEventKind::TaskCompleted { round } => {
+                if let Some(player) = actor_id.and_then(|id| self.players.get_mut(&id)) {
+                    player.used_action = Some(ActionStatus::TaskComplete);
+                    player.last_round_action = Some(*round);
+                }
+            }
+            GameEventKind::ActionConfirmed { .. } => {
+                self.set_action(actor_id, ActionStatus::TaskComplete);
+            }
+            GameEventKind::Poisoned { .. } => {
+                self.set_action(actor_id, ActionStatus::ReturnToTower);
+            }
+            // protection and inspection only matter for the round they're used in
+            GameEventKind::AbilityUsed { .. } => {}
+            GameEventKind::Voted { round, .. } => {
+                if let Some(player) = actor_id.and_then(|id| self.players.get_mut(&id)) {
+                    player.used_action = Some(ActionStatus::Voted);
+                    player.last_round_action = Some(*round);
+                }
+            }
+            GameEventKind::RunoffCalled { candidates, .. } => {
+                if let Some(game) = self.game.as_mut() {
+                    game.runoff = Some(candidates.clone());
+                }
+                for player in self.players.values_mut().filter(|p| p.eliminated != Some(true)) {
+                    player.used_action = Some(ActionStatus::Unused);
+                }
+            }
+            GameEventKind::RoundResolved { round, result } => {
+                self.results.insert(*round, result.as_ref().clone());
+            }
+            GameEventKind::RoundReset { .. } => {
+                for player in self.players.values_mut().filter(|p| p.eliminated != Some(true)) {
+                    player.used_action = Some(ActionStatus::Unused);
+                }
+            }
+            GameEventKind::Eliminated { elimination } => {
+                let player_id = elimination.player.id.clone();
+                let mut role = elimination.player.role.clone();
+                if let Some(player) = self.players.get_mut(&player_id) {
+                    player.eliminated = Some(true);
+                    role = player.role.clone().or(role);
+                }
+                if let Some(game) = self.game.as_mut() {
+                    let eliminated = game.eliminated_players.get_or_insert_with(Vec::new);
+                    if eliminated.iter().all(|e| e.player.id != player_id) {
+                        eliminated.push(elimination.as_ref().clone());
+                    }
+                    // bugs that are removed from the game are kept as corrupted players
+                    if role.map(|r| r.is_bug()).unwrap_or(false) {
+                        let corrupted = game.corrupted_players.get_or_insert_with(Vec::new);
+                        if corrupted.iter().all(|p| p.id != player_id) {
+                            corrupted.push(elimination.player.clone());
+                        }
+                    }
+                }
+            }
+            GameEventKind::PhaseChanged { status, time, win_result } => {
+                if let Some(game) = self.game.as_mut() {
+                    game.status = status.clone();
+                    game.time = Some(time.clone());
+                    game.win_result = win_result.clone();
+                    // a runoff only lasts until its vote is resolved
+                    if *status != GameStatus::Vote {
+                        game.runoff = None;
+                    }
+                }
+            }
+            GameEventKind::Paused | GameEventKind::Resumed => {
+                if let Some(game) = self.game.as_mut() {
+                    game.paused = event.kind == GameEventKind::Paused;
+                }
+            }
+            GameEventKind::TimerSkipped => {
+                if let Some(time) = self.game.as_mut().and_then(|g| g.time.as_mut()) {
+                    time.timer = 0;
+                }
+            }
+            GameEventKind::Reset => {
+                if let Some(game) = self.game.as_mut() {
+                    game.status = GameStatus::Lobby;
+                    game.demo_play = false;
+                    game.paused = false;
+                    game.corrupted_players = None;
+                    game.eliminated_players = None;
+                    game.time = Some(Time { timer: 0, round: 0 });
+                    game.win_result = None;
+                }
+                self.results.clear();
+                for player in self.players.values_mut() {
+                    player.role = None;
+                    player.eliminated = None;
+                    player.last_round_action = None;
+                    player.used_action = Some(ActionStatus::Unused);
+                }
+            }
+            GameEventKind::SeedChanged { seed } => {
+                if let Some(game) = self.game.as_mut() {
+                    game.seed = *seed;
+                }
+            }
+        }
+    }
+
+    fn set_action(&mut self, player_id: Option<String>, status: ActionStatus) {
+        if let Some(player) = player_id.and_then(|id| self.players.get_mut(&id)) {
+            player.used_action = Some(status);
+        }
+    }
+}
+
+// Rebuilds the game from its log, applying the same events always gives the same state
+pub fn replay(events: &[GameEvent]) -> Replay {
+    let mut replay = Replay::default();
+    for event in events {
+        replay.apply(event);
+    }
+    replay
+}
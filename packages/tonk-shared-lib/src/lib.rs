@@ -1,7 +1,23 @@
+use std::collections::HashMap;
 use bincode::{config, Decode, Encode};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::ser::SerializeStruct;
 
+pub mod error;
+pub mod events;
+pub mod lobby;
+pub mod metrics;
+pub mod phase;
+pub mod presence;
 pub mod redis_helper;
+pub mod rng;
+pub mod roles;
+pub mod settings;
+pub mod spectator;
+pub mod store;
+pub mod stream;
+pub mod voting;
+pub mod win;
 
 #[derive(Serialize, Deserialize, Encode, Decode, Clone, PartialEq, Debug)]
 pub enum GameStatus {
@@ -10,12 +26,13 @@ pub enum GameStatus {
 
 #[derive(Serialize, Deserialize, Encode, Decode, Clone, PartialEq, Debug)]
 pub enum WinResult {
-    Thuggery, Democracy, Perfection, Armageddon, Null
+    Thuggery, Democracy, Perfection, Armageddon, Jester, Null
 }
 
+// what each role may do and how it wins is declared in roles
 #[derive(Serialize, Deserialize, Encode, Decode, Eq, Hash, PartialEq, Clone, Debug)]
 pub enum Role {
-    Normal, Bugged
+    Normal, Bugged, Medic, Detective, Jester
 }
 
 #[derive(Serialize, Deserialize, Encode, Decode, Eq, Hash, PartialEq, Clone, Debug)]
@@ -57,7 +74,7 @@ pub struct Building {
     pub is_tower: bool
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+#[derive(Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
 pub struct Game {
     pub id: String,
     pub status: GameStatus,
@@ -66,6 +83,152 @@ pub struct Game {
     pub corrupted_players: Option<Vec<Player>>,
     pub eliminated_players: Option<Vec<Elimination>>,
     pub demo_play: bool,
+    pub paused: bool,
+    pub config: GameConfig,
+    // drives role assignment and task depots, see rng
+    #[serde(default, deserialize_with = "deserialize_seed")]
+    pub seed: u64,
+    // the candidates of a runoff vote, only they can be voted for until it's resolved
+    #[serde(default)]
+    pub runoff: Option<Vec<String>>,
+    // the player who opened the lobby, they can kick players and start the game
+    #[serde(default)]
+    pub host: Option<String>,
+}
+
+// the seed gives away every role, so clients only see it once the game has ended
+impl Serialize for Game {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Game", 12)?;
+        state.serialize_field("id", &self.id)?;
+        state.serialize_field("status", &self.status)?;
+        state.serialize_field("time", &self.time)?;
+        state.serialize_field("win_result", &self.win_result)?;
+        state.serialize_field("corrupted_players", &self.corrupted_players)?;
+        state.serialize_field("eliminated_players", &self.eliminated_players)?;
+        state.serialize_field("demo_play", &self.demo_play)?;
+        state.serialize_field("paused", &self.paused)?;
+        state.serialize_field("config", &self.config)?;
+        let seed = if self.status == GameStatus::End { Some(self.seed) } else { None };
+        state.serialize_field("seed", &seed)?;
+        state.serialize_field("runoff", &self.runoff)?;
+        state.serialize_field("host", &self.host)?;
+        state.end()
+    }
+}
+
+fn deserialize_seed<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    Ok(Option::<u64>::deserialize(deserializer)?.unwrap_or(0))
+}
+
+// Rules and timings for a single game, chosen when the lobby is created
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+#[serde(default)]
+pub struct GameConfig {
+    pub task_duration: u32,
+    pub vote_duration: u32,
+    pub vote_result_duration: u32,
+    pub end_duration: u32,
+    pub bug_ratio: f64,
+    pub building_radius: i32,
+    pub immunity_radius: i32,
+    pub player_radius: i32,
+    pub thuggery_threshold: f64,
+    // special roles handed out to players who aren't bugs
+    pub medics: u32,
+    pub detectives: u32,
+    pub jesters: u32,
+    // the ways this game can end, see win
+    pub win_rules: Vec<win::WinRule>,
+    pub tie_policy: voting::TiePolicy,
+    // share of the ballots, abstentions included, the top candidate needs to be voted out
+    pub majority_threshold: Option<f64>,
+    // whether vote results show who voted for whom
+    pub reveal_ballots: bool,
+    pub min_players: u32,
+    pub max_players: u32,
+    // seconds the lobby counts down once every player is ready, None to wait for a start request
+    pub auto_start: Option<u32>,
+    // seconds without a heartbeat before a player counts as disconnected
+    pub heartbeat_timeout: u32,
+    // seconds a disconnected player is spared from inaction eliminations, None to eliminate them straight away
+    pub grace_period: Option<u32>,
+}
+
+impl Default for GameConfig {
+    fn default() -> Self {
+        Self {
+            task_duration: 180,
+            vote_duration: 90,
+            vote_result_duration: 30,
+            end_duration: 30,
+            bug_ratio: 0.25,
+            building_radius: 2,
+            immunity_radius: 4,
+            player_radius: 3,
+            thuggery_threshold: 0.5,
+            medics: 0,
+            detectives: 0,
+            jesters: 0,
+            win_rules: win::WinRule::all(),
+            tie_policy: voting::TiePolicy::NoElimination,
+            majority_threshold: None,
+            reveal_ballots: false,
+            min_players: 2,
+            max_players: 16,
+            auto_start: None,
+            heartbeat_timeout: 20,
+            grace_period: None,
+        }
+    }
+}
+
+impl GameConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        let durations = [
+            ("task_duration", self.task_duration),
+            ("vote_duration", self.vote_duration),
+            ("vote_result_duration", self.vote_result_duration),
+            ("end_duration", self.end_duration),
+        ];
+        for (name, duration) in durations {
+            if duration == 0 || duration > 3600 {
+                return Err(format!("{} must be between 1 and 3600 seconds", name));
+            }
+        }
+        if !(self.bug_ratio > 0.0 && self.bug_ratio < 1.0) {
+            return Err("bug_ratio must be between 0 and 1".to_string());
+        }
+        if !(self.thuggery_threshold > 0.0 && self.thuggery_threshold <= 1.0) {
+            return Err("thuggery_threshold must be greater than 0 and at most 1".to_string());
+        }
+        if let Some(threshold) = self.majority_threshold {
+            if !(threshold > 0.0 && threshold <= 1.0) {
+                return Err("majority_threshold must be greater than 0 and at most 1".to_string());
+            }
+        }
+        if self.min_players < 2 || self.max_players < self.min_players {
+            return Err("min_players must be at least 2 and at most max_players".to_string());
+        }
+        if let Some(countdown) = self.auto_start {
+            if countdown == 0 || countdown > 3600 {
+                return Err("auto_start must be between 1 and 3600 seconds".to_string());
+            }
+        }
+        if self.heartbeat_timeout == 0 || self.heartbeat_timeout > 3600 {
+            return Err("heartbeat_timeout must be between 1 and 3600 seconds".to_string());
+        }
+        if self.grace_period.map(|grace| grace > 3600).unwrap_or(false) {
+            return Err("grace_period must be at most 3600 seconds".to_string());
+        }
+        if self.win_rules.is_empty() {
+            return Err("win_rules must name at least one rule".to_string());
+        }
+        if self.building_radius < 1 || self.immunity_radius < 1 || self.player_radius < 1 {
+            return Err("proximity radii must be at least 1".to_string());
+        }
+        Ok(())
+    }
 }
 
 #[derive(Encode, Decode, Serialize, Deserialize, PartialEq, Clone, Debug)]
@@ -84,15 +247,27 @@ pub struct Action {
     pub poison_target: Player,
     pub interrupted_task: bool,
     pub confirmed: bool,
-    pub round: u32
+    pub round: u32,
+    pub actor: Option<String>,
+}
+
+// A role's ability used on another player, each player gets one per round
+#[derive(Encode, Decode, Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct AbilityUse {
+    pub ability: roles::Ability,
+    pub actor: String,
+    pub target: String,
+    pub round: u32,
 }
 
 #[derive(Serialize, Deserialize, Encode, Decode, Eq, PartialEq, Clone, Debug)]
 pub struct Vote {
-    pub candidate: Player,
+    // None is an abstain ballot, it counts as voting but is cast for nobody
+    pub candidate: Option<Player>,
+    pub voter: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+#[derive(Serialize, Deserialize, Encode, Decode, Eq, Hash, PartialEq, Clone, Debug)]
 pub enum EliminationReason {
     BuggedOut, VotedOut, Inaction 
 }
@@ -107,7 +282,106 @@ pub struct Elimination {
 pub struct RoundResult {
     pub round_type: GameStatus,
     pub eliminated: Option<Vec<Elimination>>,
-    pub tasks_completed: Option<Vec<Task>>
+    pub tasks_completed: Option<Vec<Task>>,
+    // every ballot counted, only set for vote rounds
+    #[serde(default)]
+    pub tally: Option<voting::VoteTally>,
+}
+
+// Everything that happened in one round, kept so the game can be reviewed after it ends
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+pub struct RoundArchive {
+    pub round: u32,
+    pub result: RoundResult,
+    pub votes: Vec<Vote>,
+    pub tasks: Vec<Task>,
+    pub actions: Vec<Action>,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+pub struct GameArchive {
+    pub game: Game,
+    pub players: Vec<Player>,
+    pub rounds: Vec<RoundArchive>,
+    pub archived_at: u64,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct GameSummary {
+    pub id: String,
+    pub win_result: Option<WinResult>,
+    pub rounds: u32,
+    pub players: usize,
+    pub archived_at: u64,
+}
+
+impl GameArchive {
+    pub fn summary(&self) -> GameSummary {
+        GameSummary {
+            id: self.game.id.clone(),
+            win_result: self.game.win_result.clone(),
+            rounds: self.game.time.as_ref().map(|t| t.round).unwrap_or(0),
+            players: self.players.len(),
+            archived_at: self.archived_at,
+        }
+    }
+}
+
+// Career totals for a player, accumulated every time one of their games ends
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug, Default)]
+pub struct PlayerStats {
+    pub player_id: String,
+    pub games_played: u32,
+    pub wins: u32,
+    pub wins_by_role: HashMap<Role, u32>,
+    pub tasks_completed: u32,
+    pub successful_poisons: u32,
+    pub correct_votes: u32,
+    pub eliminations: HashMap<EliminationReason, u32>,
+}
+
+impl PlayerStats {
+    pub fn new(player_id: &str) -> Self {
+        Self {
+            player_id: player_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn record_game(&mut self, archive: &GameArchive) {
+        let player = match archive.players.iter().find(|p| p.id == self.player_id) {
+            Some(player) => player,
+            None => return
+        };
+        self.games_played += 1;
+
+        if let (Some(role), Some(win_result)) = (player.role.as_ref(), archive.game.win_result.as_ref()) {
+            if role.wins(win_result) {
+                self.wins += 1;
+                *self.wins_by_role.entry(role.clone()).or_insert(0) += 1;
+            }
+        }
+
+        for round in &archive.rounds {
+            let interrupted = round.actions.iter().any(|a| {
+                a.interrupted_task && a.poison_target.id == self.player_id
+            });
+            self.tasks_completed += round.tasks.iter().filter(|t| {
+                t.complete && !interrupted && t.assignee.as_ref().map(|a| a.id == self.player_id).unwrap_or(false)
+            }).count() as u32;
+            self.successful_poisons += round.actions.iter().filter(|a| {
+                a.interrupted_task && a.actor.as_ref() == Some(&self.player_id)
+            }).count() as u32;
+            self.correct_votes += round.votes.iter().filter(|v| {
+                v.voter.as_ref() == Some(&self.player_id) && v.candidate.as_ref().and_then(|c| c.role.as_ref()).map(|r| r.is_bug()).unwrap_or(false)
+            }).count() as u32;
+            if let Some(eliminated) = round.result.eliminated.as_ref() {
+                for elimination in eliminated.iter().filter(|e| e.player.id == self.player_id) {
+                    *self.eliminations.entry(elimination.reason.clone()).or_insert(0) += 1;
+                }
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
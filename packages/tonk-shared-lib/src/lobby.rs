@@ -0,0 +1,149 @@
+use std::fmt;
+use crate::{Building, Game, GameStatus, Player, Time};
+use crate::error::ErrorCode;
+use crate::events::{record_event, Actor, GameEventKind};
+use crate::redis_helper::RedisHelperError;
+use crate::rng::assign_roles;
+use crate::store::{StateStore, StateStoreExt};
+use crate::stream::{game_channel, StreamMessage};
+
+// Players who have said they are ready to start, stored as player keys
+pub fn ready_key(game_id: &str) -> String {
+    format!("game:{}:ready", game_id)
+}
+
+#[derive(Debug)]
+pub enum StartError {
+    AlreadyStarted,
+    NotEnoughBuildings,
+    NotEnoughPlayers,
+    Conflict,
+    Store(RedisHelperError),
+}
+
+impl StartError {
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            StartError::AlreadyStarted => ErrorCode::GameAlreadyStarted,
+            StartError::NotEnoughBuildings => ErrorCode::NotEnoughBuildings,
+            StartError::NotEnoughPlayers => ErrorCode::NotEnoughPlayers,
+            StartError::Conflict => ErrorCode::Conflict,
+            StartError::Store(_) => ErrorCode::InternalError,
+        }
+    }
+}
+
+impl fmt::Display for StartError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartError::AlreadyStarted => write!(f, "Game is already started"),
+            StartError::NotEnoughBuildings => write!(f, "Need to register all the proper buildings before the game can begin"),
+            StartError::NotEnoughPlayers => write!(f, "More players need to join the game before we can start"),
+            StartError::Conflict => write!(f, "The game changed while it was being started"),
+            StartError::Store(_) => write!(f, "Unknown error"),
+        }
+    }
+}
+
+impl std::error::Error for StartError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StartError::Store(err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+impl From<RedisHelperError> for StartError {
+    fn from(err: RedisHelperError) -> StartError {
+        match err {
+            RedisHelperError::Conflict => StartError::Conflict,
+            _ => StartError::Store(err)
+        }
+    }
+}
+
+// Assigns roles and moves a lobby into the first task round. Started by a player,
+// an admin, or the state service once an auto-start countdown runs out.
+// An admin can force the start, which skips the building and player count checks.
+pub async fn start_game(store: &dyn StateStore, game: Game, force: bool, actor: Actor) -> Result<Game, StartError> {
+    let lobby = game.clone();
+    let mut current_game = game;
+    if current_game.status != GameStatus::Lobby {
+        return Err(StartError::AlreadyStarted);
+    }
+    let game_key = format!("game:{}", current_game.id);
+
+    // check buildings exists
+    let buildings: Vec<Building> = store.get_index("building:index").await?;
+    let found_tower = buildings.iter().any(|e| e.is_tower);
+
+    if !force && (!found_tower || buildings.len() <= 3) {
+        return Err(StartError::NotEnoughBuildings);
+    }
+
+    let index_key = format!("game:{}:player_index", current_game.id);
+    let players: Vec<Player> = store.get_index(&index_key).await?;
+
+    let min_players = current_game.config.min_players as usize;
+    if players.is_empty() || (!force && players.len() < min_players) {
+        return Err(StartError::NotEnoughPlayers);
+    }
+
+    // the roles come from the game's seed, the same players and seed always get the same roles
+    let new_players = assign_roles(&players, &current_game.config, current_game.seed);
+
+    current_game.status = GameStatus::Tasks;
+    current_game.time = Some(Time {
+        round: 0,
+        timer: current_game.config.task_duration,
+    });
+
+    // a special case game where certain rules don't apply to allow for a demo
+    if players.len() == 2 {
+        current_game.demo_play = true;
+    }
+
+    // only the request that moves the game out of the lobby gets to hand out roles
+    let started = store.compare_and_set_key(&game_key, &lobby, &current_game).await?;
+    if !started {
+        return Err(StartError::Conflict);
+    }
+
+    for player in new_players.iter() {
+        let player_key = format!("player:{}", player.id);
+        let role = player.role.clone();
+        let _ = store.update_key(&player_key, |mut p: Player| {
+            p.role = role.clone();
+            Some(p)
+        }).await;
+    }
+    store.clear_index(&ready_key(&current_game.id)).await?;
+    record_event(store, &current_game.id, actor, GameEventKind::Started {
+        roles: new_players.iter().map(|p| (p.id.clone(), p.role.clone().unwrap())).collect(),
+        time: current_game.time.clone().unwrap(),
+        demo_play: current_game.demo_play
+    }).await?;
+
+    store.publish(&game_channel(&current_game.id), &StreamMessage::Game(current_game.clone())).await?;
+
+    Ok(current_game)
+}
+
+// Whether the lobby's auto-start countdown should run: the game has one, enough
+// players have joined and all of them are ready. A player about to mark themselves
+// ready can be counted early so the countdown is armed before the state service
+// could see everyone ready.
+pub async fn everyone_ready(store: &dyn StateStore, game: &Game, also_ready: Option<&str>) -> Result<bool, RedisHelperError> {
+    if game.config.auto_start.is_none() {
+        return Ok(false);
+    }
+    let index_key = format!("game:{}:player_index", game.id);
+    let player_keys = store.get_index_keys(&index_key).await?;
+    if player_keys.len() < game.config.min_players as usize {
+        return Ok(false);
+    }
+    let ready_keys = store.get_index_keys(&ready_key(&game.id)).await?;
+    let also_ready = also_ready.map(|id| format!("player:{}", id));
+    Ok(player_keys.iter().all(|k| ready_keys.contains(k) || also_ready.as_ref() == Some(k)))
+}
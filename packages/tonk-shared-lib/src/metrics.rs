@@ -0,0 +1,100 @@
+use std::future::Future;
+use std::sync::OnceLock;
+use std::time::Instant;
+use log::*;
+use prometheus::core::Collector;
+use prometheus::{HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder};
+
+// Process-wide metrics for both services, kept in a prometheus registry and
+// served in its text format.
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub redis_operation_duration: HistogramVec,
+    pub redis_errors: IntCounterVec,
+    pub job_runs: IntCounterVec,
+    pub job_errors: IntCounterVec,
+    pub job_duration: HistogramVec,
+    pub sync_graph_query_errors: IntCounter,
+    pub games: IntGaugeVec,
+    pub game_players: IntGaugeVec,
+    pub game_round: IntGaugeVec,
+    pub game_phase: IntGaugeVec,
+}
+
+// the names and labels are fixed, so registering them can only fail on a typo
+fn register<C: Collector + Clone + 'static>(registry: &Registry, collector: prometheus::Result<C>) -> C {
+    let collector = collector.expect("invalid metric definition");
+    registry.register(Box::new(collector.clone())).expect("metric registered twice");
+    collector
+}
+
+fn counter(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    register(registry, IntCounterVec::new(Opts::new(name, help), labels))
+}
+
+fn gauge(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    register(registry, IntGaugeVec::new(Opts::new(name, help), labels))
+}
+
+// the default buckets run from 5ms to 10s
+fn histogram(registry: &Registry, name: &str, help: &str, labels: &[&str]) -> HistogramVec {
+    register(registry, HistogramVec::new(HistogramOpts::new(name, help), labels))
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        Self {
+            http_requests: counter(&registry, "tonk_http_requests_total", "HTTP requests handled, by route, method and status", &["route", "method", "status"]),
+            http_request_duration: histogram(&registry, "tonk_http_request_duration_seconds", "Time taken to answer an HTTP request, by route and method", &["route", "method"]),
+            redis_operation_duration: histogram(&registry, "tonk_redis_operation_duration_seconds", "Time taken by a Redis operation", &["operation"]),
+            redis_errors: counter(&registry, "tonk_redis_errors_total", "Redis operations that failed, a missing key is not counted", &["operation"]),
+            job_runs: counter(&registry, "tonk_job_runs_total", "Times a state service job has run", &["job"]),
+            job_errors: counter(&registry, "tonk_job_errors_total", "Job runs that failed, and games a job failed to update", &["job"]),
+            job_duration: histogram(&registry, "tonk_job_duration_seconds", "Time taken by one run of a job", &["job"]),
+            sync_graph_query_errors: register(&registry, IntCounter::new("tonk_sync_graph_query_errors_total", "Downstream graph queries that failed")),
+            games: gauge(&registry, "tonk_games", "Games in the registry, by phase", &["phase"]),
+            game_players: gauge(&registry, "tonk_game_players", "Players still in a game", &["game"]),
+            game_round: gauge(&registry, "tonk_game_round", "The round a game is in", &["game"]),
+            game_phase: gauge(&registry, "tonk_game_phase", "1 for the phase a game is in", &["game", "phase"]),
+            registry,
+        }
+    }
+
+    pub fn render(&self) -> String {
+        TextEncoder::new().encode_to_string(&self.registry.gather()).unwrap_or_else(|e| {
+            error!("couldn't encode the metrics: {}", e);
+            String::new()
+        })
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// the metrics shared by everything in the process
+pub fn get() -> &'static Metrics {
+    static METRICS: OnceLock<Metrics> = OnceLock::new();
+    METRICS.get_or_init(Metrics::new)
+}
+
+// Runs a job once, counting the run, its time and whether it failed
+pub async fn track_job<T, E, F>(job: &'static str, run: F) -> Result<T, E>
+where
+    F: Future<Output = Result<T, E>>
+{
+    let started = Instant::now();
+    let result = run.await;
+    let metrics = get();
+    metrics.job_runs.with_label_values(&[job]).inc();
+    metrics.job_duration.with_label_values(&[job]).observe(started.elapsed().as_secs_f64());
+    if result.is_err() {
+        metrics.job_errors.with_label_values(&[job]).inc();
+    }
+    result
+}
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+use crate::{Game, GameConfig, GameStatus, Time, WinResult};
+
+// The parts of a game the phase machine moves along
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct GameSnapshot {
+    pub status: GameStatus,
+    pub time: Time,
+    pub paused: bool,
+    pub win_result: Option<WinResult>,
+    pub config: GameConfig,
+}
+
+impl GameSnapshot {
+    pub fn from_game(game: &Game) -> Self {
+        Self {
+            status: game.status.clone(),
+            time: game.time.clone().unwrap_or(Time { round: 0, timer: 0 }),
+            paused: game.paused,
+            win_result: game.win_result.clone(),
+            config: game.config.clone(),
+        }
+    }
+
+    pub fn apply_to(&self, game: &mut Game) {
+        game.status = self.status.clone();
+        game.time = Some(self.time.clone());
+        game.paused = self.paused;
+        game.win_result = self.win_result.clone();
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum Event {
+    // the state service looked at the game, all_in is whether every player has
+    // finished their task or vote for the current phase, or is ready in the lobby
+    Tick { all_in: bool },
+    // the votes were counted, runoff is whether the vote has to be held again
+    VotesCounted { runoff: bool },
+    // the round that just ended was resolved and the win rules were checked
+    Resolved { win_result: WinResult },
+}
+
+// Work the state service carries out for a transition, in order
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum Effect {
+    // hand out roles and start the first task round
+    StartGame,
+    // work out who was bugged out and which tasks were done
+    ResolveTasks,
+    // count the votes and work out who was voted out, the outcome comes back as Event::VotesCounted
+    ResolveVotes,
+    // check the win rules, the verdict comes back as Event::Resolved
+    CheckWin,
+    // keep the roster as the game ended, then tidy up after the win
+    CleanUpWin(WinResult),
+    // add the round's eliminations to the game
+    UpdateEliminated,
+    // remove eliminated players and clear the round's actions, tasks and votes
+    ResetRound,
+    // write the snapshot back to the game
+    SaveGame,
+    // archive the finished game and clear its state
+    Teardown,
+}
+
+fn with_time(snapshot: &GameSnapshot, status: GameStatus, timer: u32, round: u32) -> GameSnapshot {
+    GameSnapshot {
+        status,
+        time: Time { timer, round },
+        ..snapshot.clone()
+    }
+}
+
+// After a round is resolved the game either ends or moves on to the next phase
+fn next_round(snapshot: &GameSnapshot, win_result: WinResult, status: GameStatus, timer: u32, mut effects: Vec<Effect>) -> (GameSnapshot, Vec<Effect>) {
+    let round = snapshot.time.round;
+    if win_result == WinResult::Null {
+        return (with_time(snapshot, status, timer, round + 1), effects);
+    }
+    // the final round is applied first so the players are kept as they ended the game
+    effects.push(Effect::CleanUpWin(win_result.clone()));
+    let mut next = with_time(snapshot, GameStatus::End, snapshot.config.end_duration, round);
+    next.win_result = Some(win_result);
+    (next, effects)
+}
+
+// Lobby -> Tasks -> Vote -> VoteResult -> Tasks, until a round ends the game.
+// The timers are counted down by the clock. Games are started by the web server,
+// or here once a lobby's auto-start countdown has run out with everyone ready.
+pub fn reduce(snapshot: GameSnapshot, event: Event) -> (GameSnapshot, Vec<Effect>) {
+    // a paused game stays exactly where the admin left it
+    if snapshot.paused {
+        return (snapshot, vec![]);
+    }
+    let round = snapshot.time.round;
+    let timer = snapshot.time.timer;
+    match (snapshot.status.clone(), event) {
+        (GameStatus::Tasks, Event::Tick { all_in }) | (GameStatus::Vote, Event::Tick { all_in }) if all_in && timer > 0 => {
+            // everyone is done, so the phase ends on the next tick
+            let next = with_time(&snapshot, snapshot.status.clone(), 0, round);
+            (next, vec![Effect::SaveGame])
+        }
+        (GameStatus::Lobby, Event::Tick { all_in }) if all_in && timer == 0 => {
+            (snapshot, vec![Effect::StartGame])
+        }
+        (GameStatus::Tasks, Event::Tick { .. }) if timer == 0 => {
+            (snapshot, vec![Effect::ResolveTasks, Effect::CheckWin])
+        }
+        (GameStatus::Tasks, Event::Resolved { win_result }) => {
+            let effects = vec![Effect::UpdateEliminated, Effect::ResetRound, Effect::SaveGame];
+            next_round(&snapshot, win_result, GameStatus::Vote, snapshot.config.vote_duration, effects)
+        }
+        (GameStatus::Vote, Event::Tick { .. }) if timer == 0 => {
+            (snapshot, vec![Effect::ResolveVotes])
+        }
+        (GameStatus::Vote, Event::VotesCounted { runoff }) => {
+            // a runoff is voted on in the same round with a fresh timer
+            let next = if runoff {
+                with_time(&snapshot, GameStatus::Vote, snapshot.config.vote_duration, round)
+            } else {
+                with_time(&snapshot, GameStatus::VoteResult, snapshot.config.vote_result_duration, round)
+            };
+            (next, vec![Effect::SaveGame])
+        }
+        (GameStatus::VoteResult, Event::Tick { .. }) if timer == 0 => {
+            (snapshot, vec![Effect::CheckWin])
+        }
+        (GameStatus::VoteResult, Event::Resolved { win_result }) => {
+            let effects = vec![Effect::ResetRound, Effect::UpdateEliminated, Effect::SaveGame];
+            next_round(&snapshot, win_result, GameStatus::Tasks, snapshot.config.task_duration, effects)
+        }
+        (GameStatus::End, Event::Tick { .. }) if timer == 0 => {
+            (snapshot, vec![Effect::Teardown])
+        }
+        // nothing moves until its timer runs out
+        _ => (snapshot, vec![])
+    }
+}
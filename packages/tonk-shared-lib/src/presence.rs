@@ -0,0 +1,41 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::GameConfig;
+use crate::redis_helper::RedisHelperError;
+use crate::store::{StateStore, StateStoreExt};
+
+// When the player's client last checked in, in seconds since the epoch
+pub fn last_seen_key(player_id: &str) -> String {
+    format!("player:{}:last_seen", player_id)
+}
+
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+pub async fn touch(store: &dyn StateStore, player_id: &str) -> Result<(), RedisHelperError> {
+    store.set_key(&last_seen_key(player_id), &now()).await
+}
+
+// None for a client that has never sent a heartbeat
+pub async fn last_seen(store: &dyn StateStore, player_id: &str) -> Result<Option<u64>, RedisHelperError> {
+    match store.get_key(&last_seen_key(player_id)).await {
+        Ok(seen) => Ok(Some(seen)),
+        Err(RedisHelperError::MissingKey) => Ok(None),
+        Err(e) => Err(e)
+    }
+}
+
+// Whether a player who didn't finish their task or vote is spared from being
+// eliminated for inaction. Only players whose client has gone quiet are excused,
+// and only until they have been gone for longer than the grace period.
+// Clients that never sent a heartbeat are treated like connected ones.
+pub fn is_excused(config: &GameConfig, last_seen: Option<u64>, now: u64) -> bool {
+    match (config.grace_period, last_seen) {
+        (Some(grace_period), Some(seen)) => {
+            let silent = now.saturating_sub(seen);
+            let timeout = config.heartbeat_timeout as u64;
+            silent > timeout && silent <= timeout + grace_period as u64
+        }
+        _ => false
+    }
+}
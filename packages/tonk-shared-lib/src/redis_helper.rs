@@ -1,34 +1,74 @@
 use std::error::Error;
-use redis::{AsyncCommands, RedisResult, aio::Connection, RedisError};
-use bincode::{Decode, Encode, error};
-use tokio::sync::Mutex;
-use crate::{deserialize_struct, serialize_struct};
-use std::env;
-
-pub async fn get_connection() -> RedisResult<Connection> {
-    let redis_url = env::var("REDIS_URL").unwrap();
+use std::future::Future;
+use std::time::Instant;
+use redis::{AsyncCommands, RedisResult, aio::Connection, aio::ConnectionManager, RedisError};
+use bincode::error;
+use async_trait::async_trait;
+use crate::metrics;
+use crate::store::StateStore;
+use crate::stream::StreamMessage;
+
+pub async fn get_connection(redis_url: &str) -> RedisResult<Connection> {
     let client = redis::Client::open(redis_url)?;
     client.get_async_connection().await
 }
 
+// GET and SET in one step on the server, so nothing can write the key in between
+const COMPARE_AND_SET: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    redis.call('SET', KEYS[1], ARGV[2])
+    return 1
+end
+return 0
+";
+
+// INCR and EXPIRE in one step, the window starts with the first hit
+const INCREMENT_COUNTER: &str = r"
+local count = redis.call('INCR', KEYS[1])
+if count == 1 then
+    redis.call('EXPIRE', KEYS[1], ARGV[1])
+end
+return {count, redis.call('TTL', KEYS[1])}
+";
+
+// SCARD and SADD in one step, a key already in the set is left where it is
+const ADD_TO_INDEX_IF_BELOW: &str = r"
+if redis.call('SISMEMBER', KEYS[1], ARGV[1]) == 1 then
+    return 1
+end
+if redis.call('SCARD', KEYS[1]) >= tonumber(ARGV[2]) then
+    return 0
+end
+redis.call('SADD', KEYS[1], ARGV[1])
+return 1
+";
+
+// Commands from every caller are multiplexed over one connection, which is
+// re-established automatically if it drops. Cloning the manager is cheap.
+#[derive(Clone)]
 pub struct RedisHelper {
-    con: Mutex<Connection>
+    con: ConnectionManager
 }
 
+// the underlying serialization or redis error is kept as the source for logging
 #[derive(Debug)]
 pub enum RedisHelperError {
-    MissingKey, Deserialization, Serialization, RedisError, Unknown 
+    MissingKey,
+    Deserialization(Box<dyn Error + Send + Sync>),
+    Serialization(Box<dyn Error + Send + Sync>),
+    RedisError(RedisError),
+    Conflict,
+    Unknown 
 } 
 
 
 impl std::error::Error for RedisHelperError {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
-            RedisHelperError::MissingKey => "Error: object is missing from the state",
-            RedisHelperError::Deserialization => "Error: deserialization error",
-            RedisHelperError::Serialization => "Error: serialization error",
-            RedisHelperError::RedisError => "Error: redis error",
-            RedisHelperError::Unknown => "Error: unknown error",
+            RedisHelperError::Deserialization(err) => Some(err.as_ref()),
+            RedisHelperError::Serialization(err) => Some(err.as_ref()),
+            RedisHelperError::RedisError(err) => Some(err),
+            _ => None
         }
     }
 }
@@ -37,9 +77,10 @@ impl std::fmt::Display for RedisHelperError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             RedisHelperError::MissingKey => write!(f, "Error: object is missing from the state"),
-            RedisHelperError::Deserialization => write!(f, "Error: deserialization error"),
-            RedisHelperError::Serialization => write!(f, "Error: serialization error"),
-            RedisHelperError::RedisError => write!(f, "Error: redis error"),
+            RedisHelperError::Deserialization(_) => write!(f, "Error: deserialization error"),
+            RedisHelperError::Serialization(_) => write!(f, "Error: serialization error"),
+            RedisHelperError::RedisError(_) => write!(f, "Error: redis error"),
+            RedisHelperError::Conflict => write!(f, "Error: the state kept changing while it was being updated"),
             RedisHelperError::Unknown => write!(f, "Error: unknown error")
         }
     }
@@ -47,96 +88,183 @@ impl std::fmt::Display for RedisHelperError {
 
 impl From<error::DecodeError> for RedisHelperError {
     fn from(err: error::DecodeError) -> RedisHelperError {
-        RedisHelperError::Deserialization
+        RedisHelperError::Deserialization(Box::new(err))
     }
 }
 impl From<error::EncodeError> for RedisHelperError {
     fn from(err: error::EncodeError) -> RedisHelperError {
-        RedisHelperError::Serialization
+        RedisHelperError::Serialization(Box::new(err))
     }
 }
 impl From<RedisError> for RedisHelperError {
     fn from(err: RedisError) -> RedisHelperError {
-        //maybe log the error?
-        RedisHelperError::RedisError
+        RedisHelperError::RedisError(err)
     }
 }
 
 
 impl RedisHelper {
-    pub async fn init() -> Result<Self, RedisHelperError> {
-        let con = get_connection().await?;
-        Ok(Self { con: Mutex::new(con) })
+    pub async fn init(redis_url: &str) -> Result<Self, RedisHelperError> {
+        let client = redis::Client::open(redis_url)?;
+        let con = ConnectionManager::new(client).await?;
+        Ok(Self { con })
     }
+}
 
-    pub async fn get_key<T: Decode>(&self, key: &str) -> Result<T, RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let exists: bool = con_guard.exists(key).await?;
-        if !exists {
-            return Err(RedisHelperError::MissingKey);
-        }
-        let result: Vec<u8> = con_guard.get(key).await?;
-        let deserialized = deserialize_struct(&result)?;
-        Ok(deserialized)
-    }
-    pub async fn get_key_test(&self, key: &str) -> Result<String, RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let exists: bool = con_guard.exists(key).await?;
-        if !exists {
-            return Err(RedisHelperError::MissingKey);
+// Times the operation and counts it if it failed. A missing key is an expected
+// answer rather than a failure.
+async fn timed<T, F>(operation: &'static str, run: F) -> Result<T, RedisHelperError>
+where
+    F: Future<Output = Result<T, RedisHelperError>>
+{
+    let started = Instant::now();
+    let result = run.await;
+    let metrics = metrics::get();
+    metrics.redis_operation_duration.with_label_values(&[operation]).observe(started.elapsed().as_secs_f64());
+    if let Err(e) = &result {
+        if !matches!(e, RedisHelperError::MissingKey) {
+            metrics.redis_errors.with_label_values(&[operation]).inc();
         }
-        let result: String = con_guard.get(key).await?;
-        Ok(result)
     }
+    result
+}
 
-    pub async fn set_key<T: Encode>(&self, key: &str, obj: &T) -> Result<(), RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let vec = serialize_struct(obj)?;
-        let _ = con_guard.set(key, vec).await?;
-        Ok(())
+#[async_trait]
+impl StateStore for RedisHelper {
+    async fn get_raw(&self, key: &str) -> Result<Vec<u8>, RedisHelperError> {
+        timed("get", async {
+            let mut con = self.con.clone();
+            let result: Option<Vec<u8>> = con.get(key).await?;
+            result.ok_or(RedisHelperError::MissingKey)
+        }).await
     }
 
-    pub async fn clear_key(&self, key: &str) -> Result<(), RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let _ = con_guard.del(key).await?;
-        Ok(())
+    async fn set_raw(&self, key: &str, value: Vec<u8>) -> Result<(), RedisHelperError> {
+        timed("set", async {
+            let mut con = self.con.clone();
+            let _: () = con.set(key, value).await?;
+            Ok(())
+        }).await
     }
 
-    pub async fn add_to_index(&self, index: &str, key: &str) -> Result<(), RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let _ = con_guard.sadd(index, key).await?;
-        Ok(())
+    async fn set_raw_if_absent(&self, key: &str, value: Vec<u8>) -> Result<bool, RedisHelperError> {
+        timed("set_if_absent", async {
+            let mut con = self.con.clone();
+            let written: bool = con.set_nx(key, value).await?;
+            Ok(written)
+        }).await
     }
 
-    pub async fn remove_from_index(&self, index: &str, key: &str) -> Result<(), RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let _ = con_guard.srem(index, key).await?;
-        Ok(())
+    async fn compare_and_set_raw(&self, key: &str, expected: Vec<u8>, value: Vec<u8>) -> Result<bool, RedisHelperError> {
+        timed("compare_and_set", async {
+            let mut con = self.con.clone();
+            let written: i32 = redis::Script::new(COMPARE_AND_SET)
+                .key(key)
+                .arg(expected)
+                .arg(value)
+                .invoke_async(&mut con)
+                .await?;
+            Ok(written == 1)
+        }).await
     }
 
-    pub async fn get_index_keys(&self, index: &str) -> Result<Vec<String>, RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let members: Vec<String> = con_guard.smembers(index).await?;
-        Ok(members)
+    async fn get_key_test(&self, key: &str) -> Result<String, RedisHelperError> {
+        timed("get", async {
+            let mut con = self.con.clone();
+            let result: Option<String> = con.get(key).await?;
+            result.ok_or(RedisHelperError::MissingKey)
+        }).await
     }
 
-    pub async fn get_index<T: Decode>(&self, index: &str) -> Result<Vec<T>, RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let members: Vec<String> = con_guard.smembers(index).await?;
-        let mut deserialized_members: Vec<T> = Vec::new();
-        for member_key in members {
-            let member_bytes: Vec<u8> = con_guard.get(member_key).await?;
-            let member: T = deserialize_struct(&member_bytes)?;
-            deserialized_members.push(member);
-        }
-        Ok(deserialized_members)
+    async fn clear_key(&self, key: &str) -> Result<(), RedisHelperError> {
+        timed("clear_key", async {
+            let mut con = self.con.clone();
+            let _: () = con.del(key).await?;
+            Ok(())
+        }).await
     }
 
-    pub async fn clear_index(&self, index: &str) -> Result<(), RedisHelperError> {
-        let mut con_guard = self.con.lock().await;
-        let _ = con_guard.del(index).await?;
-        Ok(())
+    async fn add_to_index(&self, index: &str, key: &str) -> Result<(), RedisHelperError> {
+        timed("add_to_index", async {
+            let mut con = self.con.clone();
+            let _: () = con.sadd(index, key).await?;
+            Ok(())
+        }).await
     }
 
-}
+    async fn add_to_index_if_below(&self, index: &str, key: &str, max: usize) -> Result<bool, RedisHelperError> {
+        timed("add_to_index_if_below", async {
+            let mut con = self.con.clone();
+            let added: i64 = redis::Script::new(ADD_TO_INDEX_IF_BELOW)
+                .key(index)
+                .arg(key)
+                .arg(max)
+                .invoke_async(&mut con)
+                .await?;
+            Ok(added == 1)
+        }).await
+    }
 
+    async fn remove_from_index(&self, index: &str, key: &str) -> Result<(), RedisHelperError> {
+        timed("remove_from_index", async {
+            let mut con = self.con.clone();
+            let _: () = con.srem(index, key).await?;
+            Ok(())
+        }).await
+    }
+
+    async fn get_index_keys(&self, index: &str) -> Result<Vec<String>, RedisHelperError> {
+        timed("get_index_keys", async {
+            let mut con = self.con.clone();
+            let members: Vec<String> = con.smembers(index).await?;
+            Ok(members)
+        }).await
+    }
+
+    async fn clear_index(&self, index: &str) -> Result<(), RedisHelperError> {
+        timed("clear_index", async {
+            let mut con = self.con.clone();
+            let _: () = con.del(index).await?;
+            Ok(())
+        }).await
+    }
+
+    async fn push_raw(&self, list: &str, value: Vec<u8>) -> Result<(), RedisHelperError> {
+        timed("push", async {
+            let mut con = self.con.clone();
+            let _: i64 = con.rpush(list, value).await?;
+            Ok(())
+        }).await
+    }
+
+    async fn get_list_raw(&self, list: &str) -> Result<Vec<Vec<u8>>, RedisHelperError> {
+        timed("get_list", async {
+            let mut con = self.con.clone();
+            let entries: Vec<Vec<u8>> = con.lrange(list, 0, -1).await?;
+            Ok(entries)
+        }).await
+    }
+
+    async fn publish(&self, channel: &str, message: &StreamMessage) -> Result<(), RedisHelperError> {
+        timed("publish", async {
+            let payload = serde_json::to_string(message).map_err(|e| RedisHelperError::Serialization(Box::new(e)))?;
+            let mut con = self.con.clone();
+            let _: i64 = con.publish(channel, payload).await?;
+            Ok(())
+        }).await
+    }
+
+    async fn increment_counter(&self, key: &str, window: u64) -> Result<(u64, u64), RedisHelperError> {
+        timed("increment_counter", async {
+            let mut con = self.con.clone();
+            let (count, ttl): (u64, i64) = redis::Script::new(INCREMENT_COUNTER)
+                .key(key)
+                .arg(window)
+                .invoke_async(&mut con)
+                .await?;
+            // a negative TTL means the key has no expiry, which only happens if it was written some other way
+            Ok((count, ttl.max(0) as u64))
+        }).await
+    }
+
+}
@@ -0,0 +1,83 @@
+use rand::{Rng, SeedableRng};
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+use crate::{Building, GameConfig, Player, Role};
+
+pub fn new_seed() -> u64 {
+    rand::random()
+}
+
+// Every draw in a game comes from the game's seed mixed with what the draw is
+// for, so the same seed always hands out the same roles and tasks
+fn game_rng(seed: u64, purpose: &str) -> ChaCha8Rng {
+    // FNV-1a, unlike the std hasher it never changes between builds
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in purpose.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    ChaCha8Rng::seed_from_u64(seed ^ hash)
+}
+
+// Returns every player with a role. Players are ordered by id first since the
+// index they are read from has no order of its own.
+pub fn assign_roles(players: &[Player], config: &GameConfig, seed: u64) -> Vec<Player> {
+    let mut rng = game_rng(seed, "roles");
+    let mut new_players: Vec<Player> = players.to_vec();
+    new_players.sort_by(|a, b| a.id.cmp(&b.id));
+
+    // we don't want the number of bugs to outnumber the players
+    let max_bugs = (players.len() as f64 * config.bug_ratio).floor() as usize;
+    let mut num_bugs = 0;
+    for player in new_players.iter_mut() {
+        let is_bug = rng.gen_bool(config.bug_ratio);
+        if is_bug && max_bugs > num_bugs {
+            num_bugs += 1;
+            player.role = Some(Role::Bugged);
+        } else {
+            player.role = Some(Role::Normal);
+        }
+    }
+
+    if num_bugs == 0 && !new_players.is_empty() {
+        // we need to choose at least one random person to be a bug
+        let index = rng.gen_range(0..new_players.len());
+        new_players[index].role = Some(Role::Bugged);
+    }
+
+    // special roles go to players who aren't bugs, as many as there are room for
+    let mut specials = Vec::new();
+    for (role, count) in [(Role::Medic, config.medics), (Role::Detective, config.detectives), (Role::Jester, config.jesters)] {
+        for _ in 0..count {
+            specials.push(role.clone());
+        }
+    }
+    let mut normals: Vec<usize> = (0..new_players.len())
+        .filter(|i| new_players[*i].role == Some(Role::Normal))
+        .collect();
+    normals.shuffle(&mut rng);
+    for (index, role) in normals.into_iter().zip(specials) {
+        new_players[index].role = Some(role);
+    }
+    new_players
+}
+
+// Two different depots for a player's task in a round, None if fewer than two are registered
+pub fn pick_depots(buildings: &[Building], seed: u64, round: u32, player_id: &str) -> Option<(Building, Building)> {
+    let mut rng = game_rng(seed, &format!("task:{}:{}", round, player_id));
+    let mut depots: Vec<&Building> = buildings.iter().filter(|b| !b.is_tower).collect();
+    depots.sort_by(|a, b| a.id.cmp(&b.id));
+    let chosen: Vec<&&Building> = depots.choose_multiple(&mut rng, 2).collect();
+    match chosen.as_slice() {
+        [first, second] => Some(((**first).clone(), (**second).clone())),
+        _ => None
+    }
+}
+
+// Breaks a tie between vote candidates, the same ballots in the same round always pick the same player
+pub fn pick_tied(tied: &[Player], seed: u64, round: u32) -> Option<Player> {
+    let mut rng = game_rng(seed, &format!("vote:{}", round));
+    let mut candidates: Vec<&Player> = tied.iter().collect();
+    candidates.sort_by(|a, b| a.id.cmp(&b.id));
+    candidates.choose(&mut rng).map(|p| (*p).clone())
+}
@@ -0,0 +1,92 @@
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use crate::{Role, WinResult};
+
+// Which side a role plays for. Neutral roles count for neither side when
+// the state service checks whether the game is over.
+#[derive(Serialize, Deserialize, Encode, Decode, Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub enum Team {
+    Crew, Bugs, Neutral
+}
+
+// Actions a role can take on another player, besides tasks and votes
+#[derive(Serialize, Deserialize, Encode, Decode, Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub enum Ability {
+    // interrupt a nearby player's task, they are bugged out at the end of the round
+    Poison,
+    // make a nearby player immune to poison for the rest of the round
+    Protect,
+    // learn the team of any player in the game, once per round
+    Inspect,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub enum WinCondition {
+    // the bugs are all gone or every task got done
+    CrewWins,
+    // the bugs take over
+    BugsWin,
+    // the player is voted out
+    VotedOut,
+}
+
+impl Role {
+    pub fn team(&self) -> Team {
+        match self {
+            Role::Normal | Role::Medic | Role::Detective => Team::Crew,
+            Role::Bugged => Team::Bugs,
+            Role::Jester => Team::Neutral
+        }
+    }
+
+    pub fn abilities(&self) -> &'static [Ability] {
+        match self {
+            Role::Bugged => &[Ability::Poison],
+            Role::Medic => &[Ability::Protect],
+            Role::Detective => &[Ability::Inspect],
+            Role::Normal | Role::Jester => &[]
+        }
+    }
+
+    pub fn can(&self, ability: Ability) -> bool {
+        self.abilities().contains(&ability)
+    }
+
+    pub fn is_bug(&self) -> bool {
+        self.team() == Team::Bugs
+    }
+
+    // bugs go after the other players instead of doing tasks
+    pub fn does_tasks(&self) -> bool {
+        !self.is_bug()
+    }
+
+    // What a player with this role is shown as another player's role. Bugs know
+    // each other and who isn't one of them, but not which special role the rest have.
+    pub fn visible_role(&self, other: &Role) -> Option<Role> {
+        if !self.is_bug() {
+            return None;
+        }
+        if other.is_bug() {
+            Some(other.clone())
+        } else {
+            Some(Role::Normal)
+        }
+    }
+
+    pub fn win_condition(&self) -> WinCondition {
+        match self {
+            Role::Normal | Role::Medic | Role::Detective => WinCondition::CrewWins,
+            Role::Bugged => WinCondition::BugsWin,
+            Role::Jester => WinCondition::VotedOut
+        }
+    }
+
+    pub fn wins(&self, result: &WinResult) -> bool {
+        match self.win_condition() {
+            WinCondition::CrewWins => *result == WinResult::Democracy || *result == WinResult::Perfection,
+            WinCondition::BugsWin => *result == WinResult::Thuggery,
+            WinCondition::VotedOut => *result == WinResult::Jester
+        }
+    }
+}
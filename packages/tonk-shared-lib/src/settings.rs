@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+
+// Which binary is starting up, each one needs a different set of settings
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Service {
+    WebServer,
+    StateService,
+    // command line tools that only talk to redis
+    Tool,
+}
+
+// At most `requests` calls every `window` seconds
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Limit {
+    pub requests: u64,
+    pub window: u64,
+}
+
+impl Limit {
+    pub fn per_minute(requests: u64) -> Self {
+        Self { requests, window: 60 }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Missing(&'static str),
+    Invalid { name: String, value: String, reason: &'static str },
+    UnknownFlag(String),
+    File { path: String, source: dotenvy::Error },
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SettingsError::Missing(name) => write!(f, "{} is not set, add it to the .env file, the environment or pass --{}", name, flag_name(name)),
+            SettingsError::Invalid { name, value, reason } => write!(f, "{} is set to {:?}, {}", name, value, reason),
+            SettingsError::UnknownFlag(flag) => write!(f, "unknown flag {}", flag),
+            SettingsError::File { path, source } => write!(f, "couldn't read {}, {}", path, source),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SettingsError::File { source, .. } => Some(source),
+            _ => None
+        }
+    }
+}
+
+// REDIS_URL is passed as --redis-url
+fn flag_name(name: &str) -> String {
+    name.to_lowercase().replace('_', "-")
+}
+
+// Everything the services read from their configuration, checked once at startup.
+// Values come from, lowest precedence first: the stage's .env file, .env.secret,
+// the process environment and --flags on the command line.
+#[derive(Clone, Debug)]
+pub struct Settings {
+    // TONK_SERVICES_STAGE, "local" when it isn't set
+    pub stage: String,
+    pub redis_url: String,
+    pub bind_address: IpAddr,
+    pub port: u16,
+    // where /metrics is served, apart from the web server's API
+    pub metrics_port: u16,
+    // the downstream graph the player locations are synced from
+    pub ds_endpoint: Option<String>,
+    // None disables the admin API
+    pub admin_key: Option<String>,
+    // CORS lists, None keeps the stage's defaults
+    pub allowed_origins: Option<Vec<String>>,
+    pub allowed_methods: Option<Vec<String>>,
+    pub allowed_headers: Option<Vec<String>>,
+    // RATE_LIMIT_<ROUTE>_<PLAYER|IP> overrides by lowercase name, None turns the limit off
+    pub rate_limits: HashMap<String, Option<Limit>>,
+    // the proxies whose X-Forwarded-For header is believed, by default no one's is
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+const KNOWN_KEYS: [&str; 11] = [
+    "TONK_SERVICES_STAGE", "REDIS_URL", "BIND_ADDRESS", "PORT", "METRICS_PORT", "DS_ENDPOINT", "ADMIN_KEY",
+    "ALLOWED_ORIGIN", "ALLOWED_METHODS", "ALLOWED_HEADERS", "TRUSTED_PROXIES",
+];
+
+// Parsed by dotenvy so quoting, export and comments work the way they do everywhere
+// else. The values are only collected here, the process environment is left alone.
+pub fn parse_env_file(contents: &str) -> Result<HashMap<String, String>, dotenvy::Error> {
+    dotenvy::from_read_iter(contents.as_bytes()).collect()
+}
+
+fn read_env_file(path: &str) -> Result<HashMap<String, String>, SettingsError> {
+    let file_error = |source| SettingsError::File { path: path.to_string(), source };
+    match fs::read_to_string(path) {
+        Ok(contents) => parse_env_file(&contents).map_err(file_error),
+        // every file is optional, the values can all come from the environment
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(HashMap::new()),
+        Err(e) => Err(file_error(dotenvy::Error::Io(e)))
+    }
+}
+
+// --redis-url value or --redis-url=value, becomes REDIS_URL
+fn parse_flags(args: &[String]) -> Result<HashMap<String, String>, SettingsError> {
+    let mut flags = HashMap::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let flag = arg.strip_prefix("--").ok_or_else(|| SettingsError::UnknownFlag(arg.clone()))?;
+        let (name, value) = match flag.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => {
+                let value = args.next().ok_or_else(|| SettingsError::Invalid {
+                    name: arg.clone(), value: String::new(), reason: "the flag needs a value"
+                })?;
+                (flag.to_string(), value.clone())
+            }
+        };
+        let key = name.to_uppercase().replace('-', "_");
+        if !KNOWN_KEYS.contains(&key.as_str()) && !key.starts_with("RATE_LIMIT_") {
+            return Err(SettingsError::UnknownFlag(arg.clone()));
+        }
+        flags.insert(key, value);
+    }
+    Ok(flags)
+}
+
+fn list(value: Option<&String>) -> Option<Vec<String>> {
+    value.map(|v| v.split(',').map(|v| v.trim().to_string()).filter(|v| !v.is_empty()).collect())
+}
+
+// written as <requests>/<seconds>, e.g. 30/60, or "off"
+fn parse_limit(name: &str, value: &str) -> Result<Option<Limit>, SettingsError> {
+    if value.trim() == "off" {
+        return Ok(None);
+    }
+    let invalid = || SettingsError::Invalid {
+        name: name.to_string(), value: value.to_string(), reason: "expected <requests>/<seconds> like 30/60, or off"
+    };
+    let (requests, window) = value.trim().split_once('/').ok_or_else(invalid)?;
+    let limit = Limit {
+        requests: requests.trim().parse().map_err(|_| invalid())?,
+        window: window.trim().parse().map_err(|_| invalid())?,
+    };
+    if limit.window == 0 {
+        return Err(invalid());
+    }
+    Ok(Some(limit))
+}
+
+impl Settings {
+    // Reads the .env files from the working directory and the process
+    // environment, with the flags given on the command line on top
+    pub fn load(service: Service, args: &[String]) -> Result<Self, SettingsError> {
+        let flags = parse_flags(args)?;
+        let env: HashMap<String, String> = std::env::vars().collect();
+        let stage = flags.get("TONK_SERVICES_STAGE").or(env.get("TONK_SERVICES_STAGE")).cloned();
+        let stage_file = match stage.as_deref() {
+            None | Some("local") => ".env.local",
+            Some(_) => ".env.production"
+        };
+        let mut values = read_env_file(stage_file)?;
+        values.extend(read_env_file(".env.secret")?);
+        values.extend(env);
+        values.extend(flags);
+        Self::from_values(service, &values)
+    }
+
+    pub fn from_values(service: Service, values: &HashMap<String, String>) -> Result<Self, SettingsError> {
+        let redis_url = values.get("REDIS_URL").cloned().ok_or(SettingsError::Missing("REDIS_URL"))?;
+        if !redis_url.starts_with("redis://") && !redis_url.starts_with("rediss://") {
+            return Err(SettingsError::Invalid { name: "REDIS_URL".to_string(), value: redis_url, reason: "it should start with redis://" });
+        }
+        let bind_address = match values.get("BIND_ADDRESS") {
+            Some(address) => address.parse().map_err(|_| SettingsError::Invalid {
+                name: "BIND_ADDRESS".to_string(), value: address.clone(), reason: "expected an IP address to bind to"
+            })?,
+            None => IpAddr::from([0, 0, 0, 0])
+        };
+        let port = match values.get("PORT") {
+            Some(port) => port.parse().map_err(|_| SettingsError::Invalid {
+                name: "PORT".to_string(), value: port.clone(), reason: "expected a port number"
+            })?,
+            None => 8082
+        };
+        let metrics_port = match values.get("METRICS_PORT") {
+            Some(port) => port.parse().map_err(|_| SettingsError::Invalid {
+                name: "METRICS_PORT".to_string(), value: port.clone(), reason: "expected a port number"
+            })?,
+            // both services can run on one machine without clashing
+            None if service == Service::WebServer => 9091,
+            None => 9090
+        };
+        let ds_endpoint = values.get("DS_ENDPOINT").cloned();
+        if service == Service::StateService && ds_endpoint.is_none() {
+            return Err(SettingsError::Missing("DS_ENDPOINT"));
+        }
+
+        let mut rate_limits = HashMap::new();
+        for (name, value) in values.iter().filter(|(name, _)| name.starts_with("RATE_LIMIT_")) {
+            let route = name.trim_start_matches("RATE_LIMIT_").to_lowercase();
+            rate_limits.insert(route, parse_limit(name, value)?);
+        }
+
+        let trusted_proxies = list(values.get("TRUSTED_PROXIES")).unwrap_or_default().into_iter().map(|proxy| {
+            proxy.parse().map_err(|_| SettingsError::Invalid {
+                name: "TRUSTED_PROXIES".to_string(), value: proxy.clone(), reason: "expected a comma separated list of IP addresses"
+            })
+        }).collect::<Result<Vec<IpAddr>, _>>()?;
+
+        Ok(Self {
+            stage: values.get("TONK_SERVICES_STAGE").cloned().unwrap_or_else(|| "local".to_string()),
+            redis_url,
+            bind_address,
+            port,
+            metrics_port,
+            ds_endpoint,
+            admin_key: values.get("ADMIN_KEY").cloned().filter(|key| !key.is_empty()),
+            allowed_origins: list(values.get("ALLOWED_ORIGIN")),
+            allowed_methods: list(values.get("ALLOWED_METHODS")),
+            allowed_headers: list(values.get("ALLOWED_HEADERS")),
+            rate_limits,
+            trusted_proxies,
+        })
+    }
+
+    // any stage other than local is deployed
+    pub fn is_production(&self) -> bool {
+        self.stage != "local"
+    }
+
+    // the configured limit for a route, the default when it isn't configured
+    pub fn rate_limit(&self, route: &str, kind: &str, default: Limit) -> Option<Limit> {
+        let name = format!("{}_{}", route, kind).to_lowercase();
+        match self.rate_limits.get(&name) {
+            Some(limit) => *limit,
+            None => Some(default)
+        }
+    }
+}
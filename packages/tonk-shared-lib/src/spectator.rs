@@ -0,0 +1,105 @@
+use serde::{Deserialize, Serialize};
+use crate::stream::StreamMessage;
+use crate::{EliminationReason, Game, GameStatus, Location, Player, PlayerProximity, Role, RoundResult, Time, WinResult};
+
+// What someone watching the game sees of a player. Spectators never join the
+// game, and the roles stay hidden until it has ended.
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct SpectatedPlayer {
+    pub id: String,
+    pub display_name: Option<String>,
+    pub location: Option<Location>,
+    pub eliminated: Option<EliminationReason>,
+    pub role: Option<Role>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct SpectatorView {
+    pub id: String,
+    pub status: GameStatus,
+    pub time: Option<Time>,
+    pub paused: bool,
+    pub win_result: Option<WinResult>,
+    pub players: Vec<SpectatedPlayer>,
+    pub results: Vec<RoundResult>,
+}
+
+// what anyone but the player themselves gets to see of a player
+pub fn redact_player(player: &Player, reveal_roles: bool) -> Player {
+    Player {
+        id: player.id.clone(),
+        mobile_unit_id: None,
+        display_name: player.display_name.clone(),
+        secret_key: None,
+        role: if reveal_roles { player.role.clone() } else { None },
+        used_action: None,
+        last_round_action: None,
+        eliminated: player.eliminated,
+        proximity: None
+    }
+}
+
+// round results name players in their eliminations and tasks, which carry their roles
+fn redact_result(result: &RoundResult, reveal_roles: bool) -> RoundResult {
+    let mut redacted = result.clone();
+    for elimination in redacted.eliminated.iter_mut().flatten() {
+        elimination.player = redact_player(&elimination.player, reveal_roles);
+    }
+    for task in redacted.tasks_completed.iter_mut().flatten() {
+        task.assignee = task.assignee.as_ref().map(|p| redact_player(p, reveal_roles));
+    }
+    redacted
+}
+
+pub fn spectator_view(game: &Game, players: &[(Player, Option<PlayerProximity>)], results: &[RoundResult]) -> SpectatorView {
+    let reveal_roles = game.status == GameStatus::End;
+    let eliminations = game.eliminated_players.as_deref().unwrap_or_default();
+    let players = players.iter().map(|(player, proximity)| SpectatedPlayer {
+        id: player.id.clone(),
+        display_name: player.display_name.clone(),
+        location: proximity.as_ref().and_then(|p| p.location.clone()),
+        eliminated: eliminations.iter()
+            .find(|e| e.player.id == player.id)
+            .map(|e| e.reason.clone()),
+        role: if reveal_roles { player.role.clone() } else { None },
+    }).collect();
+    SpectatorView {
+        id: game.id.clone(),
+        status: game.status.clone(),
+        time: game.time.clone(),
+        paused: game.paused,
+        win_result: game.win_result.clone(),
+        players,
+        results: results.iter().map(|r| redact_result(r, reveal_roles)).collect(),
+    }
+}
+
+// The game as spectators are sent it on its stream. Which players were bugs is
+// only given away once the game has ended.
+pub fn redact_game(game: &Game) -> Game {
+    let reveal_roles = game.status == GameStatus::End;
+    let mut redacted = game.clone();
+    redacted.corrupted_players = if reveal_roles {
+        game.corrupted_players.as_ref().map(|players| players.iter().map(|p| redact_player(p, true)).collect())
+    } else {
+        None
+    };
+    for elimination in redacted.eliminated_players.iter_mut().flatten() {
+        elimination.player = redact_player(&elimination.player, reveal_roles);
+    }
+    redacted
+}
+
+// What a spectator is sent of a message on the game's stream. A round result
+// doesn't say whether the game has ended, so its roles always stay hidden.
+pub fn spectator_message(message: StreamMessage) -> Option<StreamMessage> {
+    match message {
+        StreamMessage::Game(game) => Some(StreamMessage::Game(redact_game(&game))),
+        StreamMessage::RoundResult { round, result } => Some(StreamMessage::RoundResult {
+            round,
+            result: redact_result(&result, false)
+        }),
+        // proximity belongs to the player it was sent to
+        StreamMessage::Proximity { .. } => None,
+    }
+}
@@ -0,0 +1,252 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use async_trait::async_trait;
+use bincode::{Decode, Encode};
+use log::*;
+use crate::{deserialize_struct, serialize_struct};
+use crate::redis_helper::RedisHelperError;
+use crate::stream::StreamMessage;
+
+// How many times `update_key` re-reads a key that another writer changed under it
+pub const MAX_UPDATE_ATTEMPTS: usize = 8;
+
+// Everything the services need from the state backend. Values are stored as
+// raw bytes; the typed helpers live on `StateStoreExt` so the trait stays
+// usable as `dyn StateStore`.
+#[async_trait]
+pub trait StateStore: Send + Sync {
+    async fn get_raw(&self, key: &str) -> Result<Vec<u8>, RedisHelperError>;
+    async fn set_raw(&self, key: &str, value: Vec<u8>) -> Result<(), RedisHelperError>;
+    // Writes the value only if the key does not exist yet, returns whether it was written
+    async fn set_raw_if_absent(&self, key: &str, value: Vec<u8>) -> Result<bool, RedisHelperError>;
+    // Writes the value only if the key still holds `expected`, returns whether it was written
+    async fn compare_and_set_raw(&self, key: &str, expected: Vec<u8>, value: Vec<u8>) -> Result<bool, RedisHelperError>;
+    async fn get_key_test(&self, key: &str) -> Result<String, RedisHelperError>;
+    async fn clear_key(&self, key: &str) -> Result<(), RedisHelperError>;
+    async fn add_to_index(&self, index: &str, key: &str) -> Result<(), RedisHelperError>;
+    // Adds the key unless the index already holds `max` members, in one step so racing
+    // callers can't overfill it. Returns whether the key is in the index afterwards.
+    async fn add_to_index_if_below(&self, index: &str, key: &str, max: usize) -> Result<bool, RedisHelperError>;
+    async fn remove_from_index(&self, index: &str, key: &str) -> Result<(), RedisHelperError>;
+    async fn get_index_keys(&self, index: &str) -> Result<Vec<String>, RedisHelperError>;
+    async fn clear_index(&self, index: &str) -> Result<(), RedisHelperError>;
+    // Appends to the end of a list, entries already in it are never rewritten
+    async fn push_raw(&self, list: &str, value: Vec<u8>) -> Result<(), RedisHelperError>;
+    // Every entry of a list in the order it was pushed, empty if the list doesn't exist
+    async fn get_list_raw(&self, list: &str) -> Result<Vec<Vec<u8>>, RedisHelperError>;
+    async fn publish(&self, channel: &str, message: &StreamMessage) -> Result<(), RedisHelperError>;
+    // Counts a hit against a counter that resets `window` seconds after its first hit,
+    // returns the count so far and the seconds left until it resets
+    async fn increment_counter(&self, key: &str, window: u64) -> Result<(u64, u64), RedisHelperError>;
+}
+
+#[async_trait]
+pub trait StateStoreExt: StateStore {
+    async fn get_key<T: Decode>(&self, key: &str) -> Result<T, RedisHelperError> {
+        let bytes = self.get_raw(key).await?;
+        Ok(deserialize_struct(&bytes)?)
+    }
+
+    async fn set_key<T: Encode + Sync>(&self, key: &str, obj: &T) -> Result<(), RedisHelperError> {
+        let bytes = serialize_struct(obj)?;
+        self.set_raw(key, bytes).await
+    }
+
+    async fn set_key_if_absent<T: Encode + Sync>(&self, key: &str, obj: &T) -> Result<bool, RedisHelperError> {
+        let bytes = serialize_struct(obj)?;
+        self.set_raw_if_absent(key, bytes).await
+    }
+
+    async fn compare_and_set_key<T: Encode + Sync>(&self, key: &str, expected: &T, obj: &T) -> Result<bool, RedisHelperError> {
+        let expected_bytes = serialize_struct(expected)?;
+        let bytes = serialize_struct(obj)?;
+        self.compare_and_set_raw(key, expected_bytes, bytes).await
+    }
+
+    // Read-modify-write that never loses a concurrent update: `f` is applied to the
+    // latest value and rerun if the key changed before the write landed. Returning
+    // `None` from `f` leaves the key as it is.
+    async fn update_key<T, F>(&self, key: &str, mut f: F) -> Result<Option<T>, RedisHelperError>
+    where
+        T: Encode + Decode + Send + Sync,
+        F: FnMut(T) -> Option<T> + Send
+    {
+        for _ in 0..MAX_UPDATE_ATTEMPTS {
+            let current_bytes = self.get_raw(key).await?;
+            let next = match f(deserialize_struct(&current_bytes)?) {
+                Some(next) => next,
+                None => return Ok(None)
+            };
+            let next_bytes = serialize_struct(&next)?;
+            if self.compare_and_set_raw(key, current_bytes, next_bytes).await? {
+                return Ok(Some(next));
+            }
+        }
+        Err(RedisHelperError::Conflict)
+    }
+
+    // Every member of the index that can still be read. A member whose key is gone
+    // or no longer decodes is skipped, so one stale entry doesn't fail every caller.
+    async fn get_index<T: Decode + Send>(&self, index: &str) -> Result<Vec<T>, RedisHelperError> {
+        let members = self.get_index_keys(index).await?;
+        let mut deserialized_members: Vec<T> = Vec::new();
+        for member_key in members {
+            let member_bytes = match self.get_raw(&member_key).await {
+                Ok(bytes) => bytes,
+                Err(RedisHelperError::MissingKey) => {
+                    debug!("{} lists {} which no longer exists", index, member_key);
+                    continue;
+                }
+                Err(e) => return Err(e)
+            };
+            match deserialize_struct(&member_bytes) {
+                Ok(member) => deserialized_members.push(member),
+                Err(e) => warn!("{} lists {} which couldn't be decoded: {}", index, member_key, e)
+            }
+        }
+        Ok(deserialized_members)
+    }
+
+    async fn push_to_list<T: Encode + Sync>(&self, list: &str, obj: &T) -> Result<(), RedisHelperError> {
+        let bytes = serialize_struct(obj)?;
+        self.push_raw(list, bytes).await
+    }
+
+    async fn get_list<T: Decode + Send>(&self, list: &str) -> Result<Vec<T>, RedisHelperError> {
+        let entries = self.get_list_raw(list).await?;
+        let mut deserialized_entries: Vec<T> = Vec::new();
+        for entry in entries {
+            deserialized_entries.push(deserialize_struct(&entry)?);
+        }
+        Ok(deserialized_entries)
+    }
+}
+
+impl<S: StateStore + ?Sized> StateStoreExt for S {}
+
+// A process-local store for tests and running the services without Redis.
+// Published messages are kept so tests can assert on them.
+#[derive(Default)]
+pub struct MemoryStore {
+    keys: Mutex<HashMap<String, Vec<u8>>>,
+    indexes: Mutex<HashMap<String, HashSet<String>>>,
+    lists: Mutex<HashMap<String, Vec<Vec<u8>>>>,
+    published: Mutex<Vec<(String, StreamMessage)>>,
+    counters: Mutex<HashMap<String, (u64, Instant)>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_key_test(&self, key: &str, value: &str) {
+        self.keys.lock().unwrap().insert(key.to_string(), value.as_bytes().to_vec());
+    }
+
+    pub fn published(&self) -> Vec<(String, StreamMessage)> {
+        self.published.lock().unwrap().clone()
+    }
+}
+
+#[async_trait]
+impl StateStore for MemoryStore {
+    async fn get_raw(&self, key: &str) -> Result<Vec<u8>, RedisHelperError> {
+        self.keys.lock().unwrap().get(key).cloned().ok_or(RedisHelperError::MissingKey)
+    }
+
+    async fn set_raw(&self, key: &str, value: Vec<u8>) -> Result<(), RedisHelperError> {
+        self.keys.lock().unwrap().insert(key.to_string(), value);
+        Ok(())
+    }
+
+    async fn set_raw_if_absent(&self, key: &str, value: Vec<u8>) -> Result<bool, RedisHelperError> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.contains_key(key) {
+            return Ok(false);
+        }
+        keys.insert(key.to_string(), value);
+        Ok(true)
+    }
+
+    async fn compare_and_set_raw(&self, key: &str, expected: Vec<u8>, value: Vec<u8>) -> Result<bool, RedisHelperError> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.get(key) != Some(&expected) {
+            return Ok(false);
+        }
+        keys.insert(key.to_string(), value);
+        Ok(true)
+    }
+
+    async fn get_key_test(&self, key: &str) -> Result<String, RedisHelperError> {
+        let bytes = self.get_raw(key).await?;
+        String::from_utf8(bytes).map_err(|e| RedisHelperError::Deserialization(Box::new(e)))
+    }
+
+    async fn clear_key(&self, key: &str) -> Result<(), RedisHelperError> {
+        // Redis DEL removes a key of any type, so the index and list maps are cleared too
+        self.keys.lock().unwrap().remove(key);
+        self.indexes.lock().unwrap().remove(key);
+        self.lists.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn add_to_index(&self, index: &str, key: &str) -> Result<(), RedisHelperError> {
+        self.indexes.lock().unwrap().entry(index.to_string()).or_default().insert(key.to_string());
+        Ok(())
+    }
+
+    async fn add_to_index_if_below(&self, index: &str, key: &str, max: usize) -> Result<bool, RedisHelperError> {
+        let mut indexes = self.indexes.lock().unwrap();
+        let members = indexes.entry(index.to_string()).or_default();
+        if !members.contains(key) && members.len() >= max {
+            return Ok(false);
+        }
+        members.insert(key.to_string());
+        Ok(true)
+    }
+
+    async fn remove_from_index(&self, index: &str, key: &str) -> Result<(), RedisHelperError> {
+        if let Some(members) = self.indexes.lock().unwrap().get_mut(index) {
+            members.remove(key);
+        }
+        Ok(())
+    }
+
+    async fn get_index_keys(&self, index: &str) -> Result<Vec<String>, RedisHelperError> {
+        let indexes = self.indexes.lock().unwrap();
+        Ok(indexes.get(index).map(|members| members.iter().cloned().collect()).unwrap_or_default())
+    }
+
+    async fn clear_index(&self, index: &str) -> Result<(), RedisHelperError> {
+        self.indexes.lock().unwrap().remove(index);
+        Ok(())
+    }
+
+    async fn push_raw(&self, list: &str, value: Vec<u8>) -> Result<(), RedisHelperError> {
+        self.lists.lock().unwrap().entry(list.to_string()).or_default().push(value);
+        Ok(())
+    }
+
+    async fn get_list_raw(&self, list: &str) -> Result<Vec<Vec<u8>>, RedisHelperError> {
+        Ok(self.lists.lock().unwrap().get(list).cloned().unwrap_or_default())
+    }
+
+    async fn publish(&self, channel: &str, message: &StreamMessage) -> Result<(), RedisHelperError> {
+        self.published.lock().unwrap().push((channel.to_string(), message.clone()));
+        Ok(())
+    }
+
+    async fn increment_counter(&self, key: &str, window: u64) -> Result<(u64, u64), RedisHelperError> {
+        let now = Instant::now();
+        let mut counters = self.counters.lock().unwrap();
+        let counter = counters.entry(key.to_string()).or_insert((0, now + Duration::from_secs(window)));
+        if counter.1 <= now {
+            *counter = (0, now + Duration::from_secs(window));
+        }
+        counter.0 += 1;
+        let remaining = counter.1.saturating_duration_since(now).as_secs_f64().ceil() as u64;
+        Ok((counter.0, remaining))
+    }
+}
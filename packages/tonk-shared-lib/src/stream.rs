@@ -0,0 +1,19 @@
+use serde::{Deserialize, Serialize};
+use crate::{Game, PlayerProximity, RoundResult};
+
+// Messages pushed to clients over the web server's event stream
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(tag = "type", content = "payload")]
+pub enum StreamMessage {
+    Game(Game),
+    RoundResult { round: u32, result: RoundResult },
+    Proximity { player_id: String, proximity: PlayerProximity },
+}
+
+pub fn game_channel(game_id: &str) -> String {
+    format!("game:{}:stream", game_id)
+}
+
+pub fn player_channel(player_id: &str) -> String {
+    format!("player:{}:stream", player_id)
+}
@@ -0,0 +1,124 @@
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use crate::{GameConfig, Player, Vote};
+use crate::rng::pick_tied;
+
+// What happens when two or more candidates share the most votes
+#[derive(Serialize, Deserialize, Encode, Decode, Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub enum TiePolicy {
+    // nobody is voted out
+    NoElimination,
+    // the vote is held again between the tied candidates, a second tie votes nobody out
+    Runoff,
+    // one of the tied candidates is picked with the game's seed
+    Random,
+}
+
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+pub struct CandidateTally {
+    pub candidate: Player,
+    pub votes: u32,
+}
+
+// Who a player voted for, None when they abstained
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug)]
+pub struct Ballot {
+    pub voter: String,
+    pub candidate: Option<String>,
+}
+
+// Every ballot counted in a vote round, most voted candidate first. The ballots
+// themselves are only kept when the game reveals who voted for whom.
+#[derive(Serialize, Deserialize, Encode, Decode, PartialEq, Clone, Debug, Default)]
+pub struct VoteTally {
+    pub candidates: Vec<CandidateTally>,
+    pub abstentions: u32,
+    #[serde(default)]
+    pub ballots: Option<Vec<Ballot>>,
+}
+
+impl VoteTally {
+    pub fn count(votes: &[Vote], reveal_ballots: bool) -> Self {
+        let mut tally = VoteTally::default();
+        for vote in votes {
+            match vote.candidate.as_ref() {
+                Some(candidate) => match tally.candidates.iter_mut().find(|c| c.candidate.id == candidate.id) {
+                    Some(entry) => entry.votes += 1,
+                    None => tally.candidates.push(CandidateTally { candidate: public_player(candidate), votes: 1 })
+                },
+                None => tally.abstentions += 1
+            }
+        }
+        // ties are listed by id so the same ballots always give the same tally
+        tally.candidates.sort_by(|a, b| b.votes.cmp(&a.votes).then_with(|| a.candidate.id.cmp(&b.candidate.id)));
+
+        if reveal_ballots {
+            let mut ballots: Vec<Ballot> = votes.iter().map(|v| Ballot {
+                voter: v.voter.clone().unwrap_or_default(),
+                candidate: v.candidate.as_ref().map(|c| c.id.clone())
+            }).collect();
+            ballots.sort_by(|a, b| a.voter.cmp(&b.voter));
+            tally.ballots = Some(ballots);
+        }
+        tally
+    }
+
+    pub fn total(&self) -> u32 {
+        self.candidates.iter().map(|c| c.votes).sum::<u32>() + self.abstentions
+    }
+
+    // every candidate with the most votes
+    pub fn leaders(&self) -> Vec<Player> {
+        let most = self.candidates.first().map(|c| c.votes).unwrap_or(0);
+        self.candidates.iter().filter(|c| most > 0 && c.votes == most).map(|c| c.candidate.clone()).collect()
+    }
+}
+
+// the tally is shown to every player, so candidates only keep their name
+fn public_player(player: &Player) -> Player {
+    Player {
+        id: player.id.clone(),
+        mobile_unit_id: None,
+        display_name: player.display_name.clone(),
+        secret_key: None,
+        role: None,
+        used_action: None,
+        last_round_action: None,
+        eliminated: None,
+        proximity: None
+    }
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub enum VoteOutcome {
+    VotedOut(Box<Player>),
+    NoElimination,
+    Runoff(Vec<Player>),
+}
+
+// Decides the round from the tally. A runoff round never calls for another runoff.
+pub fn resolve_votes(tally: &VoteTally, config: &GameConfig, seed: u64, round: u32, in_runoff: bool) -> VoteOutcome {
+    let leaders = tally.leaders();
+    let top = match leaders.as_slice() {
+        [] => return VoteOutcome::NoElimination,
+        [only] => only.clone(),
+        _ => match config.tie_policy {
+            TiePolicy::NoElimination => return VoteOutcome::NoElimination,
+            TiePolicy::Runoff if in_runoff => return VoteOutcome::NoElimination,
+            TiePolicy::Runoff => return VoteOutcome::Runoff(leaders),
+            TiePolicy::Random => match pick_tied(&leaders, seed, round) {
+                Some(picked) => picked,
+                None => return VoteOutcome::NoElimination
+            }
+        }
+    };
+
+    // abstentions count towards the ballots the majority is taken from
+    if let Some(threshold) = config.majority_threshold {
+        let votes = tally.candidates.iter().find(|c| c.candidate.id == top.id).map(|c| c.votes).unwrap_or(0);
+        if (votes as f64) < tally.total() as f64 * threshold {
+            return VoteOutcome::NoElimination;
+        }
+    }
+    VoteOutcome::VotedOut(Box::new(top))
+}
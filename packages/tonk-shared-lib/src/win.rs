@@ -0,0 +1,106 @@
+use bincode::{Decode, Encode};
+use serde::{Deserialize, Serialize};
+use crate::{Elimination, EliminationReason, GameStatus, Player, WinResult};
+use crate::roles::{Team, WinCondition};
+
+// The parts of a game the win rules look at, read once a round has been resolved
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct WinSnapshot {
+    // the phase the resolved round belongs to
+    pub round_type: GameStatus,
+    pub tasks_assigned: usize,
+    pub tasks_completed: usize,
+    // players still in the game, including the ones eliminated this round
+    pub players: Vec<Player>,
+    // players eliminated this round
+    pub eliminated: Vec<Elimination>,
+    pub demo_play: bool,
+    pub thuggery_threshold: f64,
+}
+
+impl WinSnapshot {
+    fn remaining(&self) -> impl Iterator<Item = &Player> {
+        self.players.iter().filter(move |p| self.eliminated.iter().all(|e| e.player.id != p.id))
+    }
+
+    fn remaining_on(&self, team: Team) -> usize {
+        self.remaining().filter(|p| p.role.as_ref().map(|r| r.team()) == Some(team)).count()
+    }
+}
+
+// A way a game can end. Game modes choose which of these apply in their config.
+#[derive(Serialize, Deserialize, Encode, Decode, Eq, Hash, PartialEq, Clone, Copy, Debug)]
+pub enum WinRule {
+    // a player whose role wins by being voted out is voted out
+    Jester,
+    // every task handed out in a task round was completed
+    Perfection,
+    // the bugs make up at least the thuggery threshold of the remaining players
+    Thuggery,
+    // nobody from the crew or the bugs is left
+    Armageddon,
+    // every bug is gone
+    Democracy,
+    // every crew member is gone
+    Overrun,
+}
+
+impl WinRule {
+    pub fn all() -> Vec<WinRule> {
+        vec![WinRule::Jester, WinRule::Perfection, WinRule::Thuggery, WinRule::Armageddon, WinRule::Democracy, WinRule::Overrun]
+    }
+
+    // when more than one rule is met, the highest priority decides the result
+    pub fn priority(&self) -> u32 {
+        match self {
+            WinRule::Jester => 60,
+            WinRule::Perfection => 50,
+            WinRule::Thuggery => 40,
+            WinRule::Armageddon => 30,
+            WinRule::Democracy => 20,
+            WinRule::Overrun => 10,
+        }
+    }
+
+    pub fn evaluate(&self, snapshot: &WinSnapshot) -> Option<WinResult> {
+        let met = match self {
+            WinRule::Jester => snapshot.eliminated.iter().any(|e| {
+                e.reason == EliminationReason::VotedOut
+                    && e.player.role.as_ref().map(|r| r.win_condition() == WinCondition::VotedOut).unwrap_or(false)
+            }),
+            // the task round's result is recorded as TaskResult, so that is the round checked here.
+            // we disable this for demo games to allow for a limited setup
+            WinRule::Perfection => {
+                snapshot.round_type == GameStatus::TaskResult
+                    && snapshot.tasks_assigned == snapshot.tasks_completed
+                    && !snapshot.demo_play
+            }
+            WinRule::Thuggery => {
+                let remaining = snapshot.remaining().count();
+                !snapshot.demo_play && snapshot.remaining_on(Team::Bugs) as f64 >= remaining as f64 * snapshot.thuggery_threshold
+            }
+            WinRule::Armageddon => snapshot.remaining_on(Team::Bugs) == 0 && snapshot.remaining_on(Team::Crew) == 0,
+            WinRule::Democracy => snapshot.remaining_on(Team::Bugs) == 0,
+            WinRule::Overrun => snapshot.remaining_on(Team::Crew) == 0,
+        };
+        if !met {
+            return None;
+        }
+        Some(match self {
+            WinRule::Jester => WinResult::Jester,
+            WinRule::Perfection => WinResult::Perfection,
+            WinRule::Thuggery | WinRule::Overrun => WinResult::Thuggery,
+            WinRule::Armageddon => WinResult::Armageddon,
+            WinRule::Democracy => WinResult::Democracy,
+        })
+    }
+}
+
+// The result of the highest priority rule that is met, WinResult::Null while the game goes on
+pub fn evaluate_win(rules: &[WinRule], snapshot: &WinSnapshot) -> WinResult {
+    rules.iter()
+        .filter_map(|rule| rule.evaluate(snapshot).map(|result| (rule.priority(), result)))
+        .max_by_key(|(priority, _)| *priority)
+        .map(|(_, result)| result)
+        .unwrap_or(WinResult::Null)
+}
@@ -0,0 +1,26 @@
+// Fixtures shared by the test files, not every file uses all of them
+#![allow(dead_code)]
+
+use tonk_shared_lib::{Player, Role};
+
+// a registered player who hasn't been dealt a role
+pub fn player(id: &str) -> Player {
+    Player {
+        id: id.to_string(),
+        mobile_unit_id: None,
+        display_name: None,
+        secret_key: None,
+        role: None,
+        used_action: None,
+        last_round_action: None,
+        eliminated: None,
+        proximity: None
+    }
+}
+
+pub fn player_with_role(id: &str, role: Role) -> Player {
+    Player {
+        role: Some(role),
+        ..player(id)
+    }
+}
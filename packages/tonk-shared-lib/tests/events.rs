@@ -0,0 +1,88 @@
+use tonk_shared_lib::*;
+use tonk_shared_lib::events::*;
+use tonk_shared_lib::store::*;
+
+mod common;
+use common::player;
+
+fn lobby(id: &str) -> Game {
+    Game {
+        id: id.to_string(),
+        status: GameStatus::Lobby,
+        time: Some(Time { round: 0, timer: 0 }),
+        win_result: None,
+        corrupted_players: None,
+        eliminated_players: None,
+        demo_play: false,
+        paused: false,
+        config: GameConfig::default(),
+        seed: 7,
+        runoff: None,
+        host: None
+    }
+}
+
+#[tokio::test]
+async fn events_are_read_back_in_order() {
+    let store = MemoryStore::new();
+    record_event(&store, "g", Actor::StateService, GameEventKind::Created { game: Box::new(lobby("g")) }).await.unwrap();
+    record_event(&store, "g", Actor::Player("a".to_string()), GameEventKind::PlayerJoined { player: Box::new(player("a")) }).await.unwrap();
+
+    let events: Vec<GameEvent> = store.get_list(&events_key("g")).await.unwrap();
+    assert_eq!(events.len(), 2);
+    assert!(matches!(events[0].kind, GameEventKind::Created { .. }));
+    assert_eq!(events[1].actor, Actor::Player("a".to_string()));
+}
+
+#[test]
+fn replay_rebuilds_game_and_players() {
+    let a = Actor::Player("a".to_string());
+    let events: Vec<GameEvent> = vec![
+        GameEvent::new(Actor::StateService, GameEventKind::Created { game: Box::new(lobby("g")) }),
+        GameEvent::new(Actor::Admin, GameEventKind::SeedChanged { seed: 42 }),
+        GameEvent::new(a.clone(), GameEventKind::PlayerJoined { player: Box::new(player("a")) }),
+        GameEvent::new(Actor::Player("b".to_string()), GameEventKind::PlayerJoined { player: Box::new(player("b")) }),
+        GameEvent::new(a.clone(), GameEventKind::Started {
+            roles: vec![("a".to_string(), Role::Normal), ("b".to_string(), Role::Bugged)],
+            time: Time { round: 0, timer: 180 },
+            demo_play: true
+        }),
+        GameEvent::new(a.clone(), GameEventKind::TaskCompleted { round: 0 }),
+        GameEvent::new(Actor::StateService, GameEventKind::RoundResolved {
+            round: 0,
+            result: Box::new(RoundResult { round_type: GameStatus::TaskResult, eliminated: None, tasks_completed: None, tally: None })
+        }),
+        GameEvent::new(Actor::StateService, GameEventKind::RoundReset { round: 0 }),
+        GameEvent::new(Actor::StateService, GameEventKind::PhaseChanged {
+            status: GameStatus::Vote,
+            time: Time { round: 1, timer: 90 },
+            win_result: None
+        }),
+        GameEvent::new(a.clone(), GameEventKind::Voted { round: 1, vote: Box::new(Vote { candidate: Some(player("b")), voter: Some("a".to_string()) }) }),
+        GameEvent::new(Actor::StateService, GameEventKind::Eliminated {
+            elimination: Box::new(Elimination { player: player("b"), reason: EliminationReason::VotedOut })
+        }),
+        GameEvent::new(Actor::StateService, GameEventKind::PhaseChanged {
+            status: GameStatus::End,
+            time: Time { round: 1, timer: 30 },
+            win_result: Some(WinResult::Democracy)
+        }),
+    ];
+
+    let replayed = replay(&events);
+    assert_eq!(replayed, replay(&events));
+
+    let game = replayed.game.unwrap();
+    assert_eq!(game.status, GameStatus::End);
+    assert_eq!(game.win_result, Some(WinResult::Democracy));
+    assert_eq!(game.seed, 42);
+    assert_eq!(game.eliminated_players.unwrap()[0].player.id, "b");
+    assert_eq!(game.corrupted_players.unwrap()[0].id, "b");
+    assert!(replayed.results.contains_key(&0));
+
+    let a = &replayed.players["a"];
+    assert_eq!(a.role, Some(Role::Normal));
+    assert_eq!(a.used_action, Some(ActionStatus::Voted));
+    assert_eq!(a.last_round_action, Some(1));
+    assert_eq!(replayed.players["b"].eliminated, Some(true));
+}
@@ -0,0 +1,87 @@
+use tonk_shared_lib::*;
+use tonk_shared_lib::events::Actor;
+use tonk_shared_lib::lobby::*;
+use tonk_shared_lib::store::*;
+
+fn lobby(config: GameConfig) -> Game {
+    Game {
+        id: "g".to_string(),
+        status: GameStatus::Lobby,
+        time: Some(Time { round: 0, timer: 0 }),
+        win_result: None,
+        corrupted_players: None,
+        eliminated_players: None,
+        demo_play: false,
+        paused: false,
+        config,
+        seed: 7,
+        runoff: None,
+        host: Some("a".to_string())
+    }
+}
+
+async fn join(store: &MemoryStore, id: &str) {
+    let player = Player {
+        id: id.to_string(),
+        mobile_unit_id: None,
+        display_name: None,
+        secret_key: None,
+        role: None,
+        used_action: None,
+        last_round_action: None,
+        eliminated: None,
+        proximity: None
+    };
+    let key = format!("player:{}", id);
+    store.set_key(&key, &player).await.unwrap();
+    store.add_to_index("game:g:player_index", &key).await.unwrap();
+}
+
+async fn add_buildings(store: &MemoryStore) {
+    for id in 0..4 {
+        let key = format!("building:{}", id);
+        store.set_key(&key, &Building {
+            id: id.to_string(),
+            readable_id: format!("Depot {}", id),
+            location: None,
+            task_message: String::new(),
+            is_tower: id == 0
+        }).await.unwrap();
+        store.add_to_index("building:index", &key).await.unwrap();
+    }
+}
+
+#[tokio::test]
+async fn countdown_needs_every_player_ready() {
+    let store = MemoryStore::new();
+    let game = lobby(GameConfig { auto_start: Some(10), ..Default::default() });
+    join(&store, "a").await;
+    assert!(!everyone_ready(&store, &game, Some("a")).await.unwrap());
+
+    join(&store, "b").await;
+    store.add_to_index(&ready_key("g"), "player:a").await.unwrap();
+    assert!(!everyone_ready(&store, &game, None).await.unwrap());
+    assert!(everyone_ready(&store, &game, Some("b")).await.unwrap());
+
+    let manual = lobby(GameConfig::default());
+    assert!(!everyone_ready(&store, &manual, Some("b")).await.unwrap());
+}
+
+#[tokio::test]
+async fn start_respects_the_player_limits() {
+    let store = MemoryStore::new();
+    add_buildings(&store).await;
+    let game = lobby(GameConfig { min_players: 3, ..Default::default() });
+    store.set_key("game:g", &game).await.unwrap();
+    join(&store, "a").await;
+    join(&store, "b").await;
+    store.add_to_index(&ready_key("g"), "player:a").await.unwrap();
+
+    let refused = start_game(&store, game.clone(), false, Actor::StateService).await;
+    assert!(matches!(refused, Err(StartError::NotEnoughPlayers)));
+
+    join(&store, "c").await;
+    let started = start_game(&store, game, false, Actor::StateService).await.unwrap();
+    assert_eq!(started.status, GameStatus::Tasks);
+    assert!(store.get_index_keys(&ready_key("g")).await.unwrap().is_empty());
+}
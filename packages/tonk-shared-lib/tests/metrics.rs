@@ -0,0 +1,52 @@
+use tonk_shared_lib::metrics::*;
+
+#[test]
+fn counters_and_gauges_render_per_label_set() {
+    let metrics = Metrics::new();
+    metrics.job_runs.with_label_values(&["clock"]).inc();
+    metrics.job_runs.with_label_values(&["clock"]).inc();
+    metrics.job_runs.with_label_values(&["game_state"]).inc();
+    metrics.game_round.with_label_values(&["g1"]).set(3);
+
+    let out = metrics.render();
+    assert!(out.contains("# TYPE tonk_job_runs_total counter\n"));
+    assert!(out.contains("tonk_job_runs_total{job=\"clock\"} 2\n"));
+    assert!(out.contains("tonk_job_runs_total{job=\"game_state\"} 1\n"));
+    assert!(out.contains("# TYPE tonk_game_round gauge\n"));
+    assert!(out.contains("tonk_game_round{game=\"g1\"} 3\n"));
+}
+
+#[test]
+fn histograms_are_cumulative() {
+    let metrics = Metrics::new();
+    let get = metrics.redis_operation_duration.with_label_values(&["get"]);
+    for seconds in [0.003, 0.2, 30.0] {
+        get.observe(seconds);
+    }
+
+    let out = metrics.render();
+    assert!(out.contains("tonk_redis_operation_duration_seconds_bucket{operation=\"get\",le=\"0.005\"} 1\n"));
+    assert!(out.contains("tonk_redis_operation_duration_seconds_bucket{operation=\"get\",le=\"0.25\"} 2\n"));
+    assert!(out.contains("tonk_redis_operation_duration_seconds_bucket{operation=\"get\",le=\"10\"} 2\n"));
+    assert!(out.contains("tonk_redis_operation_duration_seconds_bucket{operation=\"get\",le=\"+Inf\"} 3\n"));
+    assert!(out.contains("tonk_redis_operation_duration_seconds_count{operation=\"get\"} 3\n"));
+}
+
+#[test]
+fn resetting_a_gauge_drops_ended_games() {
+    let metrics = Metrics::new();
+    metrics.game_players.with_label_values(&["g1"]).set(4);
+    metrics.game_players.reset();
+    metrics.game_players.with_label_values(&["g2"]).set(6);
+
+    let out = metrics.render();
+    assert!(!out.contains("game=\"g1\""));
+    assert!(out.contains("tonk_game_players{game=\"g2\"} 6\n"));
+}
+
+#[test]
+fn label_values_are_escaped() {
+    let metrics = Metrics::new();
+    metrics.http_requests.with_label_values(&["/a\"b", "GET", "200"]).inc();
+    assert!(metrics.render().contains("tonk_http_requests_total{method=\"GET\",route=\"/a\\\"b\",status=\"200\"} 1\n"));
+}
@@ -0,0 +1,113 @@
+use tonk_shared_lib::*;
+use tonk_shared_lib::phase::*;
+
+fn snapshot(status: GameStatus, timer: u32, round: u32) -> GameSnapshot {
+    GameSnapshot {
+        status,
+        time: Time { timer, round },
+        paused: false,
+        win_result: None,
+        config: GameConfig::default(),
+    }
+}
+
+const ALL_STATUSES: [GameStatus; 7] = [
+    GameStatus::Null, GameStatus::Lobby, GameStatus::Tasks, GameStatus::TaskResult,
+    GameStatus::Vote, GameStatus::VoteResult, GameStatus::End
+];
+
+#[test]
+fn nothing_happens_while_paused() {
+    for status in ALL_STATUSES {
+        for all_in in [true, false] {
+            let mut paused = snapshot(status.clone(), 0, 1);
+            paused.paused = true;
+            assert_eq!(reduce(paused.clone(), Event::Tick { all_in }), (paused.clone(), vec![]));
+            assert_eq!(reduce(paused.clone(), Event::VotesCounted { runoff: true }), (paused.clone(), vec![]));
+            assert_eq!(reduce(paused.clone(), Event::Resolved { win_result: WinResult::Democracy }), (paused, vec![]));
+        }
+    }
+}
+
+#[test]
+fn phases_wait_for_their_timer() {
+    for status in ALL_STATUSES {
+        let running = snapshot(status, 10, 1);
+        assert_eq!(reduce(running.clone(), Event::Tick { all_in: false }), (running, vec![]));
+    }
+    let lobby = snapshot(GameStatus::Lobby, 0, 0);
+    assert_eq!(reduce(lobby.clone(), Event::Tick { all_in: false }), (lobby, vec![]));
+}
+
+#[test]
+fn ready_lobby_starts_after_its_countdown() {
+    let counting = snapshot(GameStatus::Lobby, 5, 0);
+    assert_eq!(reduce(counting.clone(), Event::Tick { all_in: true }), (counting, vec![]));
+
+    let lobby = snapshot(GameStatus::Lobby, 0, 0);
+    assert_eq!(reduce(lobby.clone(), Event::Tick { all_in: true }), (lobby, vec![Effect::StartGame]));
+}
+
+#[test]
+fn everyone_finishing_ends_the_phase_early() {
+    for status in [GameStatus::Tasks, GameStatus::Vote] {
+        let (next, effects) = reduce(snapshot(status.clone(), 10, 1), Event::Tick { all_in: true });
+        assert_eq!(next, snapshot(status, 0, 1));
+        assert_eq!(effects, vec![Effect::SaveGame]);
+    }
+}
+
+#[test]
+fn task_round_moves_to_vote_or_end() {
+    let tasks = snapshot(GameStatus::Tasks, 0, 1);
+    let (tasks, effects) = reduce(tasks, Event::Tick { all_in: false });
+    assert_eq!(effects, vec![Effect::ResolveTasks, Effect::CheckWin]);
+
+    let (next, effects) = reduce(tasks.clone(), Event::Resolved { win_result: WinResult::Null });
+    assert_eq!(next, snapshot(GameStatus::Vote, tasks.config.vote_duration, 2));
+    assert_eq!(effects, vec![Effect::UpdateEliminated, Effect::ResetRound, Effect::SaveGame]);
+
+    let (next, effects) = reduce(tasks.clone(), Event::Resolved { win_result: WinResult::Perfection });
+    assert_eq!(next.status, GameStatus::End);
+    assert_eq!(next.time, Time { timer: tasks.config.end_duration, round: 1 });
+    assert_eq!(next.win_result, Some(WinResult::Perfection));
+    assert_eq!(effects.last(), Some(&Effect::CleanUpWin(WinResult::Perfection)));
+}
+
+#[test]
+fn vote_round_moves_to_result_then_tasks_or_end() {
+    let vote = snapshot(GameStatus::Vote, 0, 2);
+    assert_eq!(reduce(vote.clone(), Event::Tick { all_in: true }), (vote.clone(), vec![Effect::ResolveVotes]));
+
+    let (runoff, effects) = reduce(vote.clone(), Event::VotesCounted { runoff: true });
+    assert_eq!(runoff, snapshot(GameStatus::Vote, vote.config.vote_duration, 2));
+    assert_eq!(effects, vec![Effect::SaveGame]);
+
+    let (result, effects) = reduce(vote, Event::VotesCounted { runoff: false });
+    assert_eq!(result, snapshot(GameStatus::VoteResult, GameConfig::default().vote_result_duration, 2));
+    assert_eq!(effects, vec![Effect::SaveGame]);
+
+    let result = snapshot(GameStatus::VoteResult, 0, 2);
+    assert_eq!(reduce(result.clone(), Event::Tick { all_in: false }).1, vec![Effect::CheckWin]);
+
+    let (next, effects) = reduce(result.clone(), Event::Resolved { win_result: WinResult::Null });
+    assert_eq!(next, snapshot(GameStatus::Tasks, result.config.task_duration, 3));
+    assert_eq!(effects, vec![Effect::ResetRound, Effect::UpdateEliminated, Effect::SaveGame]);
+
+    let (next, _) = reduce(result, Event::Resolved { win_result: WinResult::Thuggery });
+    assert_eq!((next.status, next.win_result), (GameStatus::End, Some(WinResult::Thuggery)));
+}
+
+#[test]
+fn finished_game_is_torn_down() {
+    let end = snapshot(GameStatus::End, 0, 4);
+    assert_eq!(reduce(end.clone(), Event::Tick { all_in: false }), (end, vec![Effect::Teardown]));
+}
+
+#[test]
+fn verdicts_outside_a_resolving_phase_are_ignored() {
+    for status in [GameStatus::Lobby, GameStatus::Vote, GameStatus::End, GameStatus::TaskResult, GameStatus::Null] {
+        let idle = snapshot(status, 0, 1);
+        assert_eq!(reduce(idle.clone(), Event::Resolved { win_result: WinResult::Democracy }), (idle, vec![]));
+    }
+}
@@ -0,0 +1,25 @@
+use tonk_shared_lib::GameConfig;
+use tonk_shared_lib::presence::*;
+use tonk_shared_lib::store::MemoryStore;
+
+#[test]
+fn only_recently_disconnected_players_are_excused() {
+    let strict = GameConfig { heartbeat_timeout: 20, ..Default::default() };
+    assert!(!is_excused(&strict, Some(1000), 1030));
+
+    let lenient = GameConfig { heartbeat_timeout: 20, grace_period: Some(60), ..Default::default() };
+    assert!(!is_excused(&lenient, Some(1000), 1010));
+    assert!(is_excused(&lenient, Some(1000), 1030));
+    assert!(is_excused(&lenient, Some(1000), 1080));
+    assert!(!is_excused(&lenient, Some(1000), 1081));
+    assert!(!is_excused(&lenient, None, 1030));
+}
+
+#[tokio::test]
+async fn heartbeats_are_recorded() {
+    let store = MemoryStore::new();
+    assert_eq!(last_seen(&store, "a").await.unwrap(), None);
+    touch(&store, "a").await.unwrap();
+    let seen = last_seen(&store, "a").await.unwrap().unwrap();
+    assert!(seen <= now() && seen + 5 >= now());
+}
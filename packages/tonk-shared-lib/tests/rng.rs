@@ -0,0 +1,79 @@
+use tonk_shared_lib::*;
+use tonk_shared_lib::rng::*;
+
+mod common;
+use common::player;
+
+fn building(id: &str, is_tower: bool) -> Building {
+    Building {
+        id: id.to_string(),
+        readable_id: id.to_string(),
+        location: None,
+        task_message: String::new(),
+        is_tower
+    }
+}
+
+#[test]
+fn same_seed_assigns_same_roles() {
+    let players: Vec<Player> = (0..8).map(|i| player(&i.to_string())).collect();
+    let mut reversed = players.clone();
+    reversed.reverse();
+
+    let config = GameConfig::default();
+    let roles = assign_roles(&players, &config, 42);
+    assert_eq!(roles, assign_roles(&reversed, &config, 42));
+
+    let bugs = roles.iter().filter(|p| p.role == Some(Role::Bugged)).count();
+    assert!((1..=2).contains(&bugs));
+}
+
+#[test]
+fn special_roles_go_to_the_crew() {
+    let players: Vec<Player> = (0..8).map(|i| player(&i.to_string())).collect();
+    let config = GameConfig { medics: 1, detectives: 1, jesters: 1, ..Default::default() };
+    let roles = assign_roles(&players, &config, 7);
+
+    for role in [Role::Medic, Role::Detective, Role::Jester] {
+        assert_eq!(roles.iter().filter(|p| p.role == Some(role.clone())).count(), 1);
+    }
+    assert!(roles.iter().any(|p| p.role == Some(Role::Bugged)));
+    assert!(Role::Jester.wins(&WinResult::Jester) && !Role::Medic.wins(&WinResult::Thuggery));
+}
+
+#[test]
+fn depots_are_distinct_and_repeatable() {
+    let buildings = vec![building("tower", true), building("a", false), building("b", false), building("c", false)];
+    let (first, second) = pick_depots(&buildings, 42, 3, "p").unwrap();
+    assert_ne!(first.id, second.id);
+    assert!(!first.is_tower && !second.is_tower);
+    assert_eq!(pick_depots(&buildings, 42, 3, "p"), Some((first, second)));
+
+    assert!(pick_depots(&buildings[..2], 42, 3, "p").is_none());
+}
+
+#[test]
+fn seed_is_hidden_until_the_game_ends() {
+    let mut game = Game {
+        id: "g".to_string(),
+        status: GameStatus::Vote,
+        time: None,
+        win_result: None,
+        corrupted_players: None,
+        eliminated_players: None,
+        demo_play: false,
+        paused: false,
+        config: GameConfig::default(),
+        seed: 42,
+        runoff: None,
+        host: None
+    };
+    let json = serde_json::to_value(&game).unwrap();
+    assert!(json["seed"].is_null());
+
+    game.status = GameStatus::End;
+    let json = serde_json::to_value(&game).unwrap();
+    assert_eq!(json["seed"], 42);
+    let parsed: Game = serde_json::from_value(json).unwrap();
+    assert_eq!(parsed.seed, 42);
+}
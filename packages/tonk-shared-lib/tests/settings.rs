@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+use tonk_shared_lib::settings::*;
+
+fn values(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+}
+
+#[test]
+fn env_files_are_parsed() {
+    let parsed = parse_env_file("REDIS_URL=\"redis://redis:6379\"\n# ALLOWED_ORIGIN=\"x\"\nDS_ENDPOINT = \"http://localhost:8080/query\"\n\n").unwrap();
+    assert_eq!(parsed, values(&[("REDIS_URL", "redis://redis:6379"), ("DS_ENDPOINT", "http://localhost:8080/query")]));
+
+    let parsed = parse_env_file("export ADMIN_KEY='a # b'\nPORT=8083 # the usual port\n").unwrap();
+    assert_eq!(parsed, values(&[("ADMIN_KEY", "a # b"), ("PORT", "8083")]));
+    assert!(parse_env_file("REDIS_URL=\"redis://unterminated\n").is_err());
+}
+
+#[test]
+fn settings_are_checked_up_front() {
+    let local = Settings::from_values(Service::WebServer, &values(&[("REDIS_URL", "redis://0.0.0.0/")])).unwrap();
+    assert!(!local.is_production());
+    assert_eq!((local.bind_address.to_string(), local.port), ("0.0.0.0".to_string(), 8082));
+    assert_eq!(local.metrics_port, 9091);
+    assert_eq!(local.admin_key, None);
+    assert!(local.trusted_proxies.is_empty());
+
+    let missing = Settings::from_values(Service::StateService, &values(&[("REDIS_URL", "redis://0.0.0.0/")]));
+    assert!(matches!(missing, Err(SettingsError::Missing("DS_ENDPOINT"))));
+    let bad_port = Settings::from_values(Service::WebServer, &values(&[("REDIS_URL", "redis://0.0.0.0/"), ("PORT", "http")]));
+    assert!(matches!(bad_port, Err(SettingsError::Invalid { .. })));
+    assert!(Settings::from_values(Service::Tool, &values(&[])).is_err());
+}
+
+#[test]
+fn trusted_proxies_are_ip_addresses() {
+    let settings = Settings::from_values(Service::WebServer, &values(&[("REDIS_URL", "redis://0.0.0.0/"), ("TRUSTED_PROXIES", "10.0.0.1, ::1")])).unwrap();
+    assert_eq!(settings.trusted_proxies, vec!["10.0.0.1".parse::<std::net::IpAddr>().unwrap(), "::1".parse().unwrap()]);
+    let bad = Settings::from_values(Service::WebServer, &values(&[("REDIS_URL", "redis://0.0.0.0/"), ("TRUSTED_PROXIES", "proxy.local")]));
+    assert!(matches!(bad, Err(SettingsError::Invalid { .. })));
+}
+
+#[test]
+fn rate_limits_can_be_changed_or_turned_off() {
+    let settings = Settings::from_values(Service::WebServer, &values(&[
+        ("REDIS_URL", "redis://0.0.0.0/"),
+        ("RATE_LIMIT_VOTE_PLAYER", "3/10"),
+        ("RATE_LIMIT_VOTE_IP", "off"),
+    ])).unwrap();
+    assert_eq!(settings.rate_limit("vote", "player", Limit::per_minute(10)), Some(Limit { requests: 3, window: 10 }));
+    assert_eq!(settings.rate_limit("vote", "ip", Limit::per_minute(10)), None);
+    assert_eq!(settings.rate_limit("task", "player", Limit::per_minute(10)), Some(Limit::per_minute(10)));
+
+    let bad = Settings::from_values(Service::WebServer, &values(&[("REDIS_URL", "redis://0.0.0.0/"), ("RATE_LIMIT_VOTE_IP", "lots")]));
+    assert!(bad.is_err());
+}
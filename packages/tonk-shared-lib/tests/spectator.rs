@@ -0,0 +1,90 @@
+use tonk_shared_lib::*;
+use tonk_shared_lib::spectator::*;
+use tonk_shared_lib::stream::StreamMessage;
+
+mod common;
+use common::player_with_role;
+
+// a player with every detail a spectator shouldn't see
+fn player(id: &str, role: Role) -> Player {
+    Player {
+        mobile_unit_id: Some(format!("unit-{}", id)),
+        display_name: Some(id.to_uppercase()),
+        secret_key: Some("secret".to_string()),
+        ..player_with_role(id, role)
+    }
+}
+
+fn game(status: GameStatus, eliminated: Vec<Elimination>) -> Game {
+    Game {
+        id: "g".to_string(),
+        status,
+        time: Some(Time { round: 2, timer: 30 }),
+        win_result: None,
+        corrupted_players: None,
+        eliminated_players: Some(eliminated),
+        demo_play: false,
+        paused: false,
+        config: GameConfig::default(),
+        seed: 7,
+        runoff: None,
+        host: None
+    }
+}
+
+#[test]
+fn roles_are_hidden_until_the_end() {
+    let bug = player("a", Role::Bugged);
+    let voted_out = Elimination { player: bug.clone(), reason: EliminationReason::VotedOut };
+    let location = Location("1".to_string(), "2".to_string(), "3".to_string(), "4".to_string());
+    let proximity = PlayerProximity { nearby_players: None, nearby_buildings: None, immune: None, location: Some(location.clone()) };
+    let players = vec![(bug, None), (player("b", Role::Normal), Some(proximity))];
+    let results = vec![RoundResult { round_type: GameStatus::Vote, eliminated: Some(vec![voted_out.clone()]), tasks_completed: None, tally: None }];
+
+    let live = spectator_view(&game(GameStatus::Tasks, vec![voted_out.clone()]), &players, &results);
+    assert!(live.players.iter().all(|p| p.role.is_none()));
+    assert_eq!(live.players[0].eliminated, Some(EliminationReason::VotedOut));
+    assert_eq!(live.players[1].location, Some(location));
+    let shown = &live.results[0].eliminated.as_ref().unwrap()[0].player;
+    assert_eq!((shown.role.as_ref(), shown.secret_key.as_ref()), (None, None));
+
+    let ended = spectator_view(&game(GameStatus::End, vec![voted_out]), &players, &results);
+    assert_eq!(ended.players[0].role, Some(Role::Bugged));
+    assert_eq!(ended.results[0].eliminated.as_ref().unwrap()[0].player.role, Some(Role::Bugged));
+}
+
+#[test]
+fn the_spectator_stream_hides_who_the_bugs_are() {
+    let bug = player("a", Role::Bugged);
+    let voted_out = Elimination { player: bug.clone(), reason: EliminationReason::VotedOut };
+    let mut live = game(GameStatus::Vote, vec![voted_out.clone()]);
+    live.corrupted_players = Some(vec![bug.clone()]);
+
+    let redacted = redact_game(&live);
+    assert_eq!(redacted.corrupted_players, None);
+    let shown = &redacted.eliminated_players.as_ref().unwrap()[0].player;
+    assert_eq!((shown.role.as_ref(), shown.secret_key.as_ref()), (None, None));
+
+    live.status = GameStatus::End;
+    let ended = redact_game(&live);
+    assert_eq!(ended.corrupted_players.unwrap()[0].role, Some(Role::Bugged));
+
+    let result = RoundResult { round_type: GameStatus::Vote, eliminated: Some(vec![voted_out]), tasks_completed: None, tally: None };
+    match spectator_message(StreamMessage::RoundResult { round: 1, result }) {
+        Some(StreamMessage::RoundResult { result, .. }) => assert_eq!(result.eliminated.unwrap()[0].player.role, None),
+        other => panic!("unexpected message {:?}", other),
+    }
+    let proximity = PlayerProximity { nearby_players: None, nearby_buildings: None, immune: None, location: None };
+    assert!(spectator_message(StreamMessage::Proximity { player_id: "a".to_string(), proximity }).is_none());
+}
+
+#[test]
+fn a_redacted_player_keeps_only_their_public_details() {
+    let mut bug = player("a", Role::Bugged);
+    bug.proximity = Some(PlayerProximity { nearby_players: Some(vec![player("b", Role::Bugged)]), nearby_buildings: None, immune: None, location: None });
+
+    let shown = redact_player(&bug, false);
+    assert_eq!((shown.id.as_str(), shown.display_name.as_deref()), ("a", Some("A")));
+    assert_eq!((shown.role, shown.secret_key, shown.mobile_unit_id), (None, None, None));
+    assert!(shown.proximity.is_none());
+}
@@ -0,0 +1,116 @@
+use tonk_shared_lib::Building;
+use tonk_shared_lib::redis_helper::RedisHelperError;
+use tonk_shared_lib::store::*;
+
+fn building(id: &str) -> Building {
+    Building {
+        id: id.to_string(),
+        readable_id: format!("Depot {}", id),
+        location: None,
+        task_message: String::new(),
+        is_tower: false
+    }
+}
+
+#[tokio::test]
+async fn keys_round_trip() {
+    let store = MemoryStore::new();
+    store.set_key("building:1", &building("1")).await.unwrap();
+
+    let saved: Building = store.get_key("building:1").await.unwrap();
+    assert_eq!(saved, building("1"));
+
+    store.clear_key("building:1").await.unwrap();
+    let missing: Result<Building, _> = store.get_key("building:1").await;
+    assert!(matches!(missing, Err(RedisHelperError::MissingKey)));
+}
+
+#[tokio::test]
+async fn index_returns_members() {
+    let store = MemoryStore::new();
+    for id in ["1", "2"] {
+        let key = format!("building:{}", id);
+        store.set_key(&key, &building(id)).await.unwrap();
+        store.add_to_index("building:index", &key).await.unwrap();
+    }
+    store.remove_from_index("building:index", "building:1").await.unwrap();
+
+    let buildings: Vec<Building> = store.get_index("building:index").await.unwrap();
+    assert_eq!(buildings, vec![building("2")]);
+
+    store.clear_index("building:index").await.unwrap();
+    assert!(store.get_index_keys("building:index").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn index_skips_stale_and_unreadable_members() {
+    let store = MemoryStore::new();
+    store.set_key("building:1", &building("1")).await.unwrap();
+    store.set_key_test("building:2", "not a building");
+    for key in ["building:1", "building:2", "building:3"] {
+        store.add_to_index("building:index", key).await.unwrap();
+    }
+
+    let buildings: Vec<Building> = store.get_index("building:index").await.unwrap();
+    assert_eq!(buildings, vec![building("1")]);
+}
+
+#[tokio::test]
+async fn capped_index_stops_at_its_limit() {
+    let store = MemoryStore::new();
+    assert!(store.add_to_index_if_below("lobby", "player:a", 2).await.unwrap());
+    assert!(store.add_to_index_if_below("lobby", "player:b", 2).await.unwrap());
+    assert!(!store.add_to_index_if_below("lobby", "player:c", 2).await.unwrap());
+    // a member already in the index is still in it
+    assert!(store.add_to_index_if_below("lobby", "player:a", 2).await.unwrap());
+    let mut keys = store.get_index_keys("lobby").await.unwrap();
+    keys.sort();
+    assert_eq!(keys, vec!["player:a".to_string(), "player:b".to_string()]);
+}
+
+#[tokio::test]
+async fn test_injection_keys_are_strings() {
+    let store = MemoryStore::new();
+    store.set_key_test("stop", "true");
+    assert_eq!(store.get_key_test("stop").await.unwrap(), "true");
+}
+
+#[tokio::test]
+async fn conditional_writes() {
+    let store = MemoryStore::new();
+    assert!(store.set_key_if_absent("building:1", &building("1")).await.unwrap());
+    assert!(!store.set_key_if_absent("building:1", &building("2")).await.unwrap());
+
+    assert!(!store.compare_and_set_key("building:1", &building("2"), &building("3")).await.unwrap());
+    assert!(store.compare_and_set_key("building:1", &building("1"), &building("3")).await.unwrap());
+    let saved: Building = store.get_key("building:1").await.unwrap();
+    assert_eq!(saved, building("3"));
+}
+
+#[tokio::test]
+async fn update_key_applies_to_latest_value() {
+    let store = MemoryStore::new();
+    store.set_key("building:1", &building("1")).await.unwrap();
+
+    let updated = store.update_key("building:1", |mut b: Building| {
+        b.is_tower = true;
+        Some(b)
+    }).await.unwrap();
+    assert!(updated.unwrap().is_tower);
+
+    let skipped = store.update_key("building:1", |_: Building| None).await.unwrap();
+    assert!(skipped.is_none());
+    let saved: Building = store.get_key("building:1").await.unwrap();
+    assert!(saved.is_tower);
+}
+
+#[tokio::test]
+async fn counters_reset_after_their_window() {
+    let store = MemoryStore::new();
+    assert_eq!(store.increment_counter("ratelimit:a", 60).await.unwrap(), (1, 60));
+    assert_eq!(store.increment_counter("ratelimit:a", 60).await.unwrap().0, 2);
+    assert_eq!(store.increment_counter("ratelimit:b", 60).await.unwrap().0, 1);
+
+    assert_eq!(store.increment_counter("ratelimit:c", 0).await.unwrap().0, 1);
+    assert_eq!(store.increment_counter("ratelimit:c", 0).await.unwrap().0, 1);
+}
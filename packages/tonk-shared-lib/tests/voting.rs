@@ -0,0 +1,68 @@
+use tonk_shared_lib::*;
+use tonk_shared_lib::voting::*;
+
+mod common;
+use common::player;
+
+fn ballot(voter: &str, candidate: Option<&str>) -> Vote {
+    Vote { candidate: candidate.map(player), voter: Some(voter.to_string()) }
+}
+
+#[test]
+fn tally_counts_every_ballot() {
+    let votes = vec![ballot("a", Some("c")), ballot("b", Some("c")), ballot("c", Some("a")), ballot("d", None)];
+    let tally = VoteTally::count(&votes, false);
+    assert_eq!(tally.candidates[0], CandidateTally { candidate: player("c"), votes: 2 });
+    assert_eq!(tally.candidates[1], CandidateTally { candidate: player("a"), votes: 1 });
+    assert_eq!(tally.abstentions, 1);
+    assert_eq!(tally.total(), 4);
+
+    let outcome = resolve_votes(&tally, &GameConfig::default(), 1, 1, false);
+    assert_eq!(outcome, VoteOutcome::VotedOut(Box::new(player("c"))));
+}
+
+#[test]
+fn ties_follow_the_policy() {
+    let votes = vec![ballot("a", Some("c")), ballot("b", Some("d")), ballot("c", Some("d")), ballot("d", Some("c"))];
+    let tally = VoteTally::count(&votes, false);
+
+    let mut config = GameConfig::default();
+    assert_eq!(resolve_votes(&tally, &config, 1, 1, false), VoteOutcome::NoElimination);
+
+    config.tie_policy = TiePolicy::Runoff;
+    assert_eq!(resolve_votes(&tally, &config, 1, 1, false), VoteOutcome::Runoff(vec![player("c"), player("d")]));
+    assert_eq!(resolve_votes(&tally, &config, 1, 1, true), VoteOutcome::NoElimination);
+
+    config.tie_policy = TiePolicy::Random;
+    let picked = resolve_votes(&tally, &config, 9, 1, false);
+    assert!(picked == VoteOutcome::VotedOut(Box::new(player("c"))) || picked == VoteOutcome::VotedOut(Box::new(player("d"))));
+    assert_eq!(resolve_votes(&tally, &config, 9, 1, false), picked);
+}
+
+#[test]
+fn abstentions_can_block_a_majority() {
+    let votes = vec![ballot("a", Some("c")), ballot("b", Some("c")), ballot("c", None), ballot("d", None), ballot("e", Some("a"))];
+    let tally = VoteTally::count(&votes, false);
+    let config = GameConfig { majority_threshold: Some(0.5), ..Default::default() };
+    assert_eq!(resolve_votes(&tally, &config, 1, 1, false), VoteOutcome::NoElimination);
+
+    let everyone_abstains = VoteTally::count(&[ballot("a", None)], false);
+    assert_eq!(resolve_votes(&everyone_abstains, &GameConfig::default(), 1, 1, false), VoteOutcome::NoElimination);
+}
+
+#[test]
+fn ballots_are_only_kept_when_revealed() {
+    let mut candidate = player("a");
+    candidate.role = Some(Role::Bugged);
+    let votes = vec![Vote { candidate: Some(candidate), voter: Some("b".to_string()) }, ballot("a", None)];
+
+    let hidden = VoteTally::count(&votes, false);
+    assert_eq!(hidden.ballots, None);
+    assert_eq!(hidden.candidates[0].candidate.role, None);
+
+    let revealed = VoteTally::count(&votes, true);
+    assert_eq!(revealed.ballots, Some(vec![
+        Ballot { voter: "a".to_string(), candidate: None },
+        Ballot { voter: "b".to_string(), candidate: Some("a".to_string()) },
+    ]));
+}
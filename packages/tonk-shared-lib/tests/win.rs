@@ -0,0 +1,66 @@
+use tonk_shared_lib::*;
+use tonk_shared_lib::win::*;
+
+mod common;
+use common::player_with_role;
+
+fn snapshot(players: Vec<Player>) -> WinSnapshot {
+    WinSnapshot {
+        round_type: GameStatus::VoteResult,
+        tasks_assigned: 0,
+        tasks_completed: 0,
+        players,
+        eliminated: Vec::new(),
+        demo_play: false,
+        thuggery_threshold: 0.5,
+    }
+}
+
+fn voted_out(player: &Player) -> Elimination {
+    Elimination { player: player.clone(), reason: EliminationReason::VotedOut }
+}
+
+#[test]
+fn game_goes_on_while_both_sides_remain() {
+    let players = vec![player_with_role("a", Role::Normal), player_with_role("b", Role::Normal), player_with_role("c", Role::Bugged)];
+    assert_eq!(evaluate_win(&WinRule::all(), &snapshot(players)), WinResult::Null);
+}
+
+#[test]
+fn voting_out_the_last_bug_is_democracy() {
+    let players = vec![player_with_role("a", Role::Normal), player_with_role("b", Role::Medic), player_with_role("c", Role::Bugged)];
+    let mut snapshot = snapshot(players.clone());
+    snapshot.eliminated.push(voted_out(&players[2]));
+    assert_eq!(evaluate_win(&WinRule::all(), &snapshot), WinResult::Democracy);
+}
+
+#[test]
+fn jester_outranks_the_other_rules() {
+    let players = vec![player_with_role("a", Role::Normal), player_with_role("b", Role::Jester), player_with_role("c", Role::Bugged)];
+    let mut snapshot = snapshot(players.clone());
+    snapshot.eliminated.push(voted_out(&players[1]));
+    assert_eq!(evaluate_win(&WinRule::all(), &snapshot), WinResult::Jester);
+
+    // a mode without the jester rule falls through to the bugs outnumbering the crew
+    let rules = vec![WinRule::Thuggery, WinRule::Democracy];
+    assert_eq!(evaluate_win(&rules, &snapshot), WinResult::Thuggery);
+}
+
+#[test]
+fn perfection_only_follows_a_task_round() {
+    let players = vec![player_with_role("a", Role::Normal), player_with_role("b", Role::Normal), player_with_role("c", Role::Normal), player_with_role("d", Role::Bugged)];
+    let mut snapshot = snapshot(players);
+    snapshot.tasks_assigned = 3;
+    snapshot.tasks_completed = 3;
+    assert_eq!(evaluate_win(&WinRule::all(), &snapshot), WinResult::Null);
+
+    // no result is ever recorded as Tasks, the state service writes the task round's as TaskResult
+    snapshot.round_type = GameStatus::Tasks;
+    assert_eq!(evaluate_win(&WinRule::all(), &snapshot), WinResult::Null);
+
+    snapshot.round_type = GameStatus::TaskResult;
+    assert_eq!(evaluate_win(&WinRule::all(), &snapshot), WinResult::Perfection);
+
+    snapshot.demo_play = true;
+    assert_eq!(evaluate_win(&WinRule::all(), &snapshot), WinResult::Null);
+}
@@ -0,0 +1,57 @@
+use std::env;
+use tonk_shared_lib::Game;
+use tonk_shared_lib::events::{events_key, replay, GameEvent};
+use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::settings::{Service, Settings};
+
+// Rebuilds a game from its event log and prints the result as JSON
+// usage: cargo run --bin replay -- <game_id> [--events] [--redis-url <url>]
+// --events also prints every event in the log, one per line
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let show_events = args.iter().any(|a| a == "--events");
+    // the game id comes first, anything after it is a setting like --redis-url
+    let (game_id, setting_args) = match args.iter().position(|a| !a.starts_with("--")) {
+        Some(i) => (args[i].clone(), args[i + 1..].iter().filter(|a| *a != "--events").cloned().collect::<Vec<String>>()),
+        None => {
+            eprintln!("usage: replay <game_id> [--events] [--redis-url <url>]");
+            std::process::exit(1);
+        }
+    };
+    let settings = match Settings::load(Service::Tool, &setting_args) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("replay: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let store = RedisHelper::init(&settings.redis_url).await?;
+    let events: Vec<GameEvent> = store.get_list(&events_key(&game_id)).await?;
+    if events.is_empty() {
+        eprintln!("no events recorded for game {}", game_id);
+        std::process::exit(1);
+    }
+    if show_events {
+        for event in &events {
+            println!("{}", serde_json::to_string(event)?);
+        }
+    }
+
+    let replayed = replay(&events);
+    println!("{}", serde_json::to_string_pretty(&replayed)?);
+
+    // a live game can be checked against what its log says it should be
+    let stored: Result<Game, RedisHelperError> = store.get_key(&format!("game:{}", game_id)).await;
+    if let (Ok(stored), Some(game)) = (stored, replayed.game.as_ref()) {
+        let same_phase = stored.status == game.status
+            && stored.time.as_ref().map(|t| t.round) == game.time.as_ref().map(|t| t.round);
+        if !same_phase || stored.win_result != game.win_result || stored.eliminated_players != game.eliminated_players {
+            eprintln!("the stored game differs from its replay: {:?} round {:?}",
+                stored.status, stored.time.as_ref().map(|t| t.round));
+        }
+    }
+    Ok(())
+}
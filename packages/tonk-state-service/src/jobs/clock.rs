@@ -1,11 +1,16 @@
+use std::sync::Arc;
 use tonk_shared_lib::{Game, GameStatus, Action, Time};
 use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::metrics;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::stream::{game_channel, StreamMessage};
 use serde::{Deserialize,Serialize};
+use log::*;
 
 use super::error::JobError;
 
 pub struct Clock {
-    redis: RedisHelper
+    store: Arc<dyn StateStore>
 }
 
 #[derive(Deserialize, Debug)]
@@ -16,59 +21,94 @@ pub struct ClockTestInjection {
 
 // All this does is advance the clock
 impl Clock {
-    pub fn new(redis: RedisHelper) -> Self {
-        Self { redis }
+    pub fn new(store: Arc<dyn StateStore>) -> Self {
+        Self { store }
     }
 
     pub async fn run(&self) -> Result<(), JobError> {
-        let game: Game = self.redis.get_key("game").await?;
-        if game.status == GameStatus::Null || game.status == GameStatus::Lobby {
-            return Ok(());
+        let games: Vec<Game> = self.store.get_index("game:index").await?;
+        for game in games {
+            if let Err(e) = self.tick(game).await {
+                error!("{:?}", e);
+                metrics::get().job_errors.with_label_values(&["clock"]).inc();
+            }
         }
-        let time = game.time.unwrap();
+        Ok(())
+    }
 
-        if time.timer == 0 {
-            // we should just wait
-        } else {
-            let next_game = Game {
-                id: game.id,
-                status: game.status,
-                demo_play: game.demo_play,
-                corrupted_players: game.corrupted_players,
-                eliminated_players: game.eliminated_players,
+    async fn tick(&self, game: Game) -> Result<(), JobError> {
+        // a lobby only has a running timer while its auto-start countdown is armed
+        if game.status == GameStatus::Null || game.paused {
+            return Ok(());
+        }
+        let game_key = format!("game:{}", game.id);
+        // the tick is applied to the latest copy of the game so a phase change or an
+        // admin command written since we listed the games is never overwritten
+        let ticked = self.store.update_key(&game_key, |current: Game| {
+            if current.status == GameStatus::Null || current.paused {
+                return None;
+            }
+            let time = current.time.clone()?;
+            if time.timer == 0 {
+                // we should just wait
+                return None;
+            }
+            Some(Game {
                 time: Some(Time {
                     timer: time.timer - 1,
                     round: time.round
                 }),
-                win_result: game.win_result 
-            };
-            let _ = self.redis.set_key("game", &next_game).await?;
+                ..current
+            })
+        }).await?;
+        if let Some(next_game) = ticked {
+            self.store.publish(&game_channel(&next_game.id), &StreamMessage::Game(next_game.clone())).await?;
         }
         Ok(())
     }
 
     pub async fn mock_run(&self) -> Result<(), JobError> {
-        let game: Game = self.redis.get_key("game").await?;
+        let games: Vec<Game> = self.store.get_index("game:index").await?;
+        for game in games {
+            if let Err(e) = self.mock_tick(game).await {
+                error!("{:?}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn mock_tick(&self, game: Game) -> Result<(), JobError> {
         if game.status == GameStatus::Null || game.status == GameStatus::Lobby {
             return Ok(());
         }
-        let raw = self.redis.get_key_test("clock").await;
+        let game_key = format!("game:{}", game.id);
+        let clock_key = format!("game:{}:clock", game.id);
+        let raw = self.store.get_key_test(&clock_key).await;
         if raw.is_err() {
             return Ok(());
         } else {
-            let clk: ClockTestInjection = serde_json::from_str(&raw.unwrap()).map_err(|_| RedisHelperError::Unknown)?;
+            let clk: ClockTestInjection = serde_json::from_str(&raw.unwrap()).map_err(|e| RedisHelperError::Deserialization(Box::new(e)))?;
             if clk.time.timer != game.time.as_ref().unwrap().timer || clk.time.round != game.time.as_ref().unwrap().round || clk.status != game.status {
                 let next_game = Game {
-                    id: game.id,
+                    id: game.id.clone(),
                     status: clk.status,
                     demo_play: game.demo_play,
-                    corrupted_players: game.corrupted_players,
-                    eliminated_players: game.eliminated_players,
+                    paused: game.paused,
+                    config: game.config.clone(),
+                    seed: game.seed,
+                    runoff: game.runoff.clone(),
+                    host: game.host.clone(),
+                    corrupted_players: game.corrupted_players.clone(),
+                    eliminated_players: game.eliminated_players.clone(),
                     time: Some(clk.time.clone()),
-                    win_result: game.win_result 
+                    win_result: game.win_result.clone()
                 };
-                let _ = self.redis.set_key("game", &next_game).await?;
-                self.redis.clear_key("clock").await?;
+                // leave the injection in place to retry if the game changed under us
+                if !self.store.compare_and_set_key(&game_key, &game, &next_game).await? {
+                    return Ok(());
+                }
+                self.store.publish(&game_channel(&next_game.id), &StreamMessage::Game(next_game.clone())).await?;
+                self.store.clear_key(&clock_key).await?;
             }
             Ok(())
         }
@@ -1,18 +1,18 @@
 use tonk_shared_lib::redis_helper::*;
 
+// store errors are wrapped rather than flattened so the cause shows up in the logs
 #[derive(Debug)]
 pub enum JobError {
-    RedisError, ClientError, SerializationError, Unknown
+    RedisError(RedisHelperError), ClientError(String), SerializationError(RedisHelperError), Conflict, Unknown
 } 
 
 
 impl std::error::Error for JobError {
-    fn description(&self) -> &str {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
         match self {
-            JobError::SerializationError => "Error: serialization error",
-            JobError::RedisError => "Error: redis error",
-            JobError::ClientError => "Error: client error",
-            JobError::Unknown =>  "Error: unknown error"
+            JobError::RedisError(err) => Some(err),
+            JobError::SerializationError(err) => Some(err),
+            _ => None
         }
     }
 }
@@ -20,9 +20,10 @@ impl std::error::Error for JobError {
 impl std::fmt::Display for JobError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            JobError::SerializationError => write!(f, "Error: serialization error"),
-            JobError::ClientError => write!(f, "Error: client error"),
-            JobError::RedisError => write!(f, "Error: redis error"),
+            JobError::SerializationError(_) => write!(f, "Error: serialization error"),
+            JobError::ClientError(message) => write!(f, "Error: client error: {}", message),
+            JobError::RedisError(_) => write!(f, "Error: redis error"),
+            JobError::Conflict => write!(f, "Error: the game changed before the update was written"),
             JobError::Unknown => write!(f, "Error: unknown error")
         }
     }
@@ -31,14 +32,14 @@ impl std::fmt::Display for JobError {
 impl From<RedisHelperError> for JobError {
     fn from(err: RedisHelperError) -> JobError {
         match err {
-            RedisHelperError::Deserialization => {
-                JobError::SerializationError
+            RedisHelperError::Deserialization(_) | RedisHelperError::Serialization(_) => {
+                JobError::SerializationError(err)
             }
-            RedisHelperError::Serialization => {
-                JobError::SerializationError
+            RedisHelperError::Conflict => {
+                JobError::Conflict
             }
             _ => {
-                JobError::Unknown
+                JobError::RedisError(err)
             }
         }
     }
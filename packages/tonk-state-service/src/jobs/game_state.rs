@@ -1,37 +1,227 @@
+use std::sync::Arc;
 use redis::RedisError;
-use tonk_shared_lib::{Game, Player, GameStatus, Action, Time, Task, RoundResult, Vote, Role, Elimination, EliminationReason, WinResult, PlayerProximity};
+use tonk_shared_lib::{Game, GameArchive, GameConfig, RoundArchive, Player, PlayerStats, GameStatus, Action, Time, Task, RoundResult, Vote, Elimination, EliminationReason, WinResult, PlayerProximity};
 use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::stream::{game_channel, StreamMessage};
+use tonk_shared_lib::events::{record_event, Actor, GameEventKind};
+use tonk_shared_lib::rng::new_seed;
+use tonk_shared_lib::win::{evaluate_win, WinSnapshot};
+use tonk_shared_lib::phase::{reduce, Effect, Event, GameSnapshot};
+use tonk_shared_lib::voting::{resolve_votes, VoteOutcome, VoteTally};
+use tonk_shared_lib::lobby::{everyone_ready, ready_key, start_game};
+use tonk_shared_lib::metrics;
+use tonk_shared_lib::presence;
 use std::borrow::BorrowMut;
-use std::collections::HashMap;
 use std::hash::Hash;
 use std::cmp::Eq;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::ops::Index;
+use std::time::{SystemTime, UNIX_EPOCH};
 use uuid::Uuid;
+use log::*;
 use crate::jobs::error::*;
 
 pub struct GameState {
-    redis: RedisHelper
+    store: Arc<dyn StateStore>
 }
 
 
 impl GameState {
-    pub fn new(redis: RedisHelper) -> Self {
-        Self { redis }
+    pub fn new(store: Arc<dyn StateStore>) -> Self {
+        Self { store }
+    }
+
+    // writes a game derived from the `from` snapshot, unless an admin command or another
+    // writer moved the game on since. The clock keeps ticking while we work, so its
+    // progress is carried over unless this write resets the timer itself.
+    // every write to a game is pushed to the clients watching it, and a change of
+    // phase is added to the game's event log
+    async fn save_game(&self, from: &Game, game: &Game) -> Result<(), JobError> {
+        let game_key = format!("game:{}", game.id);
+        let saved = self.store.update_key(&game_key, |current: Game| {
+            let same_round = current.time.as_ref().map(|t| t.round) == from.time.as_ref().map(|t| t.round);
+            if current.status != from.status || current.paused != from.paused || !same_round {
+                return None;
+            }
+            let mut next = game.clone();
+            if game.time == from.time {
+                next.time = current.time;
+            }
+            Some(next)
+        }).await?;
+        match saved {
+            Some(saved) => {
+                self.store.publish(&game_channel(&saved.id), &StreamMessage::Game(saved.clone())).await?;
+                if saved.status != from.status {
+                    self.record(&saved.id, GameEventKind::PhaseChanged {
+                        status: saved.status.clone(),
+                        time: saved.time.clone().unwrap(),
+                        win_result: saved.win_result.clone()
+                    }).await?;
+                }
+                Ok(())
+            }
+            None => Err(JobError::Conflict)
+        }
+    }
+
+    async fn record(&self, game_id: &str, kind: GameEventKind) -> Result<(), JobError> {
+        record_event(self.store.as_ref(), game_id, Actor::StateService, kind).await?;
+        Ok(())
+    }
+
+    async fn save_result(&self, game: &Game, result: &RoundResult) -> Result<(), JobError> {
+        let round = game.time.as_ref().unwrap().round;
+        let result_key = format!("result:{}:{}", game.id, round);
+        self.store.set_key(&result_key, result).await?;
+        self.store.publish(&game_channel(&game.id), &StreamMessage::RoundResult {
+            round,
+            result: result.clone()
+        }).await?;
+        self.record(&game.id, GameEventKind::RoundResolved {
+            round,
+            result: Box::new(result.clone())
+        }).await?;
+        self.archive_round(game, result).await?;
+        Ok(())
+    }
+
+    // the votes, tasks and actions are cleared between rounds so we keep a copy for the history
+    async fn archive_round(&self, game: &Game, result: &RoundResult) -> Result<(), JobError> {
+        let round = game.time.as_ref().unwrap().round;
+        let votes: Vec<Vote> = self.store.get_index(&format!("game:{}:votes", game.id)).await?;
+        let tasks: Vec<Task> = self.store.get_index(&format!("game:{}:tasks", game.id)).await?;
+        let actions: Vec<Action> = self.store.get_index(&format!("game:{}:actions", game.id)).await?;
+        let round_archive = RoundArchive {
+            round,
+            result: result.clone(),
+            votes,
+            tasks,
+            actions
+        };
+        let archive_key = format!("archive:{}:{}", game.id, round);
+        self.store.set_key(&archive_key, &round_archive).await?;
+        Ok(())
+    }
+
+    // the players as they were when the game ended, before a win cleaned any of them up
+    async fn snapshot_roster(&self, game: &Game) -> Result<(), JobError> {
+        let roster_key = format!("game:{}:roster", game.id);
+        let players: Vec<Player> = self.store.get_index(&roster_key).await?;
+        let final_roster_key = format!("game:{}:final_roster", game.id);
+        self.store.set_key(&final_roster_key, &players).await?;
+        Ok(())
+    }
+
+    async fn archive_game(&self, game: &Game) -> Result<(), JobError> {
+        let final_roster_key = format!("game:{}:final_roster", game.id);
+        let players: Vec<Player> = match self.store.get_key(&final_roster_key).await {
+            Ok(players) => players,
+            // games that ended before the roster was snapshotted
+            Err(RedisHelperError::MissingKey) => {
+                let roster_key = format!("game:{}:roster", game.id);
+                self.store.get_index(&roster_key).await?
+            }
+            Err(e) => return Err(e.into())
+        };
+
+        let mut rounds: Vec<RoundArchive> = Vec::new();
+        for i in 0..=game.time.as_ref().unwrap().round {
+            let archive_key = format!("archive:{}:{}", game.id, i);
+            match self.store.get_key(&archive_key).await {
+                Ok(round_archive) => rounds.push(round_archive),
+                Err(RedisHelperError::MissingKey) => {}
+                Err(e) => return Err(e.into())
+            }
+        }
+
+        let archived_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let archive = GameArchive {
+            game: game.clone(),
+            players,
+            rounds,
+            archived_at
+        };
+        let history_key = format!("history:{}", game.id);
+        self.store.set_key(&history_key, &archive).await?;
+        self.store.add_to_index("history:index", &history_key).await?;
+
+        self.record_stats(&archive).await?;
+        Ok(())
+    }
+
+    // the archive holds the players as they ended the game, so a bug cleaned out
+    // after a Perfection win is still counted with their role
+    async fn record_stats(&self, archive: &GameArchive) -> Result<(), JobError> {
+        for player in &archive.players {
+            let stats_key = format!("player:{}:stats", player.id);
+            let mut stats: PlayerStats = match self.store.get_key(&stats_key).await {
+                Ok(stats) => stats,
+                Err(RedisHelperError::MissingKey) => PlayerStats::new(&player.id),
+                Err(e) => return Err(e.into())
+            };
+            stats.record_game(archive);
+            self.store.set_key(&stats_key, &stats).await?;
+            self.store.add_to_index("stats:index", &stats_key).await?;
+        }
+        Ok(())
     }
 
     pub async fn run(&self) -> Result<(), JobError> {
-        // get the game
-        let game_result: Result<Game, _> = self.redis.get_key("game").await;
-        // if there is no game, we should create one
-        if let Err(RedisHelperError::MissingKey) = game_result {
-            return self.create_game().await;
-        } 
-
-        if game_result.is_ok() {
-            return self.update_logic(game_result.unwrap()).await;
-        } else {
-            return Err(game_result.unwrap_err().into())
+        // get every game in the registry
+        let games: Vec<Game> = self.store.get_index("game:index").await?;
+        // if there is no open lobby, we should create one
+        if games.iter().find(|g| g.status == GameStatus::Lobby).is_none() {
+            self.create_game().await?;
+        }
+
+        self.record_game_metrics(&games).await;
+
+        // each game is advanced on its own so one bad game doesn't stall the rest
+        for game in games {
+            let game_id = game.id.clone();
+            if let Err(e) = self.update_logic(game).await {
+                error!("game {}: {:?}", game_id, e);
+                metrics::get().job_errors.with_label_values(&["game_state"]).inc();
+            }
+        }
+        Ok(())
+    }
+
+    // The gauges are rebuilt from scratch each tick so games that have ended
+    // stop being reported. Games count by phase, the rest is per running game.
+    async fn record_game_metrics(&self, games: &[Game]) {
+        const PHASES: [GameStatus; 7] = [
+            GameStatus::Null, GameStatus::Lobby, GameStatus::Tasks, GameStatus::TaskResult,
+            GameStatus::Vote, GameStatus::VoteResult, GameStatus::End
+        ];
+        let metrics = metrics::get();
+        for gauge in [&metrics.games, &metrics.game_players, &metrics.game_round, &metrics.game_phase] {
+            gauge.reset();
+        }
+        for phase in PHASES.iter() {
+            let count = games.iter().filter(|g| g.status == *phase).count();
+            metrics.games.with_label_values(&[&format!("{:?}", phase)]).set(count as i64);
+        }
+        for game in games.iter().filter(|g| g.status != GameStatus::End) {
+            let players: Vec<Player> = match self.store.get_index(&format!("game:{}:player_index", game.id)).await {
+                Ok(players) => players,
+                Err(e) => {
+                    warn!("game {}: couldn't count players for metrics: {:?}", game.id, e);
+                    continue;
+                }
+            };
+            let active = players.iter().filter(|p| p.eliminated.is_none()).count();
+            metrics.game_players.with_label_values(&[&game.id]).set(active as i64);
+            if let Some(time) = &game.time {
+                metrics.game_round.with_label_values(&[&game.id]).set(time.round as i64);
+            }
+            for phase in PHASES.iter() {
+                let current = if game.status == *phase { 1 } else { 0 };
+                metrics.game_phase.with_label_values(&[&game.id, &format!("{:?}", phase)]).set(current);
+            }
         }
     }
 
@@ -41,6 +231,11 @@ impl GameState {
             id: Uuid::new_v4().as_simple().to_string(),
             status: GameStatus::Lobby,
             demo_play: false,
+            paused: false,
+            config: GameConfig::default(),
+            seed: new_seed(),
+            runoff: None,
+            host: None,
             corrupted_players: None,
             eliminated_players: None,
             time: Some(Time {
@@ -49,40 +244,54 @@ impl GameState {
             }),
             win_result: None
         };
-        let _ = self.redis.set_key("game", &game).await?;
+        let game_key = format!("game:{}", game.id);
+        self.store.set_key(&game_key, &game).await?;
+        self.store.publish(&game_channel(&game.id), &StreamMessage::Game(game.clone())).await?;
+        self.store.add_to_index("game:index", &game_key).await?;
+        self.record(&game.id, GameEventKind::Created { game: Box::new(game.clone()) }).await?;
         Ok(())
     }
 
-    async fn set_vote_result(&self, game: &Game) -> Result<Game, JobError> {
+    // Counts the round's ballots. Returns true when the vote ended in a tie that
+    // has to be voted on again, nothing is resolved in that case.
+    async fn set_vote_result(&self, game: &Game) -> Result<(Game, bool), JobError> {
         let mut vote_result = RoundResult {
             round_type: GameStatus::Vote,
             eliminated: None,
-            tasks_completed: None
+            tasks_completed: None,
+            tally: None
         };
-        let votes: Vec<Vote> = self.redis.get_index("game:votes").await.map_err(|_| JobError::RedisError)?;
+        let round = game.time.as_ref().unwrap().round;
+        let votes_index_key = format!("game:{}:votes", game.id);
+        let votes: Vec<Vote> = self.store.get_index(&votes_index_key).await?;
         let mut new_corrupted: Vec<Player> = Vec::new();
 
         // count the votes
-        let vote_counts = votes.iter().fold(HashMap::new(), |mut acc, vote| {
-            *acc.entry(vote.candidate.clone()).or_insert(0) += 1;
-            acc
-        });
-
-        let mut max_candidate: Option<Player> = None; 
-        let mut max_count = 0;
-        for (candidate, count) in vote_counts.iter() {
-            if *count > max_count {
-                max_count = *count;
-                max_candidate = Some(candidate.clone());
+        let tally = VoteTally::count(&votes, game.config.reveal_ballots);
+        let max_candidate = match resolve_votes(&tally, &game.config, game.seed, round, game.runoff.is_some()) {
+            // the tally is public, the ballots still carry the candidate's role
+            VoteOutcome::VotedOut(candidate) => votes.iter()
+                .filter_map(|v| v.candidate.as_ref())
+                .find(|c| c.id == candidate.id)
+                .cloned(),
+            VoteOutcome::NoElimination => None,
+            VoteOutcome::Runoff(candidates) => {
+                self.call_runoff(game, &candidates).await?;
+                let mut new_game = game.clone();
+                new_game.runoff = Some(candidates.iter().map(|c| c.id.clone()).collect());
+                return Ok((new_game, true));
             }
-        }
+        };
+        vote_result.tally = Some(tally);
 
         // check for inactive players
         let player_index_key = format!("game:{}:player_index", game.id);
-        let players: Vec<Player> = self.redis.get_index(&player_index_key).await.map_err(|e| JobError::RedisError )?;
+        let players: Vec<Player> = self.store.get_index(&player_index_key).await?;
 
+        let excused = self.excused_players(game, &players).await?;
         let inactive_players: Vec<Elimination> = players.iter().filter(|p| {
             p.used_action.is_some() && *p.used_action.as_ref().unwrap_or(&tonk_shared_lib::ActionStatus::Unused) != tonk_shared_lib::ActionStatus::Voted
+                && !excused.contains(&p.id)
         }).map(|p| {
             Elimination {
                 player: p.clone(),
@@ -96,7 +305,7 @@ impl GameState {
         if max_candidate.is_some() {
             max_candidate_id = max_candidate.as_ref().unwrap().id.clone();
             // println!("max_candidate: {:?}", max_candidate.as_ref().unwrap().role.as_ref().unwrap());
-            if *max_candidate.as_ref().unwrap().role.as_ref().unwrap() == Role::Bugged {
+            if max_candidate.as_ref().unwrap().role.as_ref().unwrap().is_bug() {
                 new_corrupted.push(max_candidate.as_ref().unwrap().clone());
             }
             eliminated_players.push(Elimination {
@@ -107,7 +316,7 @@ impl GameState {
 
         for inactive_player in inactive_players {
             if inactive_player.player.id != max_candidate_id && !game.demo_play {
-                if *inactive_player.player.role.as_ref().unwrap() == Role::Bugged {
+                if inactive_player.player.role.as_ref().unwrap().is_bug() {
                     new_corrupted.push(inactive_player.player.clone())
                 }
                 eliminated_players.push(inactive_player);
@@ -124,13 +333,56 @@ impl GameState {
             } 
             new_game.corrupted_players = Some(new_corrupted);
             // println!("New game object {:?}", new_game);
-            let _ = self.redis.set_key("game", &new_game).await?;
+            self.save_game(game, &new_game).await?;
         }
 
-        let result_key = format!("result:{}:{}", game.id, game.time.as_ref().unwrap().round);
-        let _ = self.redis.set_key(&result_key, &vote_result).await.map_err(|e| JobError::RedisError)?;
+        self.save_result(game, &vote_result).await?;
 
-        Ok(new_game)
+        new_game.runoff = None;
+        Ok((new_game, false))
+    }
+
+    // the tied candidates are voted on again, everyone gets a fresh ballot
+    async fn call_runoff(&self, game: &Game, candidates: &[Player]) -> Result<(), JobError> {
+        let votes_index_key = format!("game:{}:votes", game.id);
+        let vote_keys: Vec<String> = self.store.get_index_keys(&votes_index_key).await?;
+        for key in vote_keys {
+            self.store.clear_key(&key).await?;
+        }
+        self.store.clear_index(&votes_index_key).await?;
+
+        let player_index_key = format!("game:{}:player_index", game.id);
+        let players: Vec<Player> = self.store.get_index(&player_index_key).await?;
+        for player in players {
+            let player_key = format!("player:{}", player.id);
+            self.store.update_key(&player_key, |mut reset_player: Player| {
+                reset_player.used_action = Some(tonk_shared_lib::ActionStatus::Unused);
+                Some(reset_player)
+            }).await?;
+        }
+        self.record(&game.id, GameEventKind::RunoffCalled {
+            round: game.time.as_ref().unwrap().round,
+            candidates: candidates.iter().map(|c| c.id.clone()).collect()
+        }).await?;
+        Ok(())
+    }
+
+    // players whose phone dropped out are given the game's grace period to come back
+    // before missing an action gets them eliminated
+    async fn excused_players(&self, game: &Game, players: &[Player]) -> Result<HashSet<String>, JobError> {
+        let mut excused = HashSet::new();
+        if game.config.grace_period.is_none() {
+            return Ok(excused);
+        }
+        let now = presence::now();
+        for player in players {
+            let last_seen = presence::last_seen(&*self.store, &player.id).await?;
+            if presence::is_excused(&game.config, last_seen, now) {
+                info!("game {}: sparing disconnected player {}", game.id, player.id);
+                excused.insert(player.id.clone());
+            }
+        }
+        Ok(excused)
     }
 
     async fn set_task_result(&self, game: &Game) -> Result<Game, JobError> {
@@ -138,16 +390,18 @@ impl GameState {
             round_type: GameStatus::TaskResult,
             eliminated: None,
             tasks_completed: None,
+            tally: None,
         };
         let mut new_corrupted: Vec<Player> = Vec::new();
 
         let mut eliminations: HashSet<String> = HashSet::new();
         // check for inactive players
         let player_index_key = format!("game:{}:player_index", game.id);
-        let players: Vec<Player> = self.redis.get_index(&player_index_key).await.map_err(|e| JobError::RedisError )?;
+        let players: Vec<Player> = self.store.get_index(&player_index_key).await?;
 
         // we need to count all the players eliminated
-        let actions: Vec<Action> = self.redis.get_index("game:actions").await.map_err(|e| JobError::RedisError)?;
+        let actions_index_key = format!("game:{}:actions", game.id);
+        let actions: Vec<Action> = self.store.get_index(&actions_index_key).await?;
         let mut eliminated_players: Vec<Elimination> = actions.iter().filter(|a| {
             a.interrupted_task
         }).map(|a| {
@@ -165,7 +419,8 @@ impl GameState {
             .collect();
 
         // and we need to count all the tasks completed
-        let tasks: Vec<Task> = self.redis.get_index("game:tasks").await.map_err(|e| JobError::RedisError)?;
+        let tasks_index_key = format!("game:{}:tasks", game.id);
+        let tasks: Vec<Task> = self.store.get_index(&tasks_index_key).await?;
         let filtered_tasks = tasks
             .iter()
             .filter(|t| {
@@ -184,8 +439,10 @@ impl GameState {
             }
         }).collect();
 
+        let excused = self.excused_players(game, &players).await?;
         let inactive_players: Vec<Elimination> = players.iter().filter(|p| {
             p.used_action.is_some() && *p.used_action.as_ref().unwrap_or(&tonk_shared_lib::ActionStatus::Unused) != tonk_shared_lib::ActionStatus::TaskComplete
+                && !excused.contains(&p.id)
         }).map(|p| {
             Elimination {
                 player: p.clone(),
@@ -195,7 +452,7 @@ impl GameState {
 
         for inactive_player in inactive_players {
             if !eliminations.contains(&inactive_player.player.id) && !game.demo_play {
-                if *inactive_player.player.role.as_ref().unwrap() == Role::Bugged {
+                if inactive_player.player.role.as_ref().unwrap().is_bug() {
                     new_corrupted.push(inactive_player.player.clone())
                 }
                 eliminated_players.push(inactive_player);
@@ -205,8 +462,7 @@ impl GameState {
         task_result.eliminated = Some(eliminated_players);
         task_result.tasks_completed = Some(filtered_tasks);
         
-        let result_key = format!("result:{}:{}", game.id, game.time.as_ref().unwrap().round);
-        let _ = self.redis.set_key(&result_key, &task_result).await.map_err(|e| JobError::RedisError)?;
+        self.save_result(game, &task_result).await?;
 
         let mut new_game = game.clone();
         if new_corrupted.len() > 0 {
@@ -214,7 +470,7 @@ impl GameState {
                 new_corrupted.append(game.clone().corrupted_players.as_mut().unwrap());
             } 
             new_game.corrupted_players = Some(new_corrupted);
-            let _ = self.redis.set_key("game", &new_game).await?;
+            self.save_game(game, &new_game).await?;
         }
 
         Ok(new_game)
@@ -223,79 +479,101 @@ impl GameState {
     async fn reset_round(&self, game: &Game) -> Result<(), JobError> {
         // remove players who were eliminated in the prior result
         let result_key = format!("result:{}:{}", game.id, game.time.as_ref().unwrap().round); 
-        let prior_result: RoundResult = self.redis.get_key(&result_key).await?;
+        let prior_result: RoundResult = self.store.get_key(&result_key).await?;
 
         let player_index_key = format!("game:{}:player_index", game.id);
-        let players: Vec<Player> = self.redis.get_index(&player_index_key).await?;
+        let players: Vec<Player> = self.store.get_index(&player_index_key).await?;
 
         // println!("Calling reset round!");
 
         for player in players {
                 let player_key = format!("player:{}", player.id);
-                let mut reset_player = player.clone();
-                reset_player.used_action = Some(tonk_shared_lib::ActionStatus::Unused); 
 
                 // println!("resetting used_action for player {}!", player_key);
 
-                self.redis.set_key(&player_key, &reset_player).await?;
+                // a late request from the player may still be writing to them
+                self.store.update_key(&player_key, |mut reset_player: Player| {
+                    reset_player.used_action = Some(tonk_shared_lib::ActionStatus::Unused); 
+                    Some(reset_player)
+                }).await?;
         }
+        self.record(&game.id, GameEventKind::RoundReset {
+            round: game.time.as_ref().unwrap().round
+        }).await?;
 
         if prior_result.eliminated.is_some() {
             for elimination in prior_result.eliminated.as_ref().unwrap() {
                 let eliminated_player = elimination.player.clone();
                 let player_key = format!("player:{}", eliminated_player.id);
-                let mut player: Player = self.redis.get_key(&player_key).await?;
 
-                self.redis.remove_from_index(&player_index_key, &player_key).await?;
+                self.store.remove_from_index(&player_index_key, &player_key).await?;
 
-                player.eliminated = Some(true);
-                self.redis.set_key(&player_key, &player).await?;
+                self.store.update_key(&player_key, |mut player: Player| {
+                    player.eliminated = Some(true);
+                    Some(player)
+                }).await?;
+                self.record(&game.id, GameEventKind::Eliminated {
+                    elimination: Box::new(elimination.clone())
+                }).await?;
             }
         }
 
+        let actions_index_key = format!("game:{}:actions", game.id);
+        let tasks_index_key = format!("game:{}:tasks", game.id);
+        let votes_index_key = format!("game:{}:votes", game.id);
+        let abilities_index_key = format!("game:{}:abilities", game.id);
+
         if game.status == GameStatus::Tasks {
-            let action_keys: Vec<String> = self.redis.get_index_keys("game:actions").await?;
-            let task_keys: Vec<String> = self.redis.get_index_keys("game:tasks").await?;
+            let action_keys: Vec<String> = self.store.get_index_keys(&actions_index_key).await?;
+            let task_keys: Vec<String> = self.store.get_index_keys(&tasks_index_key).await?;
+            let ability_keys: Vec<String> = self.store.get_index_keys(&abilities_index_key).await?;
             for key in action_keys {
-                self.redis.clear_key(&key).await?;
+                self.store.clear_key(&key).await?;
             }
             for key in task_keys {
-                self.redis.clear_key(&key).await?;
+                self.store.clear_key(&key).await?;
+            }
+            for key in ability_keys {
+                self.store.clear_key(&key).await?;
             }
         }
 
         if game.status == GameStatus::VoteResult {
-            let vote_keys: Vec<String> = self.redis.get_index_keys("game:votes").await?;
+            let vote_keys: Vec<String> = self.store.get_index_keys(&votes_index_key).await?;
             for key in vote_keys {
-                self.redis.clear_key(&key).await?;
+                self.store.clear_key(&key).await?;
             }
         }
 
 
 
-        // clear out actions, tasks, votes
-        self.redis.clear_index("game:actions").await?;
-        self.redis.clear_index("game:tasks").await?;
-        self.redis.clear_index("game:votes").await?;
+        // clear out actions, tasks, votes, abilities
+        self.store.clear_index(&actions_index_key).await?;
+        self.store.clear_index(&tasks_index_key).await?;
+        self.store.clear_index(&votes_index_key).await?;
+        self.store.clear_index(&abilities_index_key).await?;
 
         Ok(())
     }
 
     async fn check_all_votes_in(&self, game: &Game) -> Result<bool, JobError> {
         let player_index_key = format!("game:{}:player_index", game.id);
-        let player_keys = self.redis.get_index_keys(&player_index_key).await?;
+        let player_keys = self.store.get_index_keys(&player_index_key).await?;
 
-        let vote_keys = self.redis.get_index_keys("game:votes").await?;
+        let votes_index_key = format!("game:{}:votes", game.id);
+        let vote_keys = self.store.get_index_keys(&votes_index_key).await?;
 
         Ok(player_keys.len() == vote_keys.len())
     }
 
     async fn check_all_tasks_in(&self, game: &Game) -> Result<bool, JobError> {
         let player_index_key = format!("game:{}:player_index", game.id);
-        let players: Vec<Player> = self.redis.get_index(&player_index_key).await?;
+        let players: Vec<Player> = self.store.get_index(&player_index_key).await?;
 
-        let tasks = self.redis.get_index_keys("game:tasks").await?;
-        let actions = self.redis.get_index_keys("game:actions").await?;
+        let tasks_index_key = format!("game:{}:tasks", game.id);
+        let actions_index_key = format!("game:{}:actions", game.id);
+        let tasks = self.store.get_index_keys(&tasks_index_key).await?;
+        let actions = self.store.get_index_keys(&actions_index_key).await?;
 
         if (tasks.len() + actions.len()) == players.len() {
             let all_done = players.iter().fold(true, |acc, e| {
@@ -306,23 +584,27 @@ impl GameState {
         return Ok(false);
     }
 
-    async fn clear_player_state(&self) -> Result<(), JobError> {
-        let players: Vec<Player> = self.redis.get_index("player:index").await?;
+    async fn clear_player_state(&self, game: &Game) -> Result<(), JobError> {
+        // the roster keeps every player who joined, including the eliminated ones
+        let roster_key = format!("game:{}:roster", game.id);
+        let players: Vec<Player> = self.store.get_index(&roster_key).await?;
         // println!("clearing players {:?}", players);
         for player in players {
-            let clean_player = Player {
-                id: player.id.clone(),
-                mobile_unit_id: player.mobile_unit_id.clone(),
-                display_name: player.display_name.clone(),
-                secret_key: None,
-                last_round_action: None,
-                eliminated: None,
-                proximity: None,
-                role: None,
-                used_action: Some(tonk_shared_lib::ActionStatus::Unused),
-            };
             let player_key = format!("player:{}", player.id);
-            let _ = self.redis.set_key(&player_key, &clean_player).await?;
+            // built from the latest copy so a name change made during the game is kept
+            let _ = self.store.update_key(&player_key, |player: Player| {
+                Some(Player {
+                    id: player.id.clone(),
+                    mobile_unit_id: player.mobile_unit_id.clone(),
+                    display_name: player.display_name.clone(),
+                    secret_key: None,
+                    last_round_action: None,
+                    eliminated: None,
+                    proximity: None,
+                    role: None,
+                    used_action: Some(tonk_shared_lib::ActionStatus::Unused),
+                })
+            }).await?;
 
             let proximity_key = format!("player:{}:proximity", player.id);
             let clean_proximity = PlayerProximity {
@@ -331,14 +613,17 @@ impl GameState {
                 nearby_players: None,
                 immune: None
             };
-            let _ = self.redis.set_key(&proximity_key, &clean_proximity).await?;
+            let _ = self.store.set_key(&proximity_key, &clean_proximity).await?;
+
+            let player_game_key = format!("player:{}:game", player.id);
+            self.store.clear_key(&player_game_key).await?;
         }
         Ok(())
     }
 
     async fn update_eliminated(&self, game: &Game) -> Result<Game, JobError> {
             let result_key = format!("result:{}:{}", game.id, game.time.as_ref().unwrap().round);
-            let result: RoundResult = self.redis.get_key(&result_key).await?;
+            let result: RoundResult = self.store.get_key(&result_key).await?;
 
             let mut new_elimination = game.eliminated_players.as_ref().unwrap_or(&Vec::new()).clone();
             let mut new_game = game.clone();
@@ -356,330 +641,160 @@ impl GameState {
 
             if found {
                 new_game.eliminated_players = Some(new_elimination);
-                self.redis.set_key("game", &new_game).await?;
+                self.save_game(game, &new_game).await?;
             }
 
             Ok(new_game)
     } 
 
-    async fn reset_to_new_game(&self, game: &Game) -> Result<(), JobError> {
+    async fn teardown_game(&self, game: &Game) -> Result<(), JobError> {
+
+        // keep a record of the match before any of its state is cleared
+        self.archive_game(game).await?;
 
         // clear out all individual results 
-        for i in 0..game.time.as_ref().unwrap().round {
+        for i in 0..=game.time.as_ref().unwrap().round {
             let result_key = format!("result:{}:{}", game.id, i);
-            self.redis.clear_key(&result_key).await?;
+            self.store.clear_key(&result_key).await?;
+            let archive_key = format!("archive:{}:{}", game.id, i);
+            self.store.clear_key(&archive_key).await?;
         }
 
         // clear the state of all players
-        self.clear_player_state().await?;
+        self.clear_player_state(game).await?;
 
         // remove all final players
         let game_player_index = format!("game:{}:player_index", game.id);
-        self.redis.clear_index(&game_player_index).await?;
+        self.store.clear_index(&game_player_index).await?;
+        let roster_key = format!("game:{}:roster", game.id);
+        self.store.clear_index(&roster_key).await?;
+        let final_roster_key = format!("game:{}:final_roster", game.id);
+        self.store.clear_key(&final_roster_key).await?;
 
-        // create a new game
-        self.create_game().await?;
+        // drop the game from the registry, a fresh lobby is opened on the next tick
+        let game_key = format!("game:{}", game.id);
+        self.store.remove_from_index("game:index", &game_key).await?;
+        self.store.clear_key(&game_key).await?;
 
         Ok(())
     }
 
     async fn check_end_game_condition(&self, game: &Game) -> Result<WinResult, JobError> {
         let result_key = format!("result:{}:{}", game.id, game.time.as_ref().unwrap().round);
-        let result: RoundResult = self.redis.get_key(&result_key).await?;
+        let result: RoundResult = self.store.get_key(&result_key).await?;
         let game_player_index = format!("game:{}:player_index", game.id);
-        let players: Vec<Player> = self.redis.get_index(&game_player_index).await?;
-
-        // all tasks were completed
-        if result.round_type == GameStatus::Tasks {
-            let tasks: Vec<Task> = self.redis.get_index("game:tasks").await.map_err(|e| JobError::RedisError)?;
-
-            // we disable this for games of 2 players to allow for a limited setup demo 
-            if tasks.len() == result.tasks_completed.as_ref().unwrap().len() && !game.demo_play {
-                
-                // find all the saboteurs
-                for player in players {
-                    // clean them in this very MESSY way ;__;
-                    if *player.role.as_ref().unwrap() == Role::Bugged {
-                        let player_key = format!("player:{}", player.id);
-                        let _ = self.redis.remove_from_index(&game_player_index, &player_key).await?;
-
-                        let clean_player = Player {
-                            id: player.id.clone(),
-                            mobile_unit_id: player.mobile_unit_id.clone(),
-                            display_name: player.display_name.clone(),
-                            secret_key: None,
-                            last_round_action: None,
-                            eliminated: None,
-                            proximity: None,
-                            role: None,
-                            used_action: None,
-                        };
-                        let _ = self.redis.set_key(&player_key, &clean_player).await?;
-
-                        let proximity_key = format!("player:{}:proximity", player.id);
-                        let clean_proximity = PlayerProximity {
-                            location: None,
-                            nearby_buildings: None,
-                            nearby_players: None,
-                            immune: None
-                        };
-                        let _ = self.redis.set_key(&proximity_key, &clean_proximity).await?;
-                    }
-                }
-                return Ok(WinResult::Perfection);
-            }
-        }
-
-        
-        let eliminated_ids: HashSet<String> = result
-            .eliminated
-            .as_ref()
-            .unwrap_or(&Vec::new())
-            .iter()
-            .map(|elimination| elimination.player.id.clone())
-            .collect();
-
-        let remaining_players: Vec<&Player> = players
-            .iter()
-            .filter(|&p| !eliminated_ids.contains(&p.id))
-            .collect();
-
-        // NEW win condition, more than 50% of players are bugs
-        let number_of_bugs = remaining_players.iter().fold(0, |acc, e| {
-            if *e.role.as_ref().unwrap() == Role::Bugged {
-                acc + 1
-            } else {
-                acc
-            }
-        });
-
-        if number_of_bugs as f64 >= (remaining_players.len() as f64 * 0.5) && !game.demo_play {
-            return Ok(WinResult::Thuggery);
-        }
-
-        // everyone is killed, only saboteurs remain
-        let no_villagers_remain = remaining_players.iter().fold(true, |acc, e| {
-            if *e.role.as_ref().unwrap() == Role::Normal {
-                false
-            } else {
-                acc
-            }
-        });
-        // all saboteurs are voted off
-        let no_saboteurs_remain = remaining_players.iter().fold(true, |acc, e| {
-            if *e.role.as_ref().unwrap() == Role::Bugged {
-                false
-            } else {
-                acc
-            }
-        });
+        let players: Vec<Player> = self.store.get_index(&game_player_index).await?;
+        let tasks_index_key = format!("game:{}:tasks", game.id);
+        let tasks_assigned = self.store.get_index_keys(&tasks_index_key).await?.len();
+
+        let snapshot = WinSnapshot {
+            round_type: result.round_type,
+            tasks_assigned,
+            tasks_completed: result.tasks_completed.as_ref().map(|t| t.len()).unwrap_or(0),
+            players,
+            eliminated: result.eliminated.unwrap_or_default(),
+            demo_play: game.demo_play,
+            thuggery_threshold: game.config.thuggery_threshold,
+        };
+        Ok(evaluate_win(&game.config.win_rules, &snapshot))
+    }
 
-        if no_saboteurs_remain && no_villagers_remain {
-            return Ok(WinResult::Armageddon);
+    // the bugs are cleaned out of the game when the crew wins by finishing every task
+    async fn clean_up_win(&self, game: &Game, win_result: &WinResult) -> Result<(), JobError> {
+        if *win_result != WinResult::Perfection {
+            return Ok(());
         }
+        let game_player_index = format!("game:{}:player_index", game.id);
+        let players: Vec<Player> = self.store.get_index(&game_player_index).await?;
+        for player in players.iter().filter(|p| p.role.as_ref().map(|r| r.is_bug()).unwrap_or(false)) {
+            let player_key = format!("player:{}", player.id);
+            self.store.remove_from_index(&game_player_index, &player_key).await?;
 
-        if no_saboteurs_remain {
-            return Ok(WinResult::Democracy);
-        }
+            let clean_player = Player {
+                id: player.id.clone(),
+                mobile_unit_id: player.mobile_unit_id.clone(),
+                display_name: player.display_name.clone(),
+                secret_key: None,
+                last_round_action: None,
+                eliminated: None,
+                proximity: None,
+                role: None,
+                used_action: None,
+            };
+            self.store.set_key(&player_key, &clean_player).await?;
 
-        if no_villagers_remain {
-            return Ok(WinResult::Thuggery);
+            let proximity_key = format!("player:{}:proximity", player.id);
+            let clean_proximity = PlayerProximity {
+                location: None,
+                nearby_buildings: None,
+                nearby_players: None,
+                immune: None
+            };
+            self.store.set_key(&proximity_key, &clean_proximity).await?;
         }
-
-        Ok(WinResult::Null)
+        Ok(())
     }
 
     async fn update_logic(&self, game: Game) -> Result<(), JobError> {
-        match game.status {
-        // if the game is in the Lobby, we do nothing
-            GameStatus::Lobby => {
-                // we are just waiting around for someone to start the game
-                Ok(())
-            }
-            GameStatus::Tasks => {
-                // if the game is in the task phase, we move the game into task result phase at the right time
-                // we need to update the summary for that round
-                let time = game.time.as_ref().unwrap();
-                let all_tasks_in = self.check_all_tasks_in(&game).await?;
-                if all_tasks_in && time.timer > 0 {
-                    let mut ngame = game.clone();
-                    ngame.time = Some(Time {
-                        round: time.round,
-                        timer: 0,
-                    });
-                    let _ = self.redis.set_key("game", &ngame).await?;
-                    return Ok(());
-                }
+        let all_in = match game.status {
+            GameStatus::Lobby => everyone_ready(&*self.store, &game, None).await?,
+            GameStatus::Tasks => self.check_all_tasks_in(&game).await?,
+            GameStatus::Vote => self.check_all_votes_in(&game).await?,
+            _ => false
+        };
+        let (snapshot, effects) = reduce(GameSnapshot::from_game(&game), Event::Tick { all_in });
+        self.run_effects(&game, snapshot, effects).await
+    }
 
-                if time.timer <= 0 {
-                    let mut new_game = self.set_task_result(&game).await?;
-                    let is_end = self.check_end_game_condition(&new_game).await?;
-                    new_game = self.update_eliminated(&new_game).await?;
-                    self.reset_round(&new_game).await?;
-                    if is_end != WinResult::Null {
-                        let next_game = Game {
-                            id: game.id.clone(),
-                            status: GameStatus::End,
-                            corrupted_players: new_game.corrupted_players,
-                            eliminated_players: new_game.eliminated_players,
-                            demo_play: game.demo_play,
-                            time: Some(Time {
-                                timer: 30,
-                                round: time.round 
-                            }),
-                            win_result: Some(is_end)
-                        };
-                        let _ = self.redis.set_key("game", &next_game).await?;
-                    } else {
-                        let next_game = Game {
-                            id: game.id,
-                            status: GameStatus::Vote,
-                            corrupted_players: new_game.corrupted_players,
-                            eliminated_players: new_game.eliminated_players,
-                            demo_play: game.demo_play,
-                            time: Some(Time {
-                                timer: 90,
-                                round: time.round + 1
-                            }),
-                            win_result: None
-                        };
-                        let _ = self.redis.set_key("game", &next_game).await?;
+    // Carries out what the phase machine decided. The game is only written back
+    // with the new phase once every effect before SaveGame has run.
+    async fn run_effects(&self, game: &Game, mut snapshot: GameSnapshot, effects: Vec<Effect>) -> Result<(), JobError> {
+        let mut new_game = game.clone();
+        let mut pending: VecDeque<Effect> = effects.into();
+        while let Some(effect) = pending.pop_front() {
+            match effect {
+                Effect::StartGame => {
+                    // a lobby that can't start is left for the players to sort out
+                    if let Err(e) = start_game(&*self.store, new_game.clone(), false, Actor::StateService).await {
+                        warn!("game {} could not auto-start: {}", new_game.id, e);
+                        self.store.clear_index(&ready_key(&new_game.id)).await?;
                     }
                 }
-                Ok(())
-            }
-            // THIS TaskResult round is now obselete, TODO: delete
-            GameStatus::TaskResult => {
-                // if the game is in task result phase, we move game into vote phase at the right time
-                // unless the game is over, then we move to the end phase
-                let time = game.time.as_ref().unwrap();
-
-                if time.timer == 0 {
-                    // CHECK END CONDITIONS
-                    let is_end = self.check_end_game_condition(&game).await?;
-                    self.reset_round(&game).await?;
-                    if is_end != WinResult::Null {
-                        let next_game = Game {
-                            id: game.id.clone(),
-                            status: GameStatus::End,
-                            demo_play: game.demo_play,
-                            corrupted_players: game.corrupted_players,
-                            eliminated_players: game.eliminated_players,
-                            time: Some(Time {
-                                timer: 30,
-                                round: time.round 
-                            }),
-                            win_result: Some(is_end)
-                        };
-                        let _ = self.redis.set_key("game", &next_game).await?;
-                    } else {
-                        self.reset_round(&game).await?;
-                        let next_game = Game {
-                            id: game.id.clone(),
-                            status: GameStatus::Vote,
-                            demo_play: game.demo_play,
-                            corrupted_players: game.corrupted_players,
-                            eliminated_players: game.eliminated_players,
-                            time: Some(Time {
-                                timer: 90,
-                                round: time.round + 1
-                            }),
-                            win_result: None
-                        };
-                        let _ = self.redis.set_key("game", &next_game).await?;
-                    }
+                Effect::ResolveTasks => {
+                    new_game = self.set_task_result(&new_game).await?;
                 }
-                Ok(())
-            }
-            GameStatus::Vote => {
-                // if the game is in the vote phase, we move the game into the vote result phase at the right time
-                let time = game.time.as_ref().unwrap();
-                let all_votes_in = self.check_all_votes_in(&game).await?;
-                if all_votes_in && time.timer > 0 {
-                    let mut ngame = game.clone();
-                    ngame.time = Some(Time {
-                        round: time.round,
-                        timer: 0,
-                    });
-                    let _ = self.redis.set_key("game", &ngame).await?;
-                    return Ok(());
+                Effect::ResolveVotes => {
+                    let (counted, runoff) = self.set_vote_result(&new_game).await?;
+                    new_game = counted;
+                    let (next, more) = reduce(snapshot, Event::VotesCounted { runoff });
+                    snapshot = next;
+                    pending.extend(more);
                 }
-
-                if time.timer <= 0 {
-                    let new_game = self.set_vote_result(&game).await?;
-                    let next_game = Game {
-                        id: game.id,
-                        status: GameStatus::VoteResult,
-                        corrupted_players: new_game.corrupted_players,
-                        eliminated_players: new_game.eliminated_players,
-                        demo_play: game.demo_play,
-                        time: Some(Time {
-                            timer: 30,
-                            round: time.round
-                        }),
-                        win_result: None
-                    };
-                    let _ = self.redis.set_key("game", &next_game).await?;
+                Effect::CheckWin => {
+                    let win_result = self.check_end_game_condition(&new_game).await?;
+                    let (next, more) = reduce(snapshot, Event::Resolved { win_result });
+                    snapshot = next;
+                    pending.extend(more);
                 }
-                Ok(())
-            }
-            GameStatus::VoteResult => {
-                // if the game is in the vote result phase, we move the game into the task phase at the right time
-                // unless the game is over, then we move to the end phase
-                
-                // CHECK END CONDITIONS
-                let time = game.time.as_ref().unwrap();
-
-                if time.timer == 0 {
-                    let is_end = self.check_end_game_condition(&game).await?;
-                    self.reset_round(&game.clone()).await?;
-                    let new_game = self.update_eliminated(&game).await?;
-                    if is_end != WinResult::Null {
-                        let next_game = Game {
-                            id: new_game.id.clone(),
-                            status: GameStatus::End,
-                            demo_play: new_game.demo_play,
-                            corrupted_players: new_game.corrupted_players,
-                            eliminated_players: new_game.eliminated_players,
-                            time: Some(Time {
-                                timer: 30,
-                                round: time.round
-                            }),
-                            win_result: Some(is_end)
-                        };
-                        let _ = self.redis.set_key("game", &next_game).await?;
-                    } else {
-                        let next_game = Game {
-                            id: new_game.id.clone(),
-                            status: GameStatus::Tasks,
-                            demo_play: new_game.demo_play,
-                            corrupted_players: new_game.corrupted_players,
-                            eliminated_players: new_game.eliminated_players,
-                            time: Some(Time {
-                                timer: 180,
-                                round: time.round + 1
-                            }),
-                            win_result: None
-                        };
-                        self.redis.set_key("game", &next_game).await?;
-                    }
+                Effect::CleanUpWin(win_result) => {
+                    self.snapshot_roster(&new_game).await?;
+                    self.clean_up_win(&new_game, &win_result).await?;
                 }
-
-                Ok(())
-            }
-            GameStatus::End => {
-                // if the game is at the end, and timer is up, we should reset all the state and create a new game 
-                let time = game.time.as_ref().unwrap();
-                if time.timer == 0 {
-                    self.reset_to_new_game(&game).await?;
+                Effect::UpdateEliminated => {
+                    new_game = self.update_eliminated(&new_game).await?;
+                }
+                Effect::ResetRound => {
+                    self.reset_round(&new_game).await?;
+                }
+                Effect::SaveGame => {
+                    snapshot.apply_to(&mut new_game);
+                    self.save_game(game, &new_game).await?;
+                }
+                Effect::Teardown => {
+                    self.teardown_game(&new_game).await?;
                 }
-                Ok(())
-            }
-            GameStatus::Null => {
-                // Something has gone wrong here?
-                Ok(())
             }
-
         }
+        Ok(())
     }
 }
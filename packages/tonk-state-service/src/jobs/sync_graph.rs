@@ -1,3 +1,4 @@
+use std::sync::Arc;
 use std::collections::HashMap;
 use std::ops::{RangeBounds, Index};
 use log::*;
@@ -5,10 +6,12 @@ use redis::{Commands, ToRedisArgs, RedisResult};
 use reqwest;
 use gql_client;
 use serde::{Deserialize,Serialize};
-use std::env;
 
 use tonk_shared_lib::{self, PlayerProximity};
 use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::metrics;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::stream::{player_channel, StreamMessage};
 use super::error::JobError;
 
 #[derive(Deserialize, Debug)]
@@ -56,7 +59,9 @@ pub struct Tile {
 
 pub struct SyncGraph {
     client: reqwest::Client,
-    redis: RedisHelper
+    store: Arc<dyn StateStore>,
+    // the downstream graph the player locations are read from
+    ds_endpoint: String
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -194,14 +199,14 @@ const CUBE_DIRECTION_VECTORS: [Cube; 6] = [
     Cube { q: 0, r: 1, s: -1 },
 ];
 
-async fn get_test_data(vars: &PlayerVars, redis: &RedisHelper) -> Result<Option<Data>, RedisHelperError> {
+async fn get_test_data(vars: &PlayerVars, store: &dyn StateStore) -> Result<Option<Data>, RedisHelperError> {
     let redis_keys: Vec<String> = vars.ids.iter().map(|e|  {
         format!("locations:{}", e)
     }).collect();
     let mut nodes: Vec<Node> = Vec::new();
     for rkey in redis_keys {
-        let result = redis.get_key_test(&rkey).await?;
-        let node: Node = serde_json::from_str(&result).map_err(|_| RedisHelperError::Unknown)?;
+        let result = store.get_key_test(&rkey).await?;
+        let node: Node = serde_json::from_str(&result).map_err(|e| RedisHelperError::Deserialization(Box::new(e)))?;
         nodes.push(node);
     }
     Ok(Some(Data {
@@ -216,10 +221,11 @@ async fn get_test_data(vars: &PlayerVars, redis: &RedisHelper) -> Result<Option<
 
 
 impl SyncGraph {
-    pub fn new(redis: RedisHelper) -> Self {
+    pub fn new(store: Arc<dyn StateStore>, ds_endpoint: String) -> Self {
         Self {
-            redis,
-            client: reqwest::Client::new()
+            store,
+            client: reqwest::Client::new(),
+            ds_endpoint
         }
     }
 
@@ -248,11 +254,12 @@ impl SyncGraph {
 
     async fn calculate_distance(&self, 
         players: &Vec<tonk_shared_lib::Player>, 
-        player_locations: &HashMap<String, tonk_shared_lib::Location>
+        player_locations: &HashMap<String, tonk_shared_lib::Location>,
+        config: &tonk_shared_lib::GameConfig
     ) -> Result<HashMap<String, tonk_shared_lib::PlayerProximity>, JobError> {
 
         let building_index = format!("building:index");
-        let buildings: Vec<tonk_shared_lib::Building> = self.redis.get_index(&building_index).await?;
+        let buildings: Vec<tonk_shared_lib::Building> = self.store.get_index(&building_index).await?;
         let mut player_proximities: HashMap<String, tonk_shared_lib::PlayerProximity> = HashMap::new();
         for i in 0..players.len() {
             let mut nearby_buildings: Vec<tonk_shared_lib::Building> = Vec::new();
@@ -268,7 +275,7 @@ impl SyncGraph {
             for j in 0..buildings.len() {
                 let buildings_cube_coord = Cube::new(buildings[j].location.as_ref().unwrap());
                 let distance = player_cube_coord.distance(&buildings_cube_coord);
-                if distance < 2 {
+                if distance < config.building_radius {
                     nearby_buildings.push(tonk_shared_lib::Building { 
                         id: buildings[j].id.clone(), 
                         readable_id: buildings[j].readable_id.clone(),
@@ -277,7 +284,7 @@ impl SyncGraph {
                         task_message: "".to_string(),
                     });
                 }
-                if distance < 4 && buildings[j].is_tower {
+                if distance < config.immunity_radius && buildings[j].is_tower {
                     immune = Some(true);
                 } 
             }
@@ -293,20 +300,17 @@ impl SyncGraph {
             let location = player_locations.get(&players[i].id).unwrap();
             let player_cube_coord = Cube::new(location);
             let mut nearby_players: Vec<tonk_shared_lib::Player> = Vec::new();
-            let mut show_role = false;
-            if players[i].role.is_some() && *players[i].role.as_ref().unwrap() == tonk_shared_lib::Role::Bugged {
-                show_role = true;
-            }
             for j in 0..players.len() {
                 let other_location = player_locations.get(&players[j].id).unwrap();
                 let other_cube_coord = Cube::new(other_location);
                 let distance = player_cube_coord.distance(&other_cube_coord);
                 // let is_another_bug = players[j].role.as_ref().unwrap_or(&tonk_shared_lib::Role::Bugged).clone() == tonk_shared_lib::Role::Bugged;
-                if distance < 3 && j != i {
-                    let mut role = None;
-                    if show_role {
-                        role = players[j].role.clone()
-                    } 
+                if distance < config.player_radius && j != i {
+                    // each role decides which of the other players' roles it gets to see
+                    let role = match (players[i].role.as_ref(), players[j].role.as_ref()) {
+                        (Some(viewer), Some(other)) => viewer.visible_role(other),
+                        _ => None
+                    };
                     let j_proximal = player_proximities.get(&players[j].id).unwrap();
                     nearby_players.push(tonk_shared_lib::Player {
                         id: players[j].id.clone(),
@@ -338,14 +342,41 @@ impl SyncGraph {
         Ok(player_proximities)
     }
 
+    // only proximities that changed since the last sync are pushed to the player
+    async fn save_proximity(&self, player_id: &str, proximity: &PlayerProximity) -> Result<(), JobError> {
+        let proximity_key = format!("player:{}:proximity", player_id);
+        let previous: Result<PlayerProximity, _> = self.store.get_key(&proximity_key).await;
+        if let Ok(previous) = previous {
+            if previous == *proximity {
+                return Ok(());
+            }
+        }
+        let _: () = self.store.set_key(&proximity_key, proximity).await?;
+        self.store.publish(&player_channel(player_id), &StreamMessage::Proximity {
+            player_id: player_id.to_string(),
+            proximity: proximity.clone()
+        }).await?;
+        Ok(())
+    }
+
     pub async fn run(&self) -> Result<(), JobError> {
-        let game: tonk_shared_lib::Game = self.redis.get_key("game").await?;
+        let games: Vec<tonk_shared_lib::Game> = self.store.get_index("game:index").await?;
+        for game in games {
+            if let Err(e) = self.run_game(game).await {
+                error!("{}", e);
+                metrics::get().job_errors.with_label_values(&["sync_graph"]).inc();
+            }
+        }
+        Ok(())
+    }
+
+    async fn run_game(&self, game: tonk_shared_lib::Game) -> Result<(), JobError> {
         if game.status == tonk_shared_lib::GameStatus::End {
             return Ok(());
         }
         let game_index = format!("game:{}:player_index", game.id);
-        let mut game_players: Vec<tonk_shared_lib::Player> = self.redis.get_index(&game_index).await?;
-        // let mut reg_players: Vec<tonk_shared_lib::Player> = self.redis.get_index("player:index").await?;
+        let mut game_players: Vec<tonk_shared_lib::Player> = self.store.get_index(&game_index).await?;
+        // let mut reg_players: Vec<tonk_shared_lib::Player> = self.store.get_index("player:index").await?;
         // print!("{:?}", reg_players);
         let ids: Vec<String> = game_players.iter_mut().map(|p| p.mobile_unit_id.clone().unwrap_or("".to_string()) ).collect();
         // println!("{:?}", ids);
@@ -354,8 +385,7 @@ impl SyncGraph {
             return Ok(());
         }
 
-        let endpoint = env::var("DS_ENDPOINT").unwrap();
-        let client = gql_client::Client::new(endpoint);
+        let client = gql_client::Client::new(&self.ds_endpoint);
         let vars = PlayerVars {
             gameID: "DOWNSTREAM".to_string(),
             ids,
@@ -363,7 +393,8 @@ impl SyncGraph {
         let result: Result<Option<Data>, gql_client::GraphQLError> = client.query_with_vars::<Data, PlayerVars>(DS_PLAYER_QUERY, vars).await;
         if result.is_err() {
             // println!("{:?}", result.as_ref().err().unwrap());
-            return Ok(());
+            metrics::get().sync_graph_query_errors.inc();
+            return Err(JobError::ClientError(format!("{:?}", result.err().unwrap())));
         } else {
             // println!("{:?}", result.as_ref().unwrap());
         }
@@ -372,7 +403,7 @@ impl SyncGraph {
 
         if let Some(data) = result.unwrap() {
             let player_locations = self.update_locations_player(&data, &game_players);
-            let player_proximities = self.calculate_distance(&game_players, &player_locations).await?;
+            let player_proximities = self.calculate_distance(&game_players, &player_locations, &game.config).await?;
             for player in game_players {
                 // let player_key = format!("player:{}", player.id);
                 // println!("immunity for {:?}:{:?}", player.display_name, player.immune);
@@ -380,10 +411,9 @@ impl SyncGraph {
                 // if player.last_round_action.is_some() && *player.last_round_action.as_ref().unwrap() < round {
                 //     player.used_action = Some(false);
                 // }
-                // let _: () = self.redis.set_key(&player_key, &player).await?;
+                // let _: () = self.store.set_key(&player_key, &player).await?;
                 let proximity = player_proximities.get(&player.id).unwrap();
-                let proximity_key = format!("player:{}:proximity", player.id);
-                let _: () = self.redis.set_key(&proximity_key, &proximity).await?;
+                self.save_proximity(&player.id, proximity).await?;
             }
             Ok(())
         } else {
@@ -392,13 +422,22 @@ impl SyncGraph {
     } 
 
     pub async fn mock_run(&self) -> Result<(), JobError> {
-        let game: tonk_shared_lib::Game = self.redis.get_key("game").await?;
+        let games: Vec<tonk_shared_lib::Game> = self.store.get_index("game:index").await?;
+        for game in games {
+            if let Err(e) = self.mock_run_game(game).await {
+                error!("{}", e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn mock_run_game(&self, game: tonk_shared_lib::Game) -> Result<(), JobError> {
         if game.status == tonk_shared_lib::GameStatus::End {
             return Ok(());
         }
         let game_index = format!("game:{}:player_index", game.id);
-        let mut game_players: Vec<tonk_shared_lib::Player> = self.redis.get_index(&game_index).await?;
-        // let mut reg_players: Vec<tonk_shared_lib::Player> = self.redis.get_index("player:index").await?;
+        let mut game_players: Vec<tonk_shared_lib::Player> = self.store.get_index(&game_index).await?;
+        // let mut reg_players: Vec<tonk_shared_lib::Player> = self.store.get_index("player:index").await?;
         // print!("{:?}", reg_players);
         let ids: Vec<String> = game_players.iter_mut().map(|p| p.mobile_unit_id.clone().unwrap_or("".to_string()) ).collect();
         // println!("{:?}", ids);
@@ -407,18 +446,17 @@ impl SyncGraph {
             return Ok(());
         }
 
-        let endpoint = env::var("DS_ENDPOINT").unwrap();
         let vars = PlayerVars {
             gameID: "DOWNSTREAM".to_string(),
             ids,
         };
-        let result: Result<Option<Data>, RedisHelperError> = get_test_data(&vars, &self.redis).await;
+        let result: Result<Option<Data>, RedisHelperError> = get_test_data(&vars, &*self.store).await;
 
         let round = game.time.as_ref().unwrap().round;
 
         if let Some(data) = result.unwrap() {
             let player_locations = self.update_locations_player(&data, &game_players);
-            let player_proximities = self.calculate_distance(&game_players, &player_locations).await?;
+            let player_proximities = self.calculate_distance(&game_players, &player_locations, &game.config).await?;
             for player in game_players {
                 // let player_key = format!("player:{}", player.id);
                 // println!("immunity for {:?}:{:?}", player.display_name, player.immune);
@@ -426,10 +464,9 @@ impl SyncGraph {
                 // if player.last_round_action.is_some() && *player.last_round_action.as_ref().unwrap() < round {
                 //     player.used_action = Some(false);
                 // }
-                // let _: () = self.redis.set_key(&player_key, &player).await?;
+                // let _: () = self.store.set_key(&player_key, &player).await?;
                 let proximity = player_proximities.get(&player.id).unwrap();
-                let proximity_key = format!("player:{}:proximity", player.id);
-                let _: () = self.redis.set_key(&proximity_key, &proximity).await?;
+                self.save_proximity(&player.id, proximity).await?;
             }
             Ok(())
         } else {
@@ -1,15 +1,31 @@
 use std::sync::Arc;
 use std::time::Duration;
 use tokio_cron_scheduler::{Job, JobScheduler};
-mod jobs;
+pub mod jobs;
+mod metrics_server;
 use tonk_shared_lib::{deserialize_struct, serialize_struct, Building, Location, Player, Game, GameStatus};
 use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
 use crate::jobs::sync_graph::SyncGraph;
 use crate::jobs::clock::Clock;
 use crate::jobs::game_state::GameState;
 use log::*;
 use std::env;
-use dotenv::dotenv;
+use tonk_shared_lib::metrics;
+use tonk_shared_lib::settings::{Service, Settings};
+
+// every job shares one multiplexed connection, if redis isn't up yet we wait for it
+async fn connect_store(redis_url: &str) -> Arc<dyn StateStore> {
+    loop {
+        match RedisHelper::init(redis_url).await {
+            Ok(redis) => return Arc::new(redis),
+            Err(e) => {
+                error!("redis failed to connect: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(2)).await;
+            }
+        }
+    }
+}
 
 pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     env_logger::init();
@@ -19,53 +35,56 @@ pub async fn run() -> Result<(), Box<dyn std::error::Error>> {
     // let shared_client = Arc::new(sync_graph).clone();
 
     // initialize_game_state()?;
-    match env::var("TONK_SERVICES_STAGE") {
-        Ok(stage) => {
-            println!("Starting up tonk-state-service in stage: {}", stage);
-            dotenv::from_filename(".env.production").ok();
-        }
-        Err(_) => {
-            dotenv::from_filename(".env.local").ok();
-        }
-    }
-
+    // a bad or missing setting stops the service before any job runs
+    let args: Vec<String> = env::args().skip(1).collect();
+    let settings = Settings::load(Service::StateService, &args).map_err(|e| {
+        eprintln!("tonk-state-service: {}", e);
+        e
+    })?;
+    println!("Starting up tonk-state-service in stage: {}", settings.stage);
+    let store = connect_store(&settings.redis_url).await;
+    // checked when the settings were loaded
+    let ds_endpoint = settings.ds_endpoint.clone().unwrap();
+    tokio::spawn(metrics_server::serve((settings.bind_address, settings.metrics_port)));
+
+    let sync_graph_store = store.clone();
     sched
         .add(Job::new_async("1/2 * * * * *", move |_, _| {
+            let store = sync_graph_store.clone();
+            let ds_endpoint = ds_endpoint.clone();
             Box::pin(async move {
-                if let Ok(redis) = RedisHelper::init().await {
-                    let sync_graph = SyncGraph::new(redis);
-                    let r = sync_graph.run().await;
-                    if r.is_err() {
-                        error!("{}", r.err().unwrap());
-                    }
+                let sync_graph = SyncGraph::new(store, ds_endpoint);
+                let r = metrics::track_job("sync_graph", sync_graph.run()).await;
+                if r.is_err() {
+                    error!("{}", r.err().unwrap());
                 }
             })
         })?)
         .await?;
 
+    let clock_store = store.clone();
     sched
         .add(Job::new_async("*/1 * * * * *", move |_, _| {
+            let store = clock_store.clone();
             Box::pin(async move {
-                if let Ok(redis) = RedisHelper::init().await {
-                    let clock = Clock::new(redis);
-                    let r = clock.run().await;
-                    if r.is_err() {
-                        error!("{:?}", r.err().unwrap());
-                    }
+                let clock = Clock::new(store);
+                let r = metrics::track_job("clock", clock.run()).await;
+                if r.is_err() {
+                    error!("{:?}", r.err().unwrap());
                 }
             })
         })?)
         .await?;
 
+    let game_state_store = store.clone();
     sched
         .add(Job::new_async("1/3 * * * * *", move |_, _| {
+            let store = game_state_store.clone();
             Box::pin(async move {
-                if let Ok(redis) = RedisHelper::init().await {
-                    let game_state = GameState::new(redis);
-                    let r = game_state.run().await;
-                    if r.is_err() {
-                        error!("{:?}", r.err().unwrap());
-                    }
+                let game_state = GameState::new(store);
+                let r = metrics::track_job("game_state", game_state.run()).await;
+                if r.is_err() {
+                    error!("{:?}", r.err().unwrap());
                 }
             })
         })?)
@@ -89,45 +108,48 @@ pub async fn run_test() -> Result<(), Box<dyn std::error::Error>> {
     // let shared_client = Arc::new(sync_graph).clone();
 
     // initialize_game_state()?;
-    dotenv::from_filename(".env.local").ok();
+    // the test harness owns the command line, so only the files and environment are read
+    let settings = Settings::load(Service::Tool, &[])?;
+    let store = connect_store(&settings.redis_url).await;
 
+    let sync_graph_store = store.clone();
     sched
         .add(Job::new_async("1/2 * * * * *", move |_, _| {
+            let store = sync_graph_store.clone();
             Box::pin(async move {
-                if let Ok(redis) = RedisHelper::init().await {
-                    let sync_graph = SyncGraph::new(redis);
-                    let r = sync_graph.mock_run().await;
-                    if r.is_err() {
-                        error!("{}", r.err().unwrap());
-                    }
+                // the mock run reads its player data from redis instead of the graph
+                let sync_graph = SyncGraph::new(store, String::new());
+                let r = sync_graph.mock_run().await;
+                if r.is_err() {
+                    error!("{}", r.err().unwrap());
                 }
             })
         })?)
         .await?;
 
+    let clock_store = store.clone();
     sched
         .add(Job::new_async("*/1 * * * * *", move |_, _| {
+            let store = clock_store.clone();
             Box::pin(async move {
-                if let Ok(redis) = RedisHelper::init().await {
-                    let clock = Clock::new(redis);
-                    let r = clock.mock_run().await;
-                    if r.is_err() {
-                        error!("{:?}", r.err().unwrap());
-                    }
+                let clock = Clock::new(store);
+                let r = clock.mock_run().await;
+                if r.is_err() {
+                    error!("{:?}", r.err().unwrap());
                 }
             })
         })?)
         .await?;
 
+    let game_state_store = store.clone();
     sched
         .add(Job::new_async("1/3 * * * * *", move |_, _| {
+            let store = game_state_store.clone();
             Box::pin(async move {
-                if let Ok(redis) = RedisHelper::init().await {
-                    let game_state = GameState::new(redis);
-                    let r = game_state.run().await;
-                    if r.is_err() {
-                        error!("{:?}", r.err().unwrap());
-                    }
+                let game_state = GameState::new(store);
+                let r = game_state.run().await;
+                if r.is_err() {
+                    error!("{:?}", r.err().unwrap());
                 }
             })
         })?)
@@ -138,15 +160,11 @@ pub async fn run_test() -> Result<(), Box<dyn std::error::Error>> {
     sched.start().await?;
 
     let mut stop = false;
-    if let Ok(redis) = RedisHelper::init().await {
-        // Wait while the jobs run
-        while !stop {
-            tokio::time::sleep(Duration::from_secs(20)).await;
-            let should_stop = redis.get_key_test("stop").await;
-            stop = should_stop.is_ok()
-        }
-    } else {
-        println!("redis failed to connect");
+    // Wait while the jobs run
+    while !stop {
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        let should_stop = store.get_key_test("stop").await;
+        stop = should_stop.is_ok()
     }
 
     println!("Received stop command");
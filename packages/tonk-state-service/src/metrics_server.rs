@@ -0,0 +1,35 @@
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use tonk_shared_lib::metrics;
+use log::*;
+
+// The service has no HTTP API of its own, so this only answers Prometheus'
+// scrapes of GET /metrics. A failure here never stops the jobs.
+pub async fn serve(address: (IpAddr, u16)) {
+    let address = SocketAddr::from(address);
+    let builder = match Server::try_bind(&address) {
+        Ok(builder) => builder,
+        Err(e) => {
+            error!("couldn't serve metrics on {}: {}", address, e);
+            return;
+        }
+    };
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(respond)) });
+    if let Err(e) = builder.serve(make_service).await {
+        error!("the metrics server stopped: {}", e);
+    }
+}
+
+async fn respond(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::GET || req.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+    let mut response = Response::new(Body::from(metrics::get().render()));
+    response.headers_mut().insert(CONTENT_TYPE, HeaderValue::from_static("text/plain; version=0.0.4"));
+    Ok(response)
+}
@@ -0,0 +1,180 @@
+use std::sync::Arc;
+use tonk_shared_lib::{ActionStatus, Game, GameArchive, GameConfig, GameStatus, Player, PlayerStats, Role, RoundResult, Task, Time, WinResult};
+use tonk_shared_lib::events::{events_key, GameEvent, GameEventKind};
+use tonk_shared_lib::store::*;
+use tonk_state_service::jobs::clock::Clock;
+use tonk_state_service::jobs::game_state::GameState;
+
+// The jobs run against the in-memory store, the way they run against redis
+
+fn game(id: &str, status: GameStatus, timer: u32) -> Game {
+    Game {
+        id: id.to_string(),
+        status,
+        time: Some(Time { timer, round: 0 }),
+        win_result: None,
+        corrupted_players: None,
+        eliminated_players: None,
+        demo_play: false,
+        paused: false,
+        config: GameConfig::default(),
+        seed: 1,
+        runoff: None,
+        host: None,
+    }
+}
+
+fn player(id: &str, role: Role, used_action: Option<ActionStatus>) -> Player {
+    Player {
+        id: id.to_string(),
+        mobile_unit_id: None,
+        display_name: None,
+        secret_key: None,
+        role: Some(role),
+        used_action,
+        last_round_action: None,
+        eliminated: None,
+        proximity: None
+    }
+}
+
+fn task(assignee: &Player, complete: bool) -> Task {
+    Task {
+        assignee: Some(assignee.clone()),
+        destination: None,
+        second_destination: None,
+        round: 0,
+        dropped_off: complete,
+        dropped_off_second: complete,
+        complete
+    }
+}
+
+async fn add_game(store: &MemoryStore, game: &Game) {
+    let game_key = format!("game:{}", game.id);
+    store.set_key(&game_key, game).await.unwrap();
+    store.add_to_index("game:index", &game_key).await.unwrap();
+}
+
+async fn add_player(store: &MemoryStore, game: &Game, player: &Player) {
+    let player_key = format!("player:{}", player.id);
+    store.set_key(&player_key, player).await.unwrap();
+    store.add_to_index(&format!("game:{}:player_index", game.id), &player_key).await.unwrap();
+    store.add_to_index(&format!("game:{}:roster", game.id), &player_key).await.unwrap();
+}
+
+async fn add_task(store: &MemoryStore, game: &Game, task: &Task) {
+    let task_key = format!("task:{}:{}:{}", game.id, task.round, task.assignee.as_ref().unwrap().id);
+    store.set_key(&task_key, task).await.unwrap();
+    store.add_to_index(&format!("game:{}:tasks", game.id), &task_key).await.unwrap();
+}
+
+#[tokio::test]
+async fn clock_counts_down_running_games() {
+    let store = Arc::new(MemoryStore::new());
+    let running = game("running", GameStatus::Tasks, 3);
+    let paused = Game { paused: true, ..game("paused", GameStatus::Tasks, 3) };
+    add_game(&store, &running).await;
+    add_game(&store, &paused).await;
+
+    Clock::new(store.clone()).run().await.unwrap();
+
+    let running: Game = store.get_key("game:running").await.unwrap();
+    assert_eq!(running.time.unwrap().timer, 2);
+    let paused: Game = store.get_key("game:paused").await.unwrap();
+    assert_eq!(paused.time.unwrap().timer, 3);
+    assert_eq!(store.published().len(), 1);
+}
+
+#[tokio::test]
+async fn a_lobby_is_opened_when_none_is_open() {
+    let store = Arc::new(MemoryStore::new());
+
+    GameState::new(store.clone()).run().await.unwrap();
+
+    let games: Vec<Game> = store.get_index("game:index").await.unwrap();
+    assert_eq!(games.len(), 1);
+    assert_eq!(games[0].status, GameStatus::Lobby);
+    let events: Vec<GameEvent> = store.get_list(&events_key(&games[0].id)).await.unwrap();
+    assert!(matches!(events[0].kind, GameEventKind::Created { .. }));
+}
+
+#[tokio::test]
+async fn a_finished_task_round_moves_to_the_vote() {
+    let store = Arc::new(MemoryStore::new());
+    let tasks = game("g", GameStatus::Tasks, 0);
+    add_game(&store, &tasks).await;
+    let done = player("a", Role::Normal, Some(ActionStatus::TaskComplete));
+    let busy = player("b", Role::Normal, None);
+    add_player(&store, &tasks, &done).await;
+    add_player(&store, &tasks, &busy).await;
+    add_player(&store, &tasks, &player("c", Role::Normal, None)).await;
+    add_player(&store, &tasks, &player("d", Role::Bugged, None)).await;
+    add_task(&store, &tasks, &task(&done, true)).await;
+    add_task(&store, &tasks, &task(&busy, false)).await;
+
+    GameState::new(store.clone()).run().await.unwrap();
+
+    let voting: Game = store.get_key("game:g").await.unwrap();
+    assert_eq!(voting.status, GameStatus::Vote);
+    assert_eq!(voting.time, Some(Time { timer: voting.config.vote_duration, round: 1 }));
+    assert!(store.get_key::<tonk_shared_lib::RoundResult>("result:g:0").await.is_ok());
+}
+
+// every task is done, so the crew wins by Perfection and the bug is cleaned out
+async fn perfect_game(store: &MemoryStore) -> Game {
+    let tasks = game("g", GameStatus::Tasks, 0);
+    add_game(store, &tasks).await;
+    for id in ["a", "b"] {
+        let crew = player(id, Role::Normal, Some(ActionStatus::TaskComplete));
+        add_player(store, &tasks, &crew).await;
+        add_task(store, &tasks, &task(&crew, true)).await;
+    }
+    add_player(store, &tasks, &player("c", Role::Bugged, None)).await;
+    tasks
+}
+
+// the end screen has run its course
+async fn end_game(store: &Arc<MemoryStore>) {
+    store.update_key("game:g", |mut game: Game| {
+        game.time = game.time.map(|time| Time { timer: 0, ..time });
+        Some(game)
+    }).await.unwrap();
+    GameState::new(store.clone()).run().await.unwrap();
+}
+
+#[tokio::test]
+async fn a_perfect_game_is_archived_with_every_role() {
+    let store = Arc::new(MemoryStore::new());
+    perfect_game(&store).await;
+
+    GameState::new(store.clone()).run().await.unwrap();
+    let ended: Game = store.get_key("game:g").await.unwrap();
+    assert_eq!(ended.status, GameStatus::End);
+    assert_eq!(ended.win_result, Some(WinResult::Perfection));
+    let result: RoundResult = store.get_key("result:g:0").await.unwrap();
+    assert_eq!(result.round_type, GameStatus::TaskResult);
+    let bug: Player = store.get_key("player:c").await.unwrap();
+    assert_eq!(bug.role, None);
+
+    end_game(&store).await;
+    let archive: GameArchive = store.get_key("history:g").await.unwrap();
+    let bug = archive.players.iter().find(|p| p.id == "c").unwrap();
+    assert_eq!(bug.role, Some(Role::Bugged));
+    assert!(store.get_key::<Vec<Player>>("game:g:final_roster").await.is_err());
+}
+
+#[tokio::test]
+async fn a_perfect_game_credits_the_crew_and_counts_the_bug() {
+    let store = Arc::new(MemoryStore::new());
+    perfect_game(&store).await;
+    GameState::new(store.clone()).run().await.unwrap();
+    end_game(&store).await;
+
+    let crew: PlayerStats = store.get_key("player:a:stats").await.unwrap();
+    assert_eq!((crew.games_played, crew.wins, crew.tasks_completed), (1, 1, 1));
+    assert_eq!(crew.wins_by_role.get(&Role::Normal), Some(&1));
+    let bug: PlayerStats = store.get_key("player:c:stats").await.unwrap();
+    assert_eq!((bug.games_played, bug.wins), (1, 0));
+    assert_eq!(store.get_index_keys("stats:index").await.unwrap().len(), 3);
+}
@@ -1,17 +1,91 @@
 use actix_web::web;
-use crate::handlers::{action, game, player, building, vote, task};
+use crate::handlers::{ability, action, admin, game, history, lobby, player, presence, building, vote, task, spectate, stats, stream};
+use crate::middleware::admin::AdminAuth;
+use crate::middleware::rate_limit::RateLimit;
+use crate::middleware::session::SessionAuth;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+use tonk_shared_lib::settings::{Limit, Settings};
 
-pub fn config(cfg: &mut web::ServiceConfig) {
+// The mutating player routes are rate limited per player and per address. The
+// address limits are loose since everyone at an event may share one network.
+// Each limit can be changed with RATE_LIMIT_<ROUTE>_PLAYER or RATE_LIMIT_<ROUTE>_IP in the settings,
+// where ROUTE is one of REGISTER, SESSION, SECRET, HEARTBEAT, STREAM_TICKET, CREATE_GAME, JOIN,
+// LEAVE, READY, KICK, REJOIN, ACTION, PROTECT, INSPECT, TASK, VOTE or BUILDING.
+pub fn config(cfg: &mut web::ServiceConfig, settings: &Settings) {
     cfg
+    // malformed bodies, queries and paths get the same JSON error body as the handlers
+    .app_data(web::JsonConfig::default().error_handler(|err, _| {
+        ApiError::new(ErrorCode::InvalidRequest, err.to_string()).with_source(err).into()
+    }))
+    .app_data(web::QueryConfig::default().error_handler(|err, _| {
+        ApiError::new(ErrorCode::InvalidRequest, err.to_string()).with_source(err).into()
+    }))
+    .app_data(web::PathConfig::default().error_handler(|err, _| {
+        ApiError::new(ErrorCode::InvalidRequest, err.to_string()).with_source(err).into()
+    }))
     .service(
         web::resource("/")
             .route(web::get().to(game::health_check))
     )
     .service(
-        web::scope("/building")
+        web::scope("/admin")
+            .wrap(AdminAuth)
             .service(
-                web::resource("")
-                    .route(web::post().to(building::post_building))
+                web::scope("/building")
+                    .service(
+                        web::resource("")
+                            .route(web::get().to(building::get_buildings))
+                            .route(web::post().to(building::post_building).wrap(RateLimit::new(settings, "building", Limit::per_minute(30), Limit::per_minute(60))))
+                    )
+                    .service(
+                        web::resource("/{building_id}")
+                            .route(web::get().to(building::get_building))
+                            .route(web::delete().to(building::delete_building))
+                    )
+            )
+            .service(
+                web::resource("/player/{player_id}/session")
+                    .route(web::post().to(admin::post_session))
+            )
+            .service(
+                web::scope("/game/{game_id}")
+                    .service(
+                        web::resource("/start")
+                            .route(web::post().to(admin::post_start))
+                    )
+                    .service(
+                        web::resource("/kick/{target_id}")
+                            .route(web::post().to(admin::post_kick))
+                    )
+                    .service(
+                        web::resource("/pause")
+                            .route(web::post().to(admin::post_pause))
+                    )
+                    .service(
+                        web::resource("/resume")
+                            .route(web::post().to(admin::post_resume))
+                    )
+                    .service(
+                        web::resource("/skip")
+                            .route(web::post().to(admin::post_skip))
+                    )
+                    .service(
+                        web::resource("/end")
+                            .route(web::post().to(admin::post_end))
+                    )
+                    .service(
+                        web::resource("/reset")
+                            .route(web::post().to(admin::post_reset))
+                    )
+                    .service(
+                        web::resource("/seed")
+                            .route(web::post().to(admin::post_seed))
+                    )
+                    .service(
+                        web::resource("/events")
+                            .route(web::get().to(admin::get_events))
+                    )
             )
     ).service(
         web::scope("/player")
@@ -19,54 +93,139 @@ pub fn config(cfg: &mut web::ServiceConfig) {
                 web::scope("/{player_id}")
                     .service(
                         web::resource("")
-                            .route(web::post().to(player::post_player))
+                            .route(web::post().to(player::post_player).wrap(RateLimit::new(settings, "register", Limit::per_minute(5), Limit::per_minute(60))))
                             .route(web::get().to(player::get_player))
                     )
+                    .service(
+                        web::resource("/stats")
+                            .route(web::get().to(stats::get_player_stats))
+                    )
+                    .service(
+                        web::resource("/heartbeat")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(presence::post_heartbeat).wrap(RateLimit::new(settings, "heartbeat", Limit::per_minute(120), Limit::per_minute(3000))))
+                    )
+                    .service(
+                        web::resource("/stream/ticket")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(stream::post_stream_ticket).wrap(RateLimit::new(settings, "stream_ticket", Limit::per_minute(10), Limit::per_minute(300))))
+                    )
+                    .service(
+                        web::resource("/session")
+                            .route(web::post().to(player::post_session).wrap(RateLimit::new(settings, "session", Limit::per_minute(5), Limit::per_minute(60))))
+                    )
+                    .service(
+                        web::resource("/secret")
+                            .wrap(SessionAuth)
+                            .route(web::put().to(player::put_secret).wrap(RateLimit::new(settings, "secret", Limit::per_minute(5), Limit::per_minute(60))))
+                    )
+            )
+    ).service(
+        web::resource("/leaderboard")
+            .route(web::get().to(stats::get_leaderboard))
+    ).service(
+        web::scope("/history")
+            .service(
+                web::resource("")
+                    .route(web::get().to(history::get_history))
+            )
+            .service(
+                web::resource("/{game_id}")
+                    .route(web::get().to(history::get_game_history))
             )
     ).service(
         web::scope("/game")
             .service(
                 web::resource("")
-                    .route(web::get().to(game::get_game))
-                    .route(web::post().to(game::post_game))
+                    .route(web::get().to(game::list_games))
+                    .route(web::post().to(game::create_game).wrap(RateLimit::new(settings, "create_game", Limit::per_minute(2), Limit::per_minute(30))).wrap(SessionAuth))
             )
             .service(
-                web::scope("/result")
-                .service(
-                    web::resource("")
-                        .route(web::get().to(game::get_result))
-                )
-                .service(
-                    web::scope("/{round_number}")
+                web::scope("/{game_id}")
+                    .service(
+                        web::resource("")
+                            .route(web::get().to(game::get_game))
+                    )
+                    .service(
+                        web::resource("/stream")
+                            .route(web::get().to(stream::get_stream))
+                    )
+                    .service(
+                        web::resource("/spectate")
+                            .route(web::get().to(spectate::get_spectate))
+                    )
+                    .service(
+                        web::resource("/spectate/stream")
+                            .route(web::get().to(stream::get_spectator_stream))
+                    )
+                    .service(
+                        web::resource("/start")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(game::post_game))
+                    )
+                    .service(
+                        web::resource("/lobby")
+                            .route(web::get().to(lobby::get_lobby))
+                    )
+                    .service(
+                        web::resource("/rejoin")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(presence::post_rejoin).wrap(RateLimit::new(settings, "rejoin", Limit::per_minute(10), Limit::per_minute(300))))
+                    )
+                    .service(
+                        web::resource("/ready")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(lobby::post_ready).wrap(RateLimit::new(settings, "ready", Limit::per_minute(10), Limit::per_minute(300))))
+                    )
+                    .service(
+                        web::resource("/kick/{target_id}")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(lobby::post_kick).wrap(RateLimit::new(settings, "kick", Limit::per_minute(10), Limit::per_minute(60))))
+                    )
+                    .service(
+                        web::scope("/result")
                         .service(
                             web::resource("")
+                                .route(web::get().to(game::get_result))
+                        )
+                        .service(
+                            web::resource("/{round_number}")
                                 .route(web::get().to(game::get_round_result))
                         )
-                )
-            )
-            .service(
-                web::scope("/{game_id}")
+                    )
                     .service(
                         web::resource("/player")
+                            .wrap(SessionAuth)
                             .route(web::get().to(game::get_game_players))
-                            .route(web::post().to(game::post_player))
+                            .route(web::post().to(game::post_player).wrap(RateLimit::new(settings, "join", Limit::per_minute(10), Limit::per_minute(300))))
+                            .route(web::delete().to(lobby::delete_player).wrap(RateLimit::new(settings, "leave", Limit::per_minute(10), Limit::per_minute(300))))
+                    )
+                    .service(
+                        web::resource("/action")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(action::post_action).wrap(RateLimit::new(settings, "action", Limit::per_minute(20), Limit::per_minute(600))))
+                    )
+                    .service(
+                        web::resource("/protect")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(ability::post_protect).wrap(RateLimit::new(settings, "protect", Limit::per_minute(20), Limit::per_minute(600))))
+                    )
+                    .service(
+                        web::resource("/inspect")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(ability::post_inspect).wrap(RateLimit::new(settings, "inspect", Limit::per_minute(20), Limit::per_minute(600))))
+                    )
+                    .service(
+                        web::resource("/task")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(task::post_task).wrap(RateLimit::new(settings, "task", Limit::per_minute(30), Limit::per_minute(600))))
+                            .route(web::get().to(task::get_task))
+                    )
+                    .service(
+                        web::resource("/vote")
+                            .wrap(SessionAuth)
+                            .route(web::post().to(vote::post_vote).wrap(RateLimit::new(settings, "vote", Limit::per_minute(10), Limit::per_minute(600))))
                     )
             )
-    ).service(
-        web::scope("/action")
-            .service(
-                web::resource("")
-                    .route(web::post().to(action::post_action))
-            )
-    ).service(
-        web::scope("/task")
-            .service(
-                web::resource("")
-                    .route(web::post().to(task::post_task))
-                    .route(web::get().to(task::get_task))
-            )
-    ).service(
-        web::resource("/vote")
-            .route(web::post().to(vote::post_vote))
     );
-}
\ No newline at end of file
+}
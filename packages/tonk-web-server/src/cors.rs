@@ -0,0 +1,68 @@
+use actix_cors::Cors;
+use actix_web::http::header;
+use log::*;
+use tonk_shared_lib::settings::Settings;
+use crate::middleware::admin::ADMIN_KEY_HEADER;
+
+// Which origins, methods and headers browsers may use to call the API. Each list
+// comes from the settings, falling back to defaults for the stage.
+#[derive(Clone, Debug)]
+pub struct CorsPolicy {
+    // None allows any origin
+    origins: Option<Vec<String>>,
+    methods: Vec<String>,
+    headers: Vec<String>,
+    max_age: usize,
+}
+
+impl CorsPolicy {
+    // ALLOWED_ORIGIN, ALLOWED_METHODS and ALLOWED_HEADERS override the stage's
+    // defaults. Locally the admin tools run in the browser too; in production
+    // nothing is allowed until the origins are configured.
+    pub fn new(settings: &Settings) -> Self {
+        let production = settings.is_production();
+        let default_origins: Vec<String> = if production {
+            vec![]
+        } else {
+            vec!["http://localhost:3000".to_string()]
+        };
+        let mut default_headers = vec![header::AUTHORIZATION.to_string(), header::CONTENT_TYPE.to_string()];
+        if !production {
+            default_headers.push(ADMIN_KEY_HEADER.to_string());
+        }
+
+        let origins = settings.allowed_origins.clone().unwrap_or(default_origins);
+        let origins = if origins.iter().any(|o| o == "*") { None } else { Some(origins) };
+        if origins.as_ref().map(|o| o.is_empty()).unwrap_or(false) {
+            warn!("ALLOWED_ORIGIN is not set, browsers won't be able to call the API");
+        }
+        Self {
+            origins,
+            methods: settings.allowed_methods.clone().unwrap_or_else(|| {
+                vec!["GET".to_string(), "POST".to_string(), "DELETE".to_string()]
+            }),
+            headers: settings.allowed_headers.clone().unwrap_or(default_headers),
+            max_age: 3600,
+        }
+    }
+
+    pub fn build(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.methods.iter().map(|m| m.as_str()))
+            .allowed_headers(self.headers.iter().map(|h| h.as_str()))
+            // so clients can back off when they are rate limited
+            .expose_headers(vec![header::RETRY_AFTER])
+            .max_age(self.max_age);
+        match &self.origins {
+            None => {
+                cors = cors.allow_any_origin().send_wildcard();
+            }
+            Some(origins) => {
+                for origin in origins {
+                    cors = cors.allowed_origin(origin);
+                }
+            }
+        }
+        cors
+    }
+}
@@ -0,0 +1,107 @@
+use std::error::Error;
+use std::fmt;
+use actix_web::{HttpResponse, ResponseError};
+use actix_web::http::{header, StatusCode};
+use tonk_shared_lib::error::{ErrorBody, ErrorCode};
+use tonk_shared_lib::redis_helper::RedisHelperError;
+use tonk_shared_lib::lobby::StartError;
+use log::*;
+
+// Every handler error. The client gets the code and message as JSON, the source
+// chain is only written to the log.
+pub struct ApiError {
+    code: ErrorCode,
+    message: String,
+    source: Option<Box<dyn Error + 'static>>,
+    // seconds the client should wait before trying again, sent as Retry-After
+    retry_after: Option<u64>
+}
+
+impl ApiError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into(), source: None, retry_after: None }
+    }
+
+    pub fn with_source(mut self, source: impl Error + 'static) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    pub fn with_retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    pub fn internal(source: impl Error + 'static) -> Self {
+        Self::new(ErrorCode::InternalError, "Unknown error").with_source(source)
+    }
+
+    fn chain(&self) -> String {
+        let mut chain = format!("{:?}: {}", self.code, self.message);
+        let mut source = self.source();
+        while let Some(err) = source {
+            chain.push_str(&format!(" <- {}", err));
+            source = err.source();
+        }
+        chain
+    }
+}
+
+impl fmt::Debug for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.chain())
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for ApiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source.as_deref()
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.code.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        if self.status_code().is_server_error() {
+            error!("{}", self.chain());
+        } else {
+            debug!("{}", self.chain());
+        }
+        let mut response = HttpResponse::build(self.status_code());
+        if let Some(seconds) = self.retry_after {
+            response.insert_header((header::RETRY_AFTER, seconds.to_string()));
+        }
+        response.json(ErrorBody {
+            code: self.code,
+            message: self.message.clone()
+        })
+    }
+}
+
+// a write that lost the race against another request is reported so the client can retry
+impl From<RedisHelperError> for ApiError {
+    fn from(err: RedisHelperError) -> ApiError {
+        match err {
+            RedisHelperError::Conflict => {
+                ApiError::new(ErrorCode::Conflict, "The game state changed while handling the request, try again").with_source(err)
+            }
+            _ => ApiError::internal(err)
+        }
+    }
+}
+
+impl From<StartError> for ApiError {
+    fn from(err: StartError) -> ApiError {
+        let message = err.to_string();
+        ApiError::new(err.code(), message).with_source(err)
+    }
+}
@@ -0,0 +1,106 @@
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{AbilityUse, Game, Player, GameStatus, PlayerProximity};
+use tonk_shared_lib::roles::{Ability, Team};
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::events::{record_event, Actor, GameEventKind};
+use serde::{Deserialize, Serialize};
+use super::load_game;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AbilityQuery {
+    player_id: String
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AbilityRequest {
+    target_id: String,
+    round: u32
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct InspectResult {
+    target_id: String,
+    team: Team
+}
+
+// whether a medic has protected the player from poison this round
+pub async fn is_protected(store: &dyn StateStore, game_id: &str, round: u32, player_id: &str) -> Result<bool, ApiError> {
+    let abilities_index_key = format!("game:{}:abilities", game_id);
+    let abilities: Vec<AbilityUse> = store.get_index(&abilities_index_key).await.map_err(ApiError::internal)?;
+    Ok(abilities.iter().any(|a| a.ability == Ability::Protect && a.round == round && a.target == player_id))
+}
+
+// Checks the player's role allows the ability and records it against the target.
+// Every player gets one ability use per task round, whichever ability it is.
+async fn use_ability(store: &dyn StateStore, game: &Game, player_id: &str, ability: Ability, request: &AbilityRequest) -> Result<Player, ApiError> {
+    let round = game.time.as_ref().unwrap().round;
+    if round != request.round {
+        return Err(ApiError::new(ErrorCode::ImproperRound, "Improper round in request"));
+    }
+    if game.status != GameStatus::Tasks {
+        return Err(ApiError::new(ErrorCode::GameNotInTaskRound, "The game is not in the task round"));
+    }
+
+    let index_key = format!("game:{}:player_index", game.id);
+    let player_keys: Vec<String> = store.get_index_keys(&index_key).await.map_err(ApiError::internal)?;
+    let player_key = format!("player:{}", player_id);
+    let target_key = format!("player:{}", request.target_id);
+    if !player_keys.contains(&player_key) || !player_keys.contains(&target_key) {
+        return Err(ApiError::new(ErrorCode::PlayerNotInGame, "Player is not in the game"));
+    }
+
+    let player: Player = store.get_key(&player_key).await.map_err(ApiError::internal)?;
+    if !player.role.as_ref().map(|r| r.can(ability)).unwrap_or(false) || request.target_id == player.id {
+        return Err(ApiError::new(ErrorCode::ActionNotAllowed, "You cannot take this action"));
+    }
+
+    // protection only reaches the players around the medic, like poison
+    if ability == Ability::Protect {
+        let proximity_key = format!("player:{}:proximity", player_id);
+        let proximity: PlayerProximity = store.get_key(&proximity_key).await.map_err(ApiError::internal)?;
+        let nearby = proximity.nearby_players.unwrap_or_default();
+        if !nearby.iter().any(|p| p.id == request.target_id) {
+            return Err(ApiError::new(ErrorCode::TargetOutOfRange, "The target is not within range"));
+        }
+    }
+
+    let ability_use = AbilityUse {
+        ability,
+        actor: player.id.clone(),
+        target: request.target_id.clone(),
+        round,
+    };
+    let ability_key = format!("ability:{}:{}:{}", game.id, round, player.id);
+    let created = store.set_key_if_absent(&ability_key, &ability_use).await?;
+    if !created {
+        return Err(ApiError::new(ErrorCode::AlreadyActed, "You have already used your ability this round"));
+    }
+    let abilities_index_key = format!("game:{}:abilities", game.id);
+    store.add_to_index(&abilities_index_key, &ability_key).await.map_err(ApiError::internal)?;
+    record_event(store, &game.id, Actor::Player(player.id.clone()), GameEventKind::AbilityUsed {
+        ability_use
+    }).await.map_err(ApiError::internal)?;
+
+    let target: Player = store.get_key(&target_key).await.map_err(ApiError::internal)?;
+    Ok(target)
+}
+
+// USED BY THE MEDIC TO MAKE A NEARBY PLAYER IMMUNE TO POISON FOR THE ROUND
+pub async fn post_protect(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _id: web::Json<AbilityRequest>, _query: web::Query<AbilityQuery>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    use_ability(store.get_ref(), &game, &_query.player_id, Ability::Protect, &_id).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// USED BY THE DETECTIVE TO LEARN WHICH TEAM A PLAYER IS ON
+pub async fn post_inspect(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _id: web::Json<AbilityRequest>, _query: web::Query<AbilityQuery>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    let target = use_ability(store.get_ref(), &game, &_query.player_id, Ability::Inspect, &_id).await?;
+    let team = target.role.as_ref().map(|r| r.team()).unwrap_or(Team::Crew);
+    Ok(HttpResponse::Ok().json(InspectResult {
+        target_id: target.id,
+        team
+    }))
+}
@@ -1,139 +1,138 @@
-use actix_web::{web, Error, HttpResponse, HttpRequest};
-use tonk_shared_lib::{Game, Player, Action, GameStatus, Task, Role, PlayerProximity};
-use tonk_shared_lib::redis_helper::*;
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Player, Action, GameStatus, Task, PlayerProximity};
+use tonk_shared_lib::roles::Ability;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::events::{record_event, Actor, GameEventKind};
 use serde::{Deserialize, Serialize};
 use log::*;
+use super::{load_game, update_player};
+use super::ability::is_protected;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ActionQuery {
-    player_id: String,
-    secret_key: String 
+    player_id: String
 }
 
 // USED TO POISON OTHER PLAYERS DURING THE TASK ROUND
-pub async fn post_action(_id: web::Json<Action>, _query: web::Query<ActionQuery>, req: HttpRequest) -> Result<HttpResponse, Error> {
-    let mut redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-
+pub async fn post_action(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _id: web::Json<Action>, _query: web::Query<ActionQuery>) -> Result<HttpResponse, ApiError> {
     let action = _id.0;
-    let game: Game = redis.get_key("game").await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
+    let game = load_game(store.get_ref(), &game_id).await?;
     let round = game.time.unwrap().round;
     if round != action.round {
-        return Err(actix_web::error::ErrorBadRequest("Improper round in request"));
+        return Err(ApiError::new(ErrorCode::ImproperRound, "Improper round in request"));
     }
     if game.status != GameStatus::Tasks {
-        return Err(actix_web::error::ErrorForbidden("The game is not in the task round"));
+        return Err(ApiError::new(ErrorCode::GameNotInTaskRound, "The game is not in the task round"));
     }
     
     let player_id = &_query.player_id;
     let player_key = format!("player:{}", player_id);
-    let player: Player = redis.get_key(&player_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
 
-    let mut updated_player = player.clone();
+    let index_key = format!("game:{}:player_index", game.id);
+    let player_keys: Vec<String> = store.get_index_keys(&index_key).await.map_err(ApiError::internal)?;
+    if player_keys.iter().find(|k| **k == player_key).is_none() {
+        return Err(ApiError::new(ErrorCode::PlayerNotInGame, "Player is not in the game"));
+    }
+
+    let player: Player = store.get_key(&player_key).await.map_err(ApiError::internal)?;
 
-    if *player.role.as_ref().unwrap() != Role::Bugged {
+    if !player.role.as_ref().map(|r| r.can(Ability::Poison)).unwrap_or(false) {
         error!("Player submitted an action and they were not the bug: {:?}", player);
-        return Err(actix_web::error::ErrorForbidden("You cannot take this action"));
+        return Err(ApiError::new(ErrorCode::ActionNotAllowed, "You cannot take this action"));
     }
 
     let player_proximity_key = format!("player:{}:proximity", player_id);
-    let proximity: PlayerProximity = redis.get_key(&player_proximity_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
-    let nearby_players = proximity.nearby_players.unwrap();
+    let proximity: PlayerProximity = store.get_key(&player_proximity_key).await.map_err(ApiError::internal)?;
+    let nearby_players = proximity.nearby_players.unwrap_or_default();
 
     let action_key = format!("action:{}:{}:{}", game.id, round, player.id);
-    let exists: Result<Action, _> = redis.get_key(&action_key).await;
+    let actions_index_key = format!("game:{}:actions", game.id);
+    let exists: Result<Action, _> = store.get_key(&action_key).await;
 
     let target_is_near = nearby_players.iter().find(|e| {
         e.id == action.poison_target.id
     });
 
     // we only care about these checks the first time around
-    if target_is_near.is_none() && !action.confirmed {
-        error!("Player of id {} submitted an action and they were too far from the target", player.id);
-        return Err(actix_web::error::ErrorForbidden("The target is not within range"));
-    }
     // println!("processing action {:?}", action);
     if !action.confirmed {
-        let target_proximity_key = format!("player:{}:proximity", target_is_near.as_ref().unwrap().id);
-        let target_proximity: PlayerProximity = redis.get_key(&target_proximity_key).await.map_err(|e| {
-            error!("{:?}", e);
-            actix_web::error::ErrorInternalServerError("Unknown error")
+        let target = target_is_near.ok_or_else(|| {
+            error!("Player of id {} submitted an action and they were too far from the target", player.id);
+            ApiError::new(ErrorCode::TargetOutOfRange, "The target is not within range")
         })?;
-        if *target_is_near.as_ref().unwrap().role.as_ref().unwrap() == Role::Bugged {
-            return Err(actix_web::error::ErrorForbidden("Bugs cannot bug another bug"));
+        let target_proximity_key = format!("player:{}:proximity", target.id);
+        let target_proximity: PlayerProximity = store.get_key(&target_proximity_key).await.map_err(ApiError::internal)?;
+        if target.role.as_ref().map(|r| r.is_bug()).unwrap_or(false) {
+            return Err(ApiError::new(ErrorCode::TargetIsBug, "Bugs cannot bug another bug"));
+        }
+        if target_proximity.immune.unwrap_or(false) {
+            return Err(ApiError::new(ErrorCode::TargetImmune, "You cannot bug someone within 3 tiles of the tower"));
         }
-        if *target_proximity.immune.as_ref().unwrap() {
-            return Err(actix_web::error::ErrorForbidden("You cannot bug someone within 3 tiles of the tower"));
+        if is_protected(store.get_ref(), &game.id, round, &action.poison_target.id).await? {
+            return Err(ApiError::new(ErrorCode::TargetImmune, "This player has been protected this round"));
         }
     }
 
     if exists.is_err() && !action.confirmed {
         let mut updated_action = action.clone();
         updated_action.confirmed = false;
+        updated_action.actor = Some(player.id.clone());
         let interrupted_task_key = format!("task:{}:{}:{}", game.id, round, action.poison_target.id);
-        let task_result: Task = redis.get_key(&interrupted_task_key).await.map_err(|e| {
-            error!("{:?}", e);
-            actix_web::error::ErrorInternalServerError("Unknown error")
-        })?;
+        let task_result: Task = store.get_key(&interrupted_task_key).await.map_err(ApiError::internal)?;
         if !task_result.complete {
             // println!("task result to be interrupted is: {:?}", task_result);
             updated_action.interrupted_task = true;
         }
 
-        let _ = redis.set_key(&action_key, &updated_action).await.map_err(|e| {
-            error!("{:?}", e);
-            actix_web::error::ErrorInternalServerError("Unknown error")
-        })?;
+        // only the first of two racing requests gets to take the action
+        let created = store.set_key_if_absent(&action_key, &updated_action).await?;
+        if !created {
+            error!("A player of id {} has already taken the action this round", player.id);
+            return Err(ApiError::new(ErrorCode::AlreadyActed, "You have already taken an action this round"));
+        }
 
         // println!("Setting ReturnToTower on player {:?}", updated_player.id);
-        updated_player.used_action = Some(tonk_shared_lib::ActionStatus::ReturnToTower);
-        redis.set_key(&player_key, &updated_player).await.map_err(|e| {
-            error!("{:?}", e);
-            actix_web::error::ErrorInternalServerError("Unknown error")
-        })?;
-        redis.add_to_index("game:actions", &action_key).await.map_err(|e| {
-            error!("{:?}", e);
-            actix_web::error::ErrorInternalServerError("Unknown error")
-        })?;
+        update_player(store.get_ref(), player_id, |updated_player| {
+            updated_player.used_action = Some(tonk_shared_lib::ActionStatus::ReturnToTower);
+        }).await?;
+        store.add_to_index(&actions_index_key, &action_key).await.map_err(ApiError::internal)?;
+        record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::Poisoned {
+            action: Box::new(updated_action)
+        }).await.map_err(ApiError::internal)?;
     } else {
         if let Ok(stored_action) = exists {
             // this is posted again when the player is completing their action at the tower
             if !stored_action.confirmed {
-                let mut updated_action = stored_action.clone();
                 if let Some(buildings) = proximity.nearby_buildings {
                     for building in buildings {
                         if building.is_tower {
-                            updated_action.confirmed = true;
-                            let _ = redis.set_key(&action_key, &updated_action).await.map_err(|e| {
-                                error!("{:?}", e);
-                                actix_web::error::ErrorInternalServerError("Unknown error")
-                            })?;
+                            let confirmed = store.update_key(&action_key, |mut updated_action: Action| {
+                                if updated_action.confirmed {
+                                    return None;
+                                }
+                                updated_action.confirmed = true;
+                                Some(updated_action)
+                            }).await?;
 
-                            updated_player.used_action = Some(tonk_shared_lib::ActionStatus::TaskComplete);
-                            redis.set_key(&player_key, &updated_player).await.map_err(|e| {
-                                error!("{:?}", e);
-                                actix_web::error::ErrorInternalServerError("Unknown error")
-                            })?;
+                            if confirmed.is_some() {
+                                update_player(store.get_ref(), player_id, |updated_player| {
+                                    updated_player.used_action = Some(tonk_shared_lib::ActionStatus::TaskComplete);
+                                }).await?;
+                                record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::ActionConfirmed {
+                                    round
+                                }).await.map_err(ApiError::internal)?;
+                            }
                         }
                     }
                 }
             } else {
                 error!("A player of id {} has already taken the action this round", player.id);
-                return Err(actix_web::error::ErrorForbidden("You have already taken an action this round"));
+                return Err(ApiError::new(ErrorCode::AlreadyActed, "You have already taken an action this round"));
             }
         } else {
-            return Err(actix_web::error::ErrorInternalServerError("Unknown error"));
+            return Err(ApiError::new(ErrorCode::InternalError, "Unknown error"));
         }
     }
 
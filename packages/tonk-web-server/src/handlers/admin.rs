@@ -0,0 +1,226 @@
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Game, GameStatus, Player, Time, WinResult};
+use tonk_shared_lib::redis_helper::RedisHelperError;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::events::{events_key, record_event, Actor, GameEvent, GameEventKind};
+use tonk_shared_lib::lobby::{ready_key, start_game};
+use super::{load_game, update_game, update_player};
+use super::lobby::remove_from_lobby;
+use super::player::{issue_session, PlayerSession};
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SeedBody {
+    seed: u64
+}
+
+async fn record_admin_event(store: &dyn StateStore, game_id: &str, kind: GameEventKind) -> Result<(), ApiError> {
+    record_event(store, game_id, Actor::Admin, kind).await.map_err(ApiError::internal)
+}
+
+// FORCE START, IGNORES THE BUILDING AND PLAYER COUNT CHECKS
+pub async fn post_start(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    let started = start_game(store.get_ref(), game, true, Actor::Admin).await?;
+    Ok(HttpResponse::Ok().json(started))
+}
+
+// REMOVES A PLAYER FROM THE LOBBY, WHOEVER THE HOST IS
+pub async fn post_kick(store: web::Data<dyn StateStore>, path: web::Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    let (game_id, target_id) = path.into_inner();
+    let game = load_game(store.get_ref(), &game_id).await?;
+    remove_from_lobby(store.get_ref(), &game, &target_id, GameEventKind::PlayerKicked {
+        player_id: target_id.clone()
+    }, Actor::Admin).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// FREEZES THE CLOCK AND ALL PHASE TRANSITIONS
+pub async fn post_pause(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    if game.paused {
+        return Err(ApiError::new(ErrorCode::GameAlreadyPaused, "Game is already paused"));
+    }
+    let game = update_game(store.get_ref(), &game_id, |mut game| {
+        if game.paused {
+            return None;
+        }
+        game.paused = true;
+        Some(game)
+    }).await?;
+    record_admin_event(store.get_ref(), &game.id, GameEventKind::Paused).await?;
+    Ok(HttpResponse::Ok().json(game))
+}
+
+pub async fn post_resume(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    if !game.paused {
+        return Err(ApiError::new(ErrorCode::GameNotPaused, "Game is not paused"));
+    }
+    let game = update_game(store.get_ref(), &game_id, |mut game| {
+        if !game.paused {
+            return None;
+        }
+        game.paused = false;
+        Some(game)
+    }).await?;
+    record_admin_event(store.get_ref(), &game.id, GameEventKind::Resumed).await?;
+    Ok(HttpResponse::Ok().json(game))
+}
+
+// RUNS THE TIMER OUT SO THE STATE SERVICE MOVES TO THE NEXT PHASE ON ITS NEXT TICK
+pub async fn post_skip(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    if game.status == GameStatus::Lobby || game.status == GameStatus::Null {
+        return Err(ApiError::new(ErrorCode::GameNotStarted, "There is no phase to skip until the game has started"));
+    }
+    let game = update_game(store.get_ref(), &game_id, |mut game| {
+        if game.status == GameStatus::Lobby || game.status == GameStatus::Null {
+            return None;
+        }
+        let round = game.time.as_ref().map(|t| t.round).unwrap_or(0);
+        game.time = Some(Time {
+            round,
+            timer: 0
+        });
+        Some(game)
+    }).await?;
+    record_admin_event(store.get_ref(), &game.id, GameEventKind::TimerSkipped).await?;
+    Ok(HttpResponse::Ok().json(game))
+}
+
+// ENDS THE GAME WITHOUT A WINNER, THE STATE SERVICE TEARS IT DOWN WHEN THE END TIMER RUNS OUT
+pub async fn post_end(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    if game.status == GameStatus::End {
+        return Err(ApiError::new(ErrorCode::GameAlreadyEnded, "Game has already ended"));
+    }
+    let mut was_paused = false;
+    let game = update_game(store.get_ref(), &game_id, |mut game| {
+        if game.status == GameStatus::End {
+            return None;
+        }
+        was_paused = game.paused;
+        let round = game.time.as_ref().map(|t| t.round).unwrap_or(0);
+        game.status = GameStatus::End;
+        game.paused = false;
+        game.win_result = Some(WinResult::Null);
+        game.time = Some(Time {
+            round,
+            timer: game.config.end_duration
+        });
+        Some(game)
+    }).await?;
+    if was_paused {
+        record_admin_event(store.get_ref(), &game.id, GameEventKind::Resumed).await?;
+    }
+    record_admin_event(store.get_ref(), &game.id, GameEventKind::PhaseChanged {
+        status: game.status.clone(),
+        time: game.time.clone().unwrap(),
+        win_result: game.win_result.clone()
+    }).await?;
+    Ok(HttpResponse::Ok().json(game))
+}
+
+// PUTS THE GAME BACK INTO THE LOBBY WITH EVERY PLAYER WHO JOINED IT
+pub async fn post_reset(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+
+    let round = game.time.as_ref().map(|t| t.round).unwrap_or(0);
+    for i in 0..=round {
+        let result_key = format!("result:{}:{}", game.id, i);
+        store.clear_key(&result_key).await.map_err(ApiError::internal)?;
+        let archive_key = format!("archive:{}:{}", game.id, i);
+        store.clear_key(&archive_key).await.map_err(ApiError::internal)?;
+    }
+
+    for index in ["actions", "tasks", "votes", "abilities"] {
+        let index_key = format!("game:{}:{}", game.id, index);
+        let keys: Vec<String> = store.get_index_keys(&index_key).await.map_err(ApiError::internal)?;
+        for key in keys {
+            store.clear_key(&key).await.map_err(ApiError::internal)?;
+        }
+        store.clear_index(&index_key).await.map_err(ApiError::internal)?;
+    }
+    // the ready index holds player keys, so only the index itself is cleared
+    store.clear_index(&ready_key(&game.id)).await.map_err(ApiError::internal)?;
+
+    // eliminated players are brought back into the game
+    let roster_key = format!("game:{}:roster", game.id);
+    let player_index_key = format!("game:{}:player_index", game.id);
+    let players: Vec<Player> = store.get_index(&roster_key).await.map_err(ApiError::internal)?;
+    for player in players {
+        let player_key = format!("player:{}", player.id);
+        update_player(store.get_ref(), &player.id, |clean_player| {
+            clean_player.role = None;
+            clean_player.eliminated = None;
+            clean_player.last_round_action = None;
+            clean_player.used_action = Some(tonk_shared_lib::ActionStatus::Unused);
+        }).await?;
+        store.add_to_index(&player_index_key, &player_key).await.map_err(ApiError::internal)?;
+    }
+
+    let reset_game = update_game(store.get_ref(), &game_id, |game| {
+        Some(Game {
+            id: game.id.clone(),
+            status: GameStatus::Lobby,
+            demo_play: false,
+            paused: false,
+            config: game.config.clone(),
+            seed: game.seed,
+            runoff: None,
+            host: game.host.clone(),
+            corrupted_players: None,
+            eliminated_players: None,
+            time: Some(Time {
+                timer: 0,
+                round: 0
+            }),
+            win_result: None
+        })
+    }).await?;
+    record_admin_event(store.get_ref(), &reset_game.id, GameEventKind::Reset).await?;
+    Ok(HttpResponse::Ok().json(reset_game))
+}
+
+// SETS THE SEED OF A LOBBY, TO PLAY OUT A GAME WITH THE SAME ROLES AND TASKS AS AN EARLIER ONE
+pub async fn post_seed(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _id: web::Json<SeedBody>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    if game.status != GameStatus::Lobby {
+        return Err(ApiError::new(ErrorCode::GameAlreadyStarted, "The seed can only be set before the game starts"));
+    }
+    let seed = _id.seed;
+    let game = update_game(store.get_ref(), &game_id, |mut game| {
+        if game.status != GameStatus::Lobby {
+            return None;
+        }
+        game.seed = seed;
+        Some(game)
+    }).await?;
+    record_admin_event(store.get_ref(), &game.id, GameEventKind::SeedChanged { seed }).await?;
+    Ok(HttpResponse::Ok().json(game))
+}
+
+// ISSUES A NEW SESSION TOKEN FOR A PLAYER WHO HAS LOST THEIRS AND HAS NO SECRET KEY
+pub async fn post_session(store: web::Data<dyn StateStore>, player_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let player_key = format!("player:{}", player_id);
+    store.get_key::<Player>(&player_key).await.map_err(|e| {
+        match e {
+            RedisHelperError::MissingKey => ApiError::new(ErrorCode::PlayerNotRegistered, "Player is not registered"),
+            _ => ApiError::internal(e)
+        }
+    })?;
+    let session_token = issue_session(store.get_ref(), &player_id).await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(PlayerSession::new(player_id.to_string(), session_token)))
+}
+
+// EVERY CHANGE MADE TO THE GAME IN ORDER, STILL AVAILABLE AFTER THE GAME IS TORN DOWN
+pub async fn get_events(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let events: Vec<GameEvent> = store.get_list(&events_key(&game_id)).await.map_err(ApiError::internal)?;
+    if events.is_empty() {
+        return Err(ApiError::new(ErrorCode::GameNotFound, "Game does not exist"));
+    }
+    Ok(HttpResponse::Ok().json(events))
+}
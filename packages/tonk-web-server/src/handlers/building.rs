@@ -1,33 +1,44 @@
-use actix_web::{web, HttpResponse, Error};
+use actix_web::{web, HttpResponse};
 use tonk_shared_lib::Building;
 use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
 
-pub async fn post_building(_id: web::Json<Building>) -> Result<HttpResponse, Error> {
-    //TODO check or admin key
+pub async fn post_building(store: web::Data<dyn StateStore>, _id: web::Json<Building>) -> Result<HttpResponse, ApiError> {
     let building = _id.0;
     let key = format!("building:{}", building.id);
-    let redis = RedisHelper::init().await.map_err(|e| {
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-    let exists: Result<Building, _> = redis.get_key(&key).await;
-    let result = redis.set_key(&key, &building).await;
-    let fail_to_set = result.is_err();
-    let mut resp = match result {
-        Ok(_) => {
-            HttpResponse::Ok().json(building)
-        }
-        Err(e) => {
-            println!("{}", e.to_string());
-            HttpResponse::InternalServerError().finish()
+    let exists: Result<Building, _> = store.get_key(&key).await;
+    store.set_key(&key, &building).await?;
+    if exists.is_err() {
+        store.add_to_index("building:index", &key).await?;
+    }
+    Ok(HttpResponse::Ok().json(building))
+}
+
+pub async fn get_buildings(store: web::Data<dyn StateStore>) -> Result<HttpResponse, ApiError> {
+    let buildings: Vec<Building> = store.get_index("building:index").await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(buildings))
+}
+
+pub async fn get_building(store: web::Data<dyn StateStore>, _id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let key = format!("building:{}", _id);
+    let building: Building = store.get_key(&key).await.map_err(|e| {
+        match e {
+            RedisHelperError::MissingKey => ApiError::new(ErrorCode::BuildingNotFound, "Building does not exist"),
+            _ => ApiError::from(e)
         }
-    };
-    if !fail_to_set {
-        if exists.is_err() {
-            if let Err(e) = redis.add_to_index("building:index", &key).await {
-                println!("{}", e.to_string());
-                resp = HttpResponse::InternalServerError().finish();
-            }
-        } 
+    })?;
+    Ok(HttpResponse::Ok().json(building))
+}
+
+pub async fn delete_building(store: web::Data<dyn StateStore>, _id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let key = format!("building:{}", _id);
+    let exists: Result<Building, _> = store.get_key(&key).await;
+    if let Err(RedisHelperError::MissingKey) = exists {
+        return Err(ApiError::new(ErrorCode::BuildingNotFound, "Building does not exist"));
     }
-    Ok(resp)
-}
\ No newline at end of file
+    store.remove_from_index("building:index", &key).await.map_err(ApiError::internal)?;
+    store.clear_key(&key).await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().finish())
+}
@@ -1,136 +1,110 @@
-use actix_web::{web, Error, HttpResponse, HttpRequest};
-use tonk_shared_lib::{Game, Player, deserialize_struct, GameStatus, serialize_struct, Building, Role, RoundResult, Time};
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Game, GameConfig, Player, GameStatus, Role, RoundResult, Time};
 use tonk_shared_lib::redis_helper::*;
-use rand::{Rng, thread_rng, RngCore};
-use rand::seq::SliceRandom;
-use log::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::events::{record_event, Actor, GameEventKind};
+use tonk_shared_lib::rng::new_seed;
+use tonk_shared_lib::lobby::start_game;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+use super::load_game;
+use super::lobby::set_countdown;
+use crate::middleware::session::Session;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PlayerQuery {
     player_id: String
 }
 
+// LIST ALL GAMES IN THE REGISTRY
+pub async fn list_games(store: web::Data<dyn StateStore>) -> Result<HttpResponse, ApiError> {
+    let games: Vec<Game> = store.get_index("game:index").await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(games))
+}
+
+// CREATE A NEW LOBBY
+// AN OPTIONAL GameConfig BODY OVERRIDES THE DEFAULT RULES
+// THE SEED IS ALWAYS FRESH, ONLY THE ADMIN API CAN REPLAY AN EARLIER ONE
+pub async fn create_game(store: web::Data<dyn StateStore>, body: web::Bytes, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
+    let config: GameConfig = if body.is_empty() {
+        GameConfig::default()
+    } else {
+        serde_json::from_slice(&body).map_err(|e| {
+            ApiError::new(ErrorCode::InvalidConfig, format!("Invalid game config: {}", e)).with_source(e)
+        })?
+    };
+    config.validate().map_err(|e| ApiError::new(ErrorCode::InvalidConfig, e))?;
+
+    let game = Game {
+        id: Uuid::new_v4().as_simple().to_string(),
+        status: GameStatus::Lobby,
+        demo_play: false,
+        paused: false,
+        config,
+        seed: new_seed(),
+        runoff: None,
+        host: Some(session.player_id.clone()),
+        corrupted_players: None,
+        eliminated_players: None,
+        time: Some(Time {
+            timer: 0,
+            round: 0
+        }),
+        win_result: None
+    };
+    let game_key = format!("game:{}", game.id);
+    store.set_key(&game_key, &game).await.map_err(ApiError::internal)?;
+    store.add_to_index("game:index", &game_key).await.map_err(ApiError::internal)?;
+    record_event(store.get_ref(), &game.id, Actor::Player(session.player_id.clone()), GameEventKind::Created {
+        game: Box::new(game.clone())
+    }).await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(game))
+}
+
 // START GAME
-// CALL PUT WITHOUT ANY DATA 
-pub async fn post_game() -> Result<HttpResponse, Error> {
-    let redis = RedisHelper::init().await.map_err(|e| {
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-    let game_result: Result<Game, RedisHelperError> = redis.get_key("game").await;
+// CALL POST WITHOUT ANY DATA 
+pub async fn post_game(store: web::Data<dyn StateStore>, game_id: web::Path<String>, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
+    let game_key = format!("game:{}", game_id);
+    let game_result: Result<Game, RedisHelperError> = store.get_key(&game_key).await;
     match game_result {
         Ok(game) => {
-            let mut current_game = game; 
-            if current_game.status != GameStatus::Lobby {
-                return Err(actix_web::error::ErrorForbidden("Game is already started"))
-            }
-
-            // check buildings exists
-            let buildings: Vec<Building> = redis.get_index("building:index").await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError(e)
-            })?;
-
-            let mut found_tower = false;
-            buildings.iter().for_each(|e| {
-                if e.is_tower {
-                    found_tower = true;
-                }
-            });
-
-            if !found_tower || buildings.len() <= 3 {
-                return Err(actix_web::error::ErrorForbidden("Need to register all the proper buildings before the game can begin"));
-            }
-
-            let index_key = format!("game:{}:player_index", current_game.id);
-            let players: Vec<Player> = redis.get_index(&index_key).await.map_err(|e| { 
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError("unknown error")
-            })?;
-
-            if players.len() < 2 {
-                return Err(actix_web::error::ErrorForbidden("More players need to join the game before we can start"));
+            if game.host.as_ref().map(|host| *host != session.player_id).unwrap_or(false) {
+                return Err(ApiError::new(ErrorCode::ActionNotAllowed, "Only the host can start the game"));
             }
-
-            // we don't want the number of bugs to outnumber the players
-            let max_bugs = (players.len() as f64 * 0.25).floor() as usize;
-
-            // the purely stochastic bug player setup
-            let mut new_players: Vec<Player> = Vec::new();
-            let mut rng = rand::thread_rng();
-            let mut num_bugs = 0;
-
-            // Step 2: Traverse and modify
-            for i in 0..players.len() {
-                let is_bug = rng.gen_ratio(1, 4);
-
-                let mut newp = players[i].clone();
-                if is_bug && max_bugs > num_bugs {
-                    num_bugs += 1;
-                    newp.role = Some(Role::Bugged);
-                } else {
-                    newp.role = Some(Role::Normal);
-                }
-                new_players.push(newp);
-            }
-
-            if num_bugs == 0 {
-                // we need to choose at least one random person to be a bug
-                new_players.shuffle(&mut rng);
-                new_players[0].role = Some(Role::Bugged);
-                new_players.shuffle(&mut rng);
-            }
-
-            for player in new_players {
-                let player_key = format!("player:{}", player.id.to_string());
-                let _ = redis.set_key(&player_key, &player).await;
-            }
-
-            // give tasks to all the players
-            // update status
-            current_game.status = GameStatus::Tasks;
-            current_game.time = Some(Time {
-                round: 0,
-                timer: 180,
-            });
-
-            // a special case game where certain rules don't apply to allow for a demo 
-            if players.len() == 2 {
-                current_game.demo_play = true;
-            }
-
-            redis.set_key("game", &current_game).await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError(e)
-            })?;
-
+            let _ = start_game(store.get_ref(), game, false, Actor::Player(session.player_id.clone())).await?;
             Ok(HttpResponse::Ok().finish())
         }
+        Err(RedisHelperError::MissingKey) => {
+            Err(ApiError::new(ErrorCode::GameNotFound, "Game does not exist"))
+        }
         Err(e) => {
-            println!("{}", e);
-            Err(actix_web::error::ErrorInternalServerError("If you are seeing this error, the game is likely in a corrupted state"))
+            Err(ApiError::new(ErrorCode::InternalError, "If you are seeing this error, the game is likely in a corrupted state").with_source(e))
         }
     }
 }
 
 // GET STATUS OF GAME
-pub async fn get_game() -> Result<HttpResponse, Error> {
-    let redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-    let current_game: Result<Game, RedisHelperError> = redis.get_key("game").await;
+pub async fn get_game(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game_key = format!("game:{}", game_id);
+    let current_game: Result<Game, RedisHelperError> = store.get_key(&game_key).await;
     match current_game {
         Ok(game) => {
             Ok(HttpResponse::Ok().json(game))
         }
-        Err(e) => {
+        Err(_) => {
             // the game doesn't exist
             let empty_game = Game {
                 id: "".to_string(),
                 corrupted_players: None,
                 status: GameStatus::Null,
                 demo_play: false,
+                paused: false,
+                config: GameConfig::default(),
+                seed: 0,
+                runoff: None,
+                host: None,
                 time: None,
                 eliminated_players: None,
                 win_result: None
@@ -140,16 +114,16 @@ pub async fn get_game() -> Result<HttpResponse, Error> {
     }
 }
 
-pub async fn health_check() -> Result<HttpResponse, Error> {
+pub async fn health_check() -> Result<HttpResponse, ApiError> {
     Ok(HttpResponse::Ok().body("Hello!"))
 }
 
-fn sanitize_players(players: &Vec<Player>, show_role: bool) -> Vec<Player> {
+pub fn sanitize_players(players: &[Player], viewer_role: Option<&Role>) -> Vec<Player> {
     players.iter().map(|p| {
-        let mut role: Option<Role> = None;
-        if show_role {
-            role = p.role.clone();
-        }
+        let role = match (viewer_role, p.role.as_ref()) {
+            (Some(viewer), Some(other)) => viewer.visible_role(other),
+            _ => None
+        };
         Player {
             id: p.id.clone(),
             mobile_unit_id: p.mobile_unit_id.clone(),
@@ -164,79 +138,91 @@ fn sanitize_players(players: &Vec<Player>, show_role: bool) -> Vec<Player> {
     }).collect()
 }
 
-pub async fn get_game_players(_query: web::Query<PlayerQuery>) -> Result<HttpResponse, Error> {
-    let redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-    let game: Game = redis.get_key("game").await.map_err(|e| { 
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("unknown error")
-    })?;
+pub async fn get_game_players(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _query: web::Query<PlayerQuery>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
     let player_id = _query.0.player_id;
     let player_key = format!("player:{}", player_id);
-    let player_result: Result<Player, RedisHelperError> = redis.get_key(&player_key).await;
+    let player_result: Result<Player, RedisHelperError> = store.get_key(&player_key).await;
 
-    let mut show_role = false;
-    match player_result {
-        Ok(player) => {
-            if player.role.is_some() {
-                show_role = *player.role.as_ref().unwrap() == Role::Bugged;
-            }
-        }
-        Err(RedisHelperError::MissingKey) => {
-            show_role = false;
-        }
-        Err(e) => {
-            error!("{:?}", e);
-            return Err(actix_web::error::ErrorInternalServerError("unknown error"));
-        }
-    }
+    let viewer_role = match player_result {
+        Ok(player) => player.role,
+        Err(RedisHelperError::MissingKey) => None,
+        Err(e) => return Err(ApiError::from(e)),
+    };
 
     let index_key = format!("game:{}:player_index", game.id);
-    let players: Vec<Player> = redis.get_index(&index_key).await.map_err(|e| { 
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("unknown error")
-    })?;
-    Ok(HttpResponse::Ok().json(sanitize_players(&players, show_role)))
+    let players: Vec<Player> = store.get_index(&index_key).await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(sanitize_players(&players, viewer_role.as_ref())))
 }
 
 // Used to join the game
-pub async fn post_player(_id: web::Json<Player>) -> Result<HttpResponse, Error> {
+pub async fn post_player(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _id: web::Json<Player>, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
     let player = _id.0;
-    let redis = RedisHelper::init().await.map_err(|e| {
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-    let game: Game = redis.get_key("game").await.map_err(|e| { 
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("unknown error")
-    })?;
+    if player.id != session.player_id {
+        return Err(ApiError::new(ErrorCode::SessionMismatch, "Session does not belong to this player"));
+    }
+    let game = load_game(store.get_ref(), &game_id).await?;
     if game.status != GameStatus::Lobby {
-        return Err(actix_web::error::ErrorForbidden("You cannot join a game while it is in session"))
+        return Err(ApiError::new(ErrorCode::GameAlreadyStarted, "You cannot join a game while it is in session"))
     }
     let registered_player_key = format!("player:{}", player.id);
-    let registered_player: Player = redis.get_key(&registered_player_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorForbidden("player does not have a tonk")
+    let registered_player: Player = store.get_key(&registered_player_key).await.map_err(|e| {
+        ApiError::new(ErrorCode::PlayerNotRegistered, "player does not have a tonk").with_source(e)
     })?;
 
     let index_key = format!("game:{}:player_index", game.id);
-    let game_players: Vec<Player> = redis.get_index(&index_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("There was an unknown error")
-    })?;
+    let game_players: Vec<Player> = store.get_index(&index_key).await.map_err(ApiError::internal)?;
     if game_players.iter().find(|p| p.id == player.id).is_some() {
-        return Err(actix_web::error::ErrorForbidden("This player has already joined the game"));
+        return Err(ApiError::new(ErrorCode::PlayerAlreadyJoined, "This player has already joined the game"));
     }
-    let _ = redis.add_to_index(&index_key, &registered_player_key).await.map_err(|e| { 
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("There was an unknown error")
-    })?;
+    // saves claiming the player's game for a lobby that is already full, the
+    // count that counts is the one taken when the player is added below
+    if game_players.len() >= game.config.max_players as usize {
+        return Err(ApiError::new(ErrorCode::LobbyFull, "The lobby is full"));
+    }
+
+    // a player can only be in one game at a time, the player's game key is claimed
+    // before joining so two joins racing for different games can't both succeed
+    let player_game_key = format!("player:{}:game", player.id);
+    let claimed = store.set_key_if_absent(&player_game_key, &game.id).await?;
+    let mut fresh_claim = claimed;
+    if !claimed {
+        let joined_game_id: String = store.get_key(&player_game_key).await?;
+        if joined_game_id != game.id {
+            let joined_game_key = format!("game:{}", joined_game_id);
+            let other_game: Result<Game, RedisHelperError> = store.get_key(&joined_game_key).await;
+            if other_game.is_ok() {
+                return Err(ApiError::new(ErrorCode::PlayerInAnotherGame, "This player is already in another game"));
+            }
+            // the old game is gone, take the key over unless another join just did
+            let swapped = store.compare_and_set_key(&player_game_key, &joined_game_id, &game.id).await?;
+            if !swapped {
+                return Err(ApiError::new(ErrorCode::Conflict, "The player joined another game at the same time"));
+            }
+            fresh_claim = true;
+        }
+    }
+
+    let added = store.add_to_index_if_below(&index_key, &registered_player_key, game.config.max_players as usize).await.map_err(ApiError::internal)?;
+    if !added {
+        // another join took the last place, the player is free to join a different game
+        if fresh_claim {
+            store.clear_key(&player_game_key).await.map_err(ApiError::internal)?;
+        }
+        return Err(ApiError::new(ErrorCode::LobbyFull, "The lobby is full"));
+    }
+    let roster_key = format!("game:{}:roster", game.id);
+    store.add_to_index(&roster_key, &registered_player_key).await.map_err(ApiError::internal)?;
+    record_event(store.get_ref(), &game.id, Actor::Player(registered_player.id.clone()), GameEventKind::PlayerJoined {
+        player: Box::new(registered_player.clone())
+    }).await.map_err(ApiError::internal)?;
+    // the new player isn't ready yet
+    set_countdown(store.get_ref(), &game.id, false).await?;
     Ok(HttpResponse::Ok().json(registered_player))
 
     // let index_key = format!("game:{}:player_index", game.id);
     // let player_key = format!("game:{}:player:{}", game.id, player.id);
-    // let redis_player: Result<Player, _> = redis.get_key(&player_key).await;
+    // let redis_player: Result<Player, _> = store.get_key(&player_key).await;
     // //TODO: for extra security, double check if the player is actually close to the tower or not
 
     // match redis_player {
@@ -244,53 +230,34 @@ pub async fn post_player(_id: web::Json<Player>) -> Result<HttpResponse, Error>
     //         Err(actix_web::error::ErrorForbidden("Player already in the game"))
     //     }
     //     Err(e) => {
-    //         if let Ok(_) = redis.set_key(&player_key, &registered_player).await {
+    //         if let Ok(_) = store.set_key(&player_key, &registered_player).await {
     //             let index_key = format!("game:{}:player_index", game.id);
-    //             let _ = redis.add_to_index(&index_key, &player_key).await.map_err(|_| { 
-    //                 actix_web::error::ErrorInternalServerError("unknown error")
+    //             let _ = store.add_to_index(&index_key, &player_key).await.map_err(|_| { 
+    //                 ApiError::new(ErrorCode::InternalError, "unknown error")
     //             })?;
     //             Ok(HttpResponse::Ok().json(player))
     //         } else {
-    //             Err(actix_web::error::ErrorInternalServerError("Unknown error"))
+    //             Err(ApiError::new(ErrorCode::InternalError, "Unknown error"))
     //         }
     //     }
     // }
 }
 
-pub async fn get_result() -> Result<HttpResponse, Error> {
-    let redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-    let game: Game = redis.get_key("game").await.map_err(|e| { 
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("unknown error")
-    })?;
+pub async fn get_result(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
 
     let result_key = format!("result:{}:{}", game.id, game.time.as_ref().unwrap().round);
-    let result: RoundResult = redis.get_key(&result_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("unknown error")
-    })?;
+    let result: RoundResult = store.get_key(&result_key).await.map_err(ApiError::internal)?;
 
     Ok(HttpResponse::Ok().json(result))
 }
 
-pub async fn get_round_result(round_num: web::Path<String>) -> Result<HttpResponse, Error> {
-    let redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-    let game: Game = redis.get_key("game").await.map_err(|e| { 
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("unknown error")
-    })?;
+pub async fn get_round_result(store: web::Data<dyn StateStore>, path: web::Path<(String, String)>) -> Result<HttpResponse, ApiError> {
+    let (game_id, round_num) = path.into_inner();
+    let game = load_game(store.get_ref(), &game_id).await?;
 
     let result_key = format!("result:{}:{}", game.id, round_num);
-    let result: RoundResult = redis.get_key(&result_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("unknown error")
-    })?;
+    let result: RoundResult = store.get_key(&result_key).await.map_err(ApiError::internal)?;
 
     Ok(HttpResponse::Ok().json(result))
 }
\ No newline at end of file
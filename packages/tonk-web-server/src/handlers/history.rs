@@ -0,0 +1,27 @@
+use std::cmp::Reverse;
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{GameArchive, GameSummary};
+use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+// LIST FINISHED GAMES, MOST RECENT FIRST
+pub async fn get_history(store: web::Data<dyn StateStore>) -> Result<HttpResponse, ApiError> {
+    let archives: Vec<GameArchive> = store.get_index("history:index").await.map_err(ApiError::internal)?;
+    let mut summaries: Vec<GameSummary> = archives.iter().map(|a| a.summary()).collect();
+    summaries.sort_by_key(|s| Reverse(s.archived_at));
+    Ok(HttpResponse::Ok().json(summaries))
+}
+
+// FULL RECORD OF A FINISHED GAME, ROLES AND ROUND BY ROUND RESULTS INCLUDED
+pub async fn get_game_history(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let history_key = format!("history:{}", game_id);
+    let archive: GameArchive = store.get_key(&history_key).await.map_err(|e| {
+        match e {
+            RedisHelperError::MissingKey => ApiError::new(ErrorCode::HistoryNotFound, "No finished game with this id"),
+            _ => ApiError::from(e)
+        }
+    })?;
+    Ok(HttpResponse::Ok().json(archive))
+}
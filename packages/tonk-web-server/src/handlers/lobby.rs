@@ -0,0 +1,167 @@
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Game, GameStatus, Player, Time};
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::stream::{game_channel, StreamMessage};
+use tonk_shared_lib::events::{record_event, Actor, GameEventKind};
+use tonk_shared_lib::lobby::{everyone_ready, ready_key};
+use serde::{Deserialize, Serialize};
+use super::{load_game, update_game};
+use super::game::sanitize_players;
+use crate::middleware::session::Session;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ReadyRequest {
+    ready: bool
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LobbyView {
+    host: Option<String>,
+    players: Vec<Player>,
+    ready: Vec<String>,
+    min_players: u32,
+    max_players: u32,
+    // seconds left before the game starts on its own
+    countdown: Option<u32>,
+}
+
+// Arms or stops the lobby's auto-start countdown. The clock runs it down and the
+// state service starts the game once it reaches zero with everyone still ready.
+pub async fn set_countdown(store: &dyn StateStore, game_id: &str, armed: bool) -> Result<(), ApiError> {
+    let game_key = format!("game:{}", game_id);
+    let updated = store.update_key(&game_key, |mut game: Game| {
+        let countdown = game.config.auto_start?;
+        if game.status != GameStatus::Lobby {
+            return None;
+        }
+        let running = game.time.as_ref().map(|t| t.timer > 0).unwrap_or(false);
+        if running == armed {
+            return None;
+        }
+        game.time = Some(Time {
+            round: 0,
+            timer: if armed { countdown } else { 0 }
+        });
+        Some(game)
+    }).await?;
+    if let Some(game) = updated {
+        store.publish(&game_channel(&game.id), &StreamMessage::Game(game.clone())).await.map_err(ApiError::internal)?;
+    }
+    Ok(())
+}
+
+// Takes a player out of an open lobby. If they were the host, the lobby is handed
+// to the remaining player with the lowest id.
+pub async fn remove_from_lobby(store: &dyn StateStore, game: &Game, player_id: &str, kind: GameEventKind, actor: Actor) -> Result<(), ApiError> {
+    if game.status != GameStatus::Lobby {
+        return Err(ApiError::new(ErrorCode::GameAlreadyStarted, "Players can only leave the lobby before the game starts"));
+    }
+    let index_key = format!("game:{}:player_index", game.id);
+    let player_key = format!("player:{}", player_id);
+    let player_keys: Vec<String> = store.get_index_keys(&index_key).await.map_err(ApiError::internal)?;
+    if !player_keys.contains(&player_key) {
+        return Err(ApiError::new(ErrorCode::PlayerNotInGame, "Player is not in the game"));
+    }
+
+    store.remove_from_index(&index_key, &player_key).await.map_err(ApiError::internal)?;
+    let roster_key = format!("game:{}:roster", game.id);
+    store.remove_from_index(&roster_key, &player_key).await.map_err(ApiError::internal)?;
+    store.remove_from_index(&ready_key(&game.id), &player_key).await.map_err(ApiError::internal)?;
+
+    // the player is free to join another game
+    let player_game_key = format!("player:{}:game", player_id);
+    let joined_game_id: Result<String, _> = store.get_key(&player_game_key).await;
+    if joined_game_id.map(|id| id == game.id).unwrap_or(false) {
+        store.clear_key(&player_game_key).await.map_err(ApiError::internal)?;
+    }
+
+    if game.host.as_deref() == Some(player_id) {
+        let mut remaining: Vec<String> = player_keys.iter()
+            .filter(|k| **k != player_key)
+            .filter_map(|k| k.strip_prefix("player:").map(|id| id.to_string()))
+            .collect();
+        remaining.sort();
+        let next_host = remaining.into_iter().next();
+        update_game(store, &game.id, |mut current| {
+            current.host = next_host.clone();
+            Some(current)
+        }).await?;
+    }
+    record_event(store, &game.id, actor, kind).await.map_err(ApiError::internal)?;
+
+    let ready = everyone_ready(store, game, None).await.map_err(ApiError::internal)?;
+    set_countdown(store, &game.id, ready).await
+}
+
+// LEAVE THE LOBBY
+pub async fn delete_player(store: web::Data<dyn StateStore>, game_id: web::Path<String>, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    let player_id = session.player_id.clone();
+    remove_from_lobby(store.get_ref(), &game, &player_id, GameEventKind::PlayerLeft {
+        player_id: player_id.clone()
+    }, Actor::Player(player_id.clone())).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// USED BY THE HOST TO REMOVE A PLAYER FROM THE LOBBY
+pub async fn post_kick(store: web::Data<dyn StateStore>, path: web::Path<(String, String)>, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
+    let (game_id, target_id) = path.into_inner();
+    let game = load_game(store.get_ref(), &game_id).await?;
+    if game.host.as_ref() != Some(&session.player_id) || target_id == session.player_id {
+        return Err(ApiError::new(ErrorCode::ActionNotAllowed, "Only the host can kick other players"));
+    }
+    remove_from_lobby(store.get_ref(), &game, &target_id, GameEventKind::PlayerKicked {
+        player_id: target_id.clone()
+    }, Actor::Player(session.player_id.clone())).await?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// MARK THE PLAYER AS READY, OR NOT, TO START
+pub async fn post_ready(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _id: web::Json<ReadyRequest>, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    if game.status != GameStatus::Lobby {
+        return Err(ApiError::new(ErrorCode::GameAlreadyStarted, "The game has already started"));
+    }
+    let player_id = &session.player_id;
+    let player_key = format!("player:{}", player_id);
+    let index_key = format!("game:{}:player_index", game.id);
+    let player_keys: Vec<String> = store.get_index_keys(&index_key).await.map_err(ApiError::internal)?;
+    if !player_keys.contains(&player_key) {
+        return Err(ApiError::new(ErrorCode::PlayerNotInGame, "Player is not in the game"));
+    }
+
+    // the countdown is armed before the last player is marked ready and stopped
+    // after a player backs out, so the game is never started without it
+    if _id.ready {
+        let ready = everyone_ready(store.get_ref(), &game, Some(player_id)).await.map_err(ApiError::internal)?;
+        set_countdown(store.get_ref(), &game.id, ready).await?;
+        store.add_to_index(&ready_key(&game.id), &player_key).await.map_err(ApiError::internal)?;
+    } else {
+        store.remove_from_index(&ready_key(&game.id), &player_key).await.map_err(ApiError::internal)?;
+        set_countdown(store.get_ref(), &game.id, false).await?;
+    }
+    record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::PlayerReady {
+        ready: _id.ready
+    }).await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// WHO IS IN THE LOBBY AND WHO IS READY
+pub async fn get_lobby(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    let index_key = format!("game:{}:player_index", game.id);
+    let players: Vec<Player> = store.get_index(&index_key).await.map_err(ApiError::internal)?;
+    let ready_keys = store.get_index_keys(&ready_key(&game.id)).await.map_err(ApiError::internal)?;
+    let ready = ready_keys.iter().filter_map(|k| k.strip_prefix("player:").map(|id| id.to_string())).collect();
+    let countdown = game.time.as_ref().map(|t| t.timer).filter(|timer| game.status == GameStatus::Lobby && *timer > 0);
+    Ok(HttpResponse::Ok().json(LobbyView {
+        host: game.host.clone(),
+        players: sanitize_players(&players, None),
+        ready,
+        min_players: game.config.min_players,
+        max_players: game.config.max_players,
+        countdown,
+    }))
+}
@@ -0,0 +1,11 @@
+use actix_web::HttpResponse;
+use tonk_shared_lib::metrics;
+use crate::error::ApiError;
+
+// Prometheus scrapes this for the server's request and redis timings, it is served
+// on METRICS_PORT rather than with the API
+pub async fn get_metrics() -> Result<HttpResponse, ApiError> {
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(metrics::get().render()))
+}
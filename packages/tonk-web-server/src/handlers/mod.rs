@@ -1,6 +1,61 @@
+use tonk_shared_lib::{Game, Player};
+use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::stream::{game_channel, StreamMessage};
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+pub mod ability;
 pub mod action;
+pub mod admin;
 pub mod game;
+pub mod lobby;
+pub mod metrics;
+pub mod history;
 pub mod player;
+pub mod presence;
 pub mod building;
 pub mod vote;
-pub mod task;
\ No newline at end of file
+pub mod spectate;
+pub mod stats;
+pub mod task;
+pub mod stream;
+
+pub async fn load_game(store: &dyn StateStore, game_id: &str) -> Result<Game, ApiError> {
+    let game_key = format!("game:{}", game_id);
+    store.get_key(&game_key).await.map_err(|e| {
+        match e {
+            RedisHelperError::MissingKey => ApiError::new(ErrorCode::GameNotFound, "Game does not exist"),
+            _ => ApiError::from(e)
+        }
+    })
+}
+
+// changes are applied to the latest copy of the player so that concurrent
+// requests and the state service never overwrite each other's fields
+pub async fn update_player<F>(store: &dyn StateStore, player_id: &str, mut f: F) -> Result<Player, ApiError>
+where
+    F: FnMut(&mut Player) + Send
+{
+    let player_key = format!("player:{}", player_id);
+    let updated = store.update_key(&player_key, |mut player: Player| {
+        f(&mut player);
+        Some(player)
+    }).await?;
+    updated.ok_or_else(|| ApiError::new(ErrorCode::InternalError, "Unknown error"))
+}
+
+// applies a change to the latest copy of the game, so a clock tick or phase change
+// that lands in between is kept. The change returns None once it no longer applies.
+pub async fn update_game<F>(store: &dyn StateStore, game_id: &str, f: F) -> Result<Game, ApiError>
+where
+    F: FnMut(Game) -> Option<Game> + Send
+{
+    let game_key = format!("game:{}", game_id);
+    let updated = store.update_key(&game_key, f).await?;
+    let game = updated.ok_or_else(|| {
+        ApiError::new(ErrorCode::Conflict, "The game changed while it was being updated")
+    })?;
+    store.publish(&game_channel(&game.id), &StreamMessage::Game(game.clone())).await.map_err(ApiError::internal)?;
+    Ok(game)
+}
@@ -1,24 +1,71 @@
-use actix_web::{web, Error, HttpResponse};
-use tonk_shared_lib::{Player, Game, Action, Task, Vote, GameStatus, Role, PlayerProximity};
+use actix_web::{web, HttpResponse, HttpRequest};
+use tonk_shared_lib::{Player, PlayerProximity};
 use serde::{Deserialize, Serialize};
 use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::spectator::redact_player;
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use sha2::{Digest, Sha256};
 use log::*;
+use crate::middleware::session::authenticate;
+use super::update_player;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
 // use ethers_rs::{H256, keccak256};
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PlayerSession {
+    player_id: String,
+    session_token: String,
+}
+
+impl PlayerSession {
+    pub fn new(player_id: String, session_token: String) -> Self {
+        Self { player_id, session_token }
+    }
+}
 
 #[derive(Debug, Serialize, Deserialize)]
-pub struct PlayerQuery {
-    onchain_hash: String,
-    secret_key: String,
+pub struct SecretBody {
+    secret_key: String
+}
+
+// Only a hash of the player's secret is kept, apart from the player so it is never served
+fn secret_key(player_id: &str) -> String {
+    format!("player:{}:secret", player_id)
+}
+
+fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+pub fn random_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+pub async fn issue_session(store: &dyn StateStore, player_id: &str) -> Result<String, RedisHelperError> {
+    let token = random_token();
+
+    // only the most recently issued token is valid for a player
+    let player_session_key = format!("player:{}:session", player_id);
+    if let Ok(old_token) = store.get_key::<String>(&player_session_key).await {
+        store.clear_key(&format!("session:{}", old_token)).await?;
+    }
+    store.set_key(&format!("session:{}", token), &player_id.to_string()).await?;
+    store.set_key(&player_session_key, &token).await?;
+    Ok(token)
 }
 
 // Used to establish a new player and is registered by the tonk item
-pub async fn post_player(_id: web::Json<Player>, _path: web::Path<String>) -> Result<HttpResponse, Error> {
+// The response carries the session token that must be sent as a bearer token on every mutating call
+// A secret_key sent with the registration can later be traded for a new session token
+pub async fn post_player(store: web::Data<dyn StateStore>, _id: web::Json<Player>, _path: web::Path<String>, req: HttpRequest) -> Result<HttpResponse, ApiError> {
     // check if the player already exists
-    let redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
     //TODO: IMPLEMENT LATER
     // let onchain_hash = _query.onchain_hash.as_str().clone();
     // let secret = _query.secret_key.as_str().clone();
@@ -26,8 +73,8 @@ pub async fn post_player(_id: web::Json<Player>, _path: web::Path<String>) -> Re
 
     let player_obj = _id.0.clone();
 
-    let player_key = format!("player:{}", _path.to_string());
-    let player: Result<Player, _> = redis.get_key(&player_key).await;
+    let player_key = format!("player:{}", _path);
+    let player: Result<Player, _> = store.get_key(&player_key).await;
     if let Err(RedisHelperError::MissingKey) = player {
         // let secret_bytes = secret.as_bytes();
         // let hash: H256 = ethers_rs::BytesM(keccak256(secret_bytes));
@@ -50,59 +97,76 @@ pub async fn post_player(_id: web::Json<Player>, _path: web::Path<String>) -> Re
                 secret_key: None,
                 eliminated: None
             };
-            let _ = redis.set_key(&player_key, &registered_player).await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError(e)
-            })?;
-
-            let _ = redis.add_to_index("player:index", &player_key).await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError(e)
-            })?;
-            return Ok(HttpResponse::Ok().finish());
+            // two registrations racing for the same id can't both get a session
+            let created = store.set_key_if_absent(&player_key, &registered_player).await.map_err(ApiError::internal)?;
+            if !created {
+                return Err(ApiError::new(ErrorCode::PlayerAlreadyRegistered, "Player was registered by another request"));
+            }
+
+            store.add_to_index("player:index", &player_key).await.map_err(ApiError::internal)?;
+            if let Some(secret) = player_obj.secret_key.as_ref() {
+                store.set_key(&secret_key(&registered_player.id), &hash_secret(secret)).await.map_err(ApiError::internal)?;
+            }
+
+            let session_token = issue_session(store.get_ref(), &registered_player.id).await.map_err(ApiError::internal)?;
+            return Ok(HttpResponse::Ok().json(PlayerSession::new(registered_player.id, session_token)));
         // }
     } else if player.is_ok() && 
         (player_obj.display_name != player.as_ref().unwrap().display_name ||
         player_obj.mobile_unit_id != player.as_ref().unwrap().mobile_unit_id)
     {
-        // potentially some race conditions here
-        let cp = player.as_ref().unwrap().clone();
-        let registered_player = Player {
-            id: cp.id,
-            mobile_unit_id: player_obj.mobile_unit_id,
-            last_round_action: cp.last_round_action,
-            display_name: player_obj.display_name,
-            role: cp.role,
-            proximity: None,
-            used_action: cp.used_action,
-            // secret_key: Some(secret.clone().to_string()),
-            secret_key: cp.secret_key,
-            eliminated: None
-        };
-        let _ = redis.set_key(&player_key, &registered_player).await.map_err(|e| {
-            error!("{:?}", e);
-            actix_web::error::ErrorInternalServerError(e)
-        })?;
+        // only the holder of the player's session can change their details
+        let _ = authenticate(&req).await?;
+
+        // only the details are replaced, whatever the game has written to the player since is kept
+        update_player(store.get_ref(), &_path, |registered_player| {
+            registered_player.mobile_unit_id = player_obj.mobile_unit_id.clone();
+            registered_player.display_name = player_obj.display_name.clone();
+            registered_player.proximity = None;
+            registered_player.eliminated = None;
+        }).await?;
         return Ok(HttpResponse::Ok().finish());
     } 
 
-    Err(actix_web::error::ErrorInternalServerError("unknown error"))
+    Err(ApiError::new(ErrorCode::InternalError, "unknown error"))
 }
 
-pub async fn get_player(_id: web::Path<String>) -> Result<HttpResponse, Error> {
-    let redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
+// ISSUES A NEW SESSION TOKEN TO WHOEVER HOLDS THE PLAYER'S SECRET KEY, THE OLD TOKEN STOPS WORKING
+pub async fn post_session(store: web::Data<dyn StateStore>, _id: web::Json<SecretBody>, _path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let player_key = format!("player:{}", _path);
+    store.get_key::<Player>(&player_key).await.map_err(|e| {
+        match e {
+            RedisHelperError::MissingKey => ApiError::new(ErrorCode::PlayerNotRegistered, "Player is not registered"),
+            _ => ApiError::internal(e)
+        }
     })?;
-    let player_key = format!("player:{}", _id.to_string());
-    let player: Result<Player, _> = redis.get_key(&player_key).await;
-
+    let stored: String = store.get_key(&secret_key(&_path)).await.map_err(|e| {
+        match e {
+            RedisHelperError::MissingKey => ApiError::new(ErrorCode::InvalidSecret, "Player has no secret key, ask an admin for a new session"),
+            _ => ApiError::internal(e)
+        }
+    })?;
+    if stored != hash_secret(&_id.secret_key) {
+        warn!("Wrong secret key sent for player {}", _path);
+        return Err(ApiError::new(ErrorCode::InvalidSecret, "Invalid secret key"));
+    }
+    let session_token = issue_session(store.get_ref(), &_path).await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(PlayerSession::new(_path.to_string(), session_token)))
+}
 
-    // let game: Game = redis.get_key("game").await.map_err(|e| {
-    //     error!("{:?}", e);
-    //     actix_web::error::ErrorInternalServerError(e)
-    // })?;
+// SETS THE SECRET KEY OF A PLAYER WHO STILL HOLDS A SESSION, FOR PLAYERS REGISTERED WITHOUT ONE
+// A SECRET THAT IS ALREADY SET IS NEVER REPLACED
+pub async fn put_secret(store: web::Data<dyn StateStore>, _id: web::Json<SecretBody>, _path: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let created = store.set_key_if_absent(&secret_key(&_path), &hash_secret(&_id.secret_key)).await.map_err(ApiError::internal)?;
+    if !created {
+        return Err(ApiError::new(ErrorCode::Conflict, "A secret key is already set for this player"));
+    }
+    Ok(HttpResponse::Ok().finish())
+}
 
+pub async fn get_player(store: web::Data<dyn StateStore>, _id: web::Path<String>, req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let player_key = format!("player:{}", _id);
+    let player: Result<Player, _> = store.get_key(&player_key).await;
 
     if let Err(RedisHelperError::MissingKey) = player {
         Ok(HttpResponse::Ok().json(Player {
@@ -117,52 +181,21 @@ pub async fn get_player(_id: web::Path<String>) -> Result<HttpResponse, Error> {
             eliminated: None
         }))
     } else if let Ok(registered_player) = player {
-        // let wrapper_player = registered_player.clone();
-        let proximity_key = format!("player:{}:proximity", _id.to_string());
-        let proximity: Option<PlayerProximity> = match redis.get_key(&proximity_key).await {
-            Ok(proximity) => Some(proximity),
-            Err(_) => None
-        };
-
-        // let index_key = format!("game:{}:player_index", game.id);
-        // let game_players: Vec<Player> = redis.get_index(&index_key).await.map_err(|e| {
-        //     error!("{:?}", e);
-        //     actix_web::error::ErrorInternalServerError("There was an unknown error")
-        // })?;
-        // let is_in_game = game_players.iter().find(|p| p.id == registered_player.id).is_some();
-
-        // if game.status == GameStatus::Tasks && is_in_game {
-        //     let role = registered_player.role.as_ref().unwrap();
-
-        //     if *role == Role::Bugged {
-        //         let action_key = format!("action:{}:{}:{}", game.id, game.time.as_ref().unwrap().round, registered_player.id);
-        //         let took_action: Result<Action, RedisHelperError> = redis.get_key(&action_key).await;
-        //         if took_action.is_ok() {
-        //             wrapper_player.used_action = Some(true);
-        //         }
-        //     } else {
-        //         let task_key = format!("task:{}:{}:{}", game.id, game.time.as_ref().unwrap().round, registered_player.id);
-        //         let task: Result<Task, RedisHelperError> = redis.get_key(&task_key).await;
-        //         if task.is_ok() {
-        //             wrapper_player.used_action = Some(task.as_ref().unwrap().complete);
-        //         }
-        //     }
-        // }
+        // ONLY THE PLAYER THEMSELVES SEES THEIR ROLE AND WHO IS NEARBY
+        let is_self = authenticate(&req).await.map(|session| session.player_id == *_id).unwrap_or(false);
+        if !is_self {
+            return Ok(HttpResponse::Ok().json(redact_player(&registered_player, false)));
+        }
 
-        // if game.status == GameStatus::Vote && is_in_game {
-        //     let vote_key = format!("vote:{}:{}:{}", game.id, game.time.as_ref().unwrap().round, registered_player.id);
-        //     let cast_vote: Result<Vote, RedisHelperError> = redis.get_key(&vote_key).await;
-        //     if cast_vote.is_ok() {
-        //         wrapper_player.used_action = Some(true);
-        //     }
-        // }
+        let proximity_key = format!("player:{}:proximity", _id);
+        let proximity: Option<PlayerProximity> = store.get_key(&proximity_key).await.ok();
 
         let mut return_player = registered_player.clone();
-        return_player.proximity = proximity.clone();
+        return_player.proximity = proximity;
         Ok(HttpResponse::Ok().json(return_player))
     } else {
         error!("Couldn't get the player key from redis");
-        Err(actix_web::error::ErrorInternalServerError("unknown error"))
+        Err(ApiError::new(ErrorCode::InternalError, "unknown error"))
     }
 
 }
\ No newline at end of file
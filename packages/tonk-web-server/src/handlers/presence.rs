@@ -0,0 +1,80 @@
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Game, GameStatus, Player, PlayerProximity, Task};
+use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::events::{record_event, Actor, GameEventKind};
+use tonk_shared_lib::presence::touch;
+use serde::{Deserialize, Serialize};
+use bincode::Decode;
+use super::load_game;
+use super::game::sanitize_players;
+use crate::middleware::session::Session;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+// Everything a client needs to pick the game back up after it lost its connection
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RejoinView {
+    game: Game,
+    player: Player,
+    players: Vec<Player>,
+    task: Option<Task>,
+    proximity: Option<PlayerProximity>,
+}
+
+async fn get_optional<T: Decode + Send>(store: &dyn StateStore, key: &str) -> Result<Option<T>, ApiError> {
+    let value: Result<T, RedisHelperError> = store.get_key(key).await;
+    match value {
+        Ok(value) => Ok(Some(value)),
+        Err(RedisHelperError::MissingKey) => Ok(None),
+        Err(e) => Err(ApiError::from(e))
+    }
+}
+
+// SENT BY THE CLIENT EVERY FEW SECONDS SO THE GAME KNOWS IT IS STILL CONNECTED
+pub async fn post_heartbeat(store: web::Data<dyn StateStore>, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
+    touch(store.get_ref(), &session.player_id).await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().finish())
+}
+
+// USED BY A RETURNING PLAYER TO GET BACK INTO A GAME THEY ARE STILL PART OF
+pub async fn post_rejoin(store: web::Data<dyn StateStore>, game_id: web::Path<String>, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    let player_id = &session.player_id;
+    let player_key = format!("player:{}", player_id);
+
+    let index_key = format!("game:{}:player_index", game.id);
+    let players: Vec<Player> = store.get_index(&index_key).await.map_err(ApiError::internal)?;
+    let player = players.iter().find(|p| p.id == *player_id).cloned().ok_or_else(|| {
+        ApiError::new(ErrorCode::PlayerNotInGame, "Player is not in the game")
+    })?;
+
+    // eliminated players are taken out of the index, so only live players get this far.
+    // The player's game key may have been cleared while they were away
+    let player_game_key = format!("player:{}:game", player_id);
+    store.set_key_if_absent(&player_game_key, &game.id).await?;
+    touch(store.get_ref(), player_id).await.map_err(ApiError::internal)?;
+
+    let task = match (&game.status, game.time.as_ref()) {
+        (GameStatus::Tasks, Some(time)) => {
+            let task_key = format!("task:{}:{}:{}", game.id, time.round, player_id);
+            get_optional(store.get_ref(), &task_key).await?
+        }
+        _ => None
+    };
+    let proximity_key = format!("{}:proximity", player_key);
+    let proximity = get_optional(store.get_ref(), &proximity_key).await?;
+
+    record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::PlayerRejoined {
+        player_id: player_id.clone()
+    }).await.map_err(ApiError::internal)?;
+
+    let players = sanitize_players(&players, player.role.as_ref());
+    Ok(HttpResponse::Ok().json(RejoinView {
+        game,
+        player: Player { secret_key: None, ..player },
+        players,
+        task,
+        proximity,
+    }))
+}
@@ -0,0 +1,43 @@
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Player, PlayerProximity, RoundResult};
+use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::spectator::spectator_view;
+use super::load_game;
+use crate::error::ApiError;
+
+// READ-ONLY VIEW OF A GAME FOR SPECTATORS
+// no session is needed and the spectator never joins the game, live phase and
+// timer changes are on the spectator stream
+pub async fn get_spectate(store: web::Data<dyn StateStore>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+
+    // the roster keeps the players who were voted or bugged out
+    let roster_key = format!("game:{}:roster", game.id);
+    let players: Vec<Player> = store.get_index(&roster_key).await.map_err(ApiError::internal)?;
+    let mut located = Vec::with_capacity(players.len());
+    for player in players {
+        let proximity_key = format!("player:{}:proximity", player.id);
+        let proximity: Result<PlayerProximity, RedisHelperError> = store.get_key(&proximity_key).await;
+        let proximity = match proximity {
+            Ok(proximity) => Some(proximity),
+            Err(RedisHelperError::MissingKey) => None,
+            Err(e) => return Err(ApiError::from(e)),
+        };
+        located.push((player, proximity));
+    }
+
+    let mut results = vec![];
+    let round = game.time.as_ref().map(|t| t.round).unwrap_or(0);
+    for round in 0..=round {
+        let result_key = format!("result:{}:{}", game.id, round);
+        let result: Result<RoundResult, RedisHelperError> = store.get_key(&result_key).await;
+        match result {
+            Ok(result) => results.push(result),
+            Err(RedisHelperError::MissingKey) => {}
+            Err(e) => return Err(ApiError::from(e)),
+        }
+    }
+
+    Ok(HttpResponse::Ok().json(spectator_view(&game, &located, &results)))
+}
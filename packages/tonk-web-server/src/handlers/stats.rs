@@ -0,0 +1,72 @@
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Player, PlayerStats};
+use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use serde::{Deserialize, Serialize};
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaderboardQuery {
+    limit: Option<usize>
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LeaderboardEntry {
+    rank: usize,
+    player_id: String,
+    display_name: Option<String>,
+    wins: u32,
+    games_played: u32,
+    tasks_completed: u32,
+    successful_poisons: u32,
+    correct_votes: u32,
+}
+
+pub async fn get_player_stats(store: web::Data<dyn StateStore>, _id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let player_key = format!("player:{}", _id);
+    let player: Result<Player, _> = store.get_key(&player_key).await;
+    if let Err(RedisHelperError::MissingKey) = player {
+        return Err(ApiError::new(ErrorCode::PlayerNotFound, "Player does not exist"));
+    }
+
+    // players who haven't finished a game yet have empty stats
+    let stats_key = format!("player:{}:stats", _id);
+    let stats: PlayerStats = match store.get_key(&stats_key).await {
+        Ok(stats) => stats,
+        Err(RedisHelperError::MissingKey) => PlayerStats::new(&_id),
+        Err(e) => return Err(ApiError::from(e)),
+    };
+    Ok(HttpResponse::Ok().json(stats))
+}
+
+// RANKED BY WINS, THEN BY THE FEWEST GAMES IT TOOK TO GET THEM
+pub async fn get_leaderboard(store: web::Data<dyn StateStore>, _query: web::Query<LeaderboardQuery>) -> Result<HttpResponse, ApiError> {
+    let mut all_stats: Vec<PlayerStats> = store.get_index("stats:index").await.map_err(ApiError::internal)?;
+    all_stats.sort_by(|a, b| {
+        b.wins.cmp(&a.wins)
+            .then(a.games_played.cmp(&b.games_played))
+            .then(a.player_id.cmp(&b.player_id))
+    });
+
+    let limit = _query.limit.unwrap_or(100);
+    let mut leaderboard: Vec<LeaderboardEntry> = Vec::new();
+    for (i, stats) in all_stats.into_iter().take(limit).enumerate() {
+        let player_key = format!("player:{}", stats.player_id);
+        let display_name = match store.get_key::<Player>(&player_key).await {
+            Ok(player) => player.display_name,
+            Err(_) => None
+        };
+        leaderboard.push(LeaderboardEntry {
+            rank: i + 1,
+            player_id: stats.player_id,
+            display_name,
+            wins: stats.wins,
+            games_played: stats.games_played,
+            tasks_completed: stats.tasks_completed,
+            successful_poisons: stats.successful_poisons,
+            correct_votes: stats.correct_votes,
+        });
+    }
+    Ok(HttpResponse::Ok().json(leaderboard))
+}
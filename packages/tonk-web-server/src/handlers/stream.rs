@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use actix_web::{web, Error, HttpResponse, HttpRequest};
+use actix_web::http::header;
+use futures_util::{stream, StreamExt};
+use tokio::sync::broadcast;
+use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::stream::{game_channel, player_channel, StreamMessage};
+use tonk_shared_lib::spectator::{redact_game, spectator_message};
+use log::*;
+use serde::{Deserialize, Serialize};
+use super::load_game;
+use super::player::random_token;
+use crate::middleware::session::{authenticate, Session};
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+// EventSource can't send an Authorization header, so a player trades their
+// session for a ticket that is good for opening one stream within a minute
+const TICKET_SECONDS: u64 = 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StreamTicket {
+    ticket: String,
+    expires_in: u64
+}
+
+fn ticket_key(player_id: &str) -> String {
+    format!("player:{}:stream_ticket", player_id)
+}
+
+fn now_seconds() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn event(payload: &str) -> web::Bytes {
+    web::Bytes::from(format!("data: {}\n\n", payload))
+}
+
+// Every event stream is served from one redis subscription per server instead of
+// a connection per client. Messages are fanned out with their channel name and
+// each stream keeps the ones it subscribed to.
+pub struct StreamHub {
+    sender: broadcast::Sender<(String, String)>
+}
+
+impl StreamHub {
+    pub fn start(redis_url: String) -> Self {
+        let (sender, _) = broadcast::channel(1024);
+        let hub_sender = sender.clone();
+        actix_web::rt::spawn(async move {
+            loop {
+                if let Err(e) = forward_messages(&redis_url, &hub_sender).await {
+                    error!("{:?}", e);
+                }
+                // the subscription dropped, reconnect after a moment
+                actix_web::rt::time::sleep(Duration::from_secs(1)).await;
+            }
+        });
+        Self { sender }
+    }
+
+    fn subscribe(&self) -> broadcast::Receiver<(String, String)> {
+        self.sender.subscribe()
+    }
+}
+
+async fn forward_messages(redis_url: &str, sender: &broadcast::Sender<(String, String)>) -> Result<(), redis::RedisError> {
+    let con = get_connection(redis_url).await?;
+    let mut pubsub = con.into_pubsub();
+    pubsub.psubscribe(game_channel("*")).await?;
+    pubsub.psubscribe(player_channel("*")).await?;
+    let mut messages = pubsub.into_on_message();
+    while let Some(msg) = messages.next().await {
+        let payload: String = msg.get_payload().unwrap_or_default();
+        // no open streams is not an error
+        let _ = sender.send((msg.get_channel_name().to_string(), payload));
+    }
+    Ok(())
+}
+
+// Streams the initial message and then every message published on the channels,
+// each passed through the filter, which can rewrite or drop it
+fn event_stream(hub: &StreamHub, channels: Vec<String>, initial: StreamMessage, filter: fn(String) -> Option<String>) -> Result<HttpResponse, ApiError> {
+    let receiver = hub.subscribe();
+
+    // the client gets the current state straight away, then every change after it
+    let snapshot = serde_json::to_string(&initial).map_err(ApiError::internal)?;
+    let initial = stream::once(async move { Ok::<_, Error>(event(&snapshot)) });
+    let updates = stream::unfold((receiver, channels), move |(mut receiver, channels)| async move {
+        loop {
+            match receiver.recv().await {
+                Ok((channel, payload)) => {
+                    if !channels.contains(&channel) {
+                        continue;
+                    }
+                    if let Some(payload) = filter(payload) {
+                        return Some((Ok::<_, Error>(event(&payload)), (receiver, channels)));
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("Event stream fell behind and skipped {} messages", skipped);
+                }
+                Err(broadcast::error::RecvError::Closed) => return None
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .insert_header((header::CONTENT_TYPE, "text/event-stream"))
+        .insert_header((header::CACHE_CONTROL, "no-cache"))
+        .streaming(initial.chain(updates)))
+}
+
+// ISSUES A ONE-USE TICKET FOR OPENING THE PLAYER'S EVENT STREAM WITHOUT A HEADER
+// pass it as /game/{game_id}/stream?player_id=...&ticket=..., a new ticket replaces the last one
+pub async fn post_stream_ticket(store: web::Data<dyn StateStore>, session: web::ReqData<Session>) -> Result<HttpResponse, ApiError> {
+    let ticket = random_token();
+    let expires_at = now_seconds() + TICKET_SECONDS;
+    store.set_key(&ticket_key(&session.player_id), &(ticket.clone(), expires_at)).await.map_err(ApiError::internal)?;
+    Ok(HttpResponse::Ok().json(StreamTicket {
+        ticket,
+        expires_in: TICKET_SECONDS
+    }))
+}
+
+// the player a stream ticket was issued to, the ticket can't be used again
+async fn redeem_ticket(store: &dyn StateStore, player_id: &str, ticket: &str) -> Result<(), ApiError> {
+    let key = ticket_key(player_id);
+    let invalid = || ApiError::new(ErrorCode::InvalidSession, "Invalid or expired stream ticket");
+    let (issued, expires_at): (String, u64) = store.get_key(&key).await.map_err(|e| {
+        match e {
+            RedisHelperError::MissingKey => invalid(),
+            _ => ApiError::internal(e)
+        }
+    })?;
+    if issued != ticket {
+        return Err(invalid());
+    }
+    store.clear_key(&key).await.map_err(ApiError::internal)?;
+    if expires_at < now_seconds() {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+// SERVER-SENT EVENTS FOR A GAME
+// status/timer changes and round results are public, proximity updates are only
+// sent when the request carries the player's session token, or a stream ticket
+// and player_id in the query string
+pub async fn get_stream(store: web::Data<dyn StateStore>, hub: web::Data<StreamHub>, game_id: web::Path<String>, query: web::Query<HashMap<String, String>>, req: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+
+    let mut channels = vec![game_channel(&game.id)];
+    if req.headers().contains_key(header::AUTHORIZATION) {
+        let session = authenticate(&req).await?;
+        channels.push(player_channel(&session.player_id));
+    } else if let Some(ticket) = query.get("ticket") {
+        let player_id = query.get("player_id").ok_or_else(|| {
+            ApiError::new(ErrorCode::InvalidRequest, "A stream ticket needs the player_id it was issued to")
+        })?;
+        redeem_ticket(store.get_ref(), player_id, ticket).await?;
+        channels.push(player_channel(player_id));
+    }
+    event_stream(&hub, channels, StreamMessage::Game(game), Some)
+}
+
+fn spectator_payload(payload: String) -> Option<String> {
+    let message: StreamMessage = serde_json::from_str(&payload).ok()?;
+    serde_json::to_string(&spectator_message(message)?).ok()
+}
+
+// SERVER-SENT EVENTS FOR SPECTATORS
+// the game's public stream with the roles and player details taken out until it ends
+pub async fn get_spectator_stream(store: web::Data<dyn StateStore>, hub: web::Data<StreamHub>, game_id: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
+    let channels = vec![game_channel(&game.id)];
+    event_stream(&hub, channels, StreamMessage::Game(redact_game(&game)), spectator_payload)
+}
@@ -1,90 +1,69 @@
-use std::borrow::BorrowMut;
 
-use actix_web::{web, Error, HttpResponse, HttpRequest};
-use tonk_shared_lib::{Task, Building, Game, Player, GameStatus, Role, PlayerProximity};
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Task, Building, Game, Player, GameStatus, PlayerProximity};
 use serde::{Deserialize, Serialize};
 use tonk_shared_lib::redis_helper::*;
-use rand::Rng;
-use log::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::events::{record_event, Actor, GameEventKind};
+use tonk_shared_lib::rng::pick_depots;
+use super::{load_game, update_player};
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct TaskQuery {
-    player_id: String,
-    secret_key: String 
+    player_id: String
 }
 
-async fn get_random_depot(redis: &mut RedisHelper) -> Result<Building, Box<dyn std::error::Error>> {
-    let mut rng = rand::thread_rng();
-    let buildings: Vec<Building> = redis.get_index("building:index").await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-
-    let random_index = rng.gen_range(0..buildings.len()-1); 
-    let depots: Vec<&Building> = buildings.iter().filter(|e| {
-        !e.is_tower
-    }).collect();
-    let chosen_depot = depots[random_index].clone();
-    Ok(chosen_depot)
+// the depots are drawn from the game's seed, so a replayed game hands out the same tasks
+async fn get_task_depots(store: &dyn StateStore, game: &Game, round: u32, player_id: &str) -> Result<(Building, Building), ApiError> {
+    let buildings: Vec<Building> = store.get_index("building:index").await.map_err(ApiError::internal)?;
+    pick_depots(&buildings, game.seed, round, player_id).ok_or_else(|| {
+        ApiError::new(ErrorCode::NotEnoughBuildings, "Need at least two depots to hand out a task")
+    })
 }
 
 // RETURNS TASK AND IF IT DOESNT EXIST THEN RANDOMLY ASSIGNS NEW TASK
-pub async fn get_task(_query: web::Query<TaskQuery>, req: HttpRequest) -> Result<HttpResponse, Error> {
-    let mut redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-
-    let game: Game = redis.get_key("game").await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
+pub async fn get_task(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _query: web::Query<TaskQuery>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
     if game.status != GameStatus::Tasks {
-        return Err(actix_web::error::ErrorForbidden("The game is not in the task round"));
+        return Err(ApiError::new(ErrorCode::GameNotInTaskRound, "The game is not in the task round"));
     }
 
     let index_key = format!("game:{}:player_index", game.id);
-    let player_keys: Vec<String> = redis.get_index_keys(&index_key).await.map_err(|e| { 
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
+    let player_keys: Vec<String> = store.get_index_keys(&index_key).await.map_err(ApiError::internal)?;
 
     let player_id = &_query.player_id;
     let player_key = format!("player:{}", player_id);
 
     if player_keys.iter().find(|k| **k == player_key).is_none() {
-        return Err(actix_web::error::ErrorForbidden("Player is not in the game"));
+        return Err(ApiError::new(ErrorCode::PlayerNotInGame, "Player is not in the game"));
     }
 
-    let player: Player = redis.get_key(&player_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
-    if *player.role.as_ref().unwrap() == Role::Bugged {
+    let player: Player = store.get_key(&player_key).await.map_err(ApiError::internal)?;
+    let role = player.role.as_ref().ok_or_else(|| ApiError::new(ErrorCode::ActionNotAllowed, "You have not been given a role"))?;
+    if !role.does_tasks() {
         let empty_task = Task {
             assignee: Some(player.clone()),
             destination: Some(Building { id: "".to_string(), readable_id: "".to_string(), location: None, task_message: "You have been corrupted and seek to attack others.".to_string(), is_tower: false }),
             second_destination: Some(Building { id: "".to_string(), readable_id: "".to_string(), location: None, task_message: "You have been corrupted and seek to attack others.".to_string(), is_tower: false }),
-            round: game.time.as_ref().unwrap().round.clone(),
+            round: game.time.as_ref().unwrap().round,
             dropped_off: false,
             dropped_off_second: false,
             complete: false
         };
         return Ok(HttpResponse::Ok().json(empty_task));
     }
-    let round = game.time.unwrap().round;
+    let round = game.time.as_ref().unwrap().round;
     let task_key = format!("task:{}:{}:{}", game.id, round, player_id);
-    let task_result: Result<Task, RedisHelperError> = redis.get_key(&task_key).await;
+    let tasks_index_key = format!("game:{}:tasks", game.id);
+    let task_result: Result<Task, RedisHelperError> = store.get_key(&task_key).await;
     match task_result {
         Ok(task) => {
             Ok(HttpResponse::Ok().json(task))
         }
         Err(RedisHelperError::MissingKey) => {
-            let depot = get_random_depot(redis.borrow_mut()).await?;
-            let mut depot_2 = get_random_depot(redis.borrow_mut()).await?;
-            while depot_2.id == depot.id {
-                depot_2 = get_random_depot(redis.borrow_mut()).await?;
-            }
+            let (depot, depot_2) = get_task_depots(store.get_ref(), &game, round, player_id).await?;
             let random_task = Task {
                 assignee: Some(Player { 
                     id: player_id.clone(), 
@@ -99,114 +78,118 @@ pub async fn get_task(_query: web::Query<TaskQuery>, req: HttpRequest) -> Result
                 }),
                 destination: Some(depot),
                 second_destination: Some(depot_2),
-                round: round,
+                round,
                 dropped_off: false,
                 dropped_off_second: false,
                 complete: false
             };
-            let _ = redis.set_key(&task_key, &random_task).await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError("Unknown error")
-            })?;
-            redis.add_to_index("game:tasks", &task_key).await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError("Unknown error")
-            })?;
+            // if a parallel request assigned a task first, that one is the player's task
+            let created = store.set_key_if_absent(&task_key, &random_task).await?;
+            if !created {
+                let task: Task = store.get_key(&task_key).await?;
+                return Ok(HttpResponse::Ok().json(task));
+            }
+            store.add_to_index(&tasks_index_key, &task_key).await.map_err(ApiError::internal)?;
+            record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::TaskAssigned {
+                task: Box::new(random_task.clone())
+            }).await.map_err(ApiError::internal)?;
             Ok(HttpResponse::Ok().json(random_task))
         }
         _ => {
-            Err(actix_web::error::ErrorInternalServerError("An unexpected error occurred."))
+            Err(ApiError::new(ErrorCode::InternalError, "An unexpected error occurred."))
         }
     }
 }
 
 // USED TO CONFIRM SUCCESSFUL COMPLETION OF TASK
-pub async fn post_task(_id: web::Json<Task>, _query: web::Query<TaskQuery>, req: HttpRequest) -> Result<HttpResponse, Error> {
-    let mut redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-
-    let game: Game = redis.get_key("game").await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
+pub async fn post_task(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _id: web::Json<Task>, _query: web::Query<TaskQuery>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
     let round = game.time.unwrap().round;
     if game.status != GameStatus::Tasks {
-        return Err(actix_web::error::ErrorForbidden("The game is not in the task round"));
+        return Err(ApiError::new(ErrorCode::GameNotInTaskRound, "The game is not in the task round"));
     }
 
     let player_id = &_query.player_id;
     let player_key = format!("player:{}", player_id);
-    let task_key = format!("task:{}:{}:{}", game.id, round, player_id);
 
-    let player: Player = redis.get_key(&player_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
-    let mut updated_player = player.clone();
+    let index_key = format!("game:{}:player_index", game.id);
+    let player_keys: Vec<String> = store.get_index_keys(&index_key).await.map_err(ApiError::internal)?;
+    if player_keys.iter().find(|k| **k == player_key).is_none() {
+        return Err(ApiError::new(ErrorCode::PlayerNotInGame, "Player is not in the game"));
+    }
+
+    let task_key = format!("task:{}:{}:{}", game.id, round, player_id);
 
-    let task: Task = redis.get_key(&task_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
+    let task: Task = store.get_key(&task_key).await.map_err(ApiError::internal)?;
 
     if task.complete {
-        return Err(actix_web::error::ErrorForbidden("Task is already complete"));
+        return Err(ApiError::new(ErrorCode::TaskAlreadyComplete, "Task is already complete"));
     }
 
     let player_proximity_key = format!("player:{}:proximity", player_id);
-    let proximity: PlayerProximity = redis.get_key(&player_proximity_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
+    let proximity: PlayerProximity = store.get_key(&player_proximity_key).await.map_err(ApiError::internal)?;
     if let Some(buildings) = proximity.nearby_buildings {
         for building in buildings {
-            if !task.dropped_off && building.id == _id.0.destination.as_ref().unwrap().id {
-                let mut updated_task = task.clone();
-                updated_task.dropped_off = true;
-                updated_player.used_action = Some(tonk_shared_lib::ActionStatus::NextDepot);
-                redis.set_key(&player_key, &updated_player).await.map_err(|e| {
-                    error!("{:?}", e);
-                    actix_web::error::ErrorInternalServerError("Unknown error")
-                })?;
-                redis.set_key(&task_key, &updated_task).await.map_err(|e| {
-                    actix_web::error::ErrorInternalServerError("Unknown error")
-                })?;
+            // each step re-checks the latest copy of the task, so a repeated request can't apply it twice
+            if !task.dropped_off && _id.0.destination.as_ref().map(|d| d.id == building.id).unwrap_or(false) {
+                let updated_task = store.update_key(&task_key, |mut updated_task: Task| {
+                    if updated_task.dropped_off {
+                        return None;
+                    }
+                    updated_task.dropped_off = true;
+                    Some(updated_task)
+                }).await?;
+                let updated_task = updated_task.ok_or_else(|| ApiError::new(ErrorCode::TaskAlreadyUpdated, "Task was already updated"))?;
+                update_player(store.get_ref(), player_id, |updated_player| {
+                    updated_player.used_action = Some(tonk_shared_lib::ActionStatus::NextDepot);
+                }).await?;
+                record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::TaskDroppedOff {
+                    round,
+                    second: false
+                }).await.map_err(ApiError::internal)?;
                 return Ok(HttpResponse::Ok().json(updated_task));
             }
-            if !task.dropped_off_second && building.id == _id.0.second_destination.as_ref().unwrap().id {
-                let mut updated_task = task.clone();
-                updated_task.dropped_off_second = true;
-                updated_player.used_action = Some(tonk_shared_lib::ActionStatus::ReturnToTower);
-                redis.set_key(&player_key, &updated_player).await.map_err(|e| {
-                    error!("{:?}", e);
-                    actix_web::error::ErrorInternalServerError("Unknown error")
-                })?;
-                redis.set_key(&task_key, &updated_task).await.map_err(|e| {
-                    actix_web::error::ErrorInternalServerError("Unknown error")
-                })?;
+            if !task.dropped_off_second && _id.0.second_destination.as_ref().map(|d| d.id == building.id).unwrap_or(false) {
+                let updated_task = store.update_key(&task_key, |mut updated_task: Task| {
+                    if updated_task.dropped_off_second {
+                        return None;
+                    }
+                    updated_task.dropped_off_second = true;
+                    Some(updated_task)
+                }).await?;
+                let updated_task = updated_task.ok_or_else(|| ApiError::new(ErrorCode::TaskAlreadyUpdated, "Task was already updated"))?;
+                update_player(store.get_ref(), player_id, |updated_player| {
+                    updated_player.used_action = Some(tonk_shared_lib::ActionStatus::ReturnToTower);
+                }).await?;
+                record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::TaskDroppedOff {
+                    round,
+                    second: true
+                }).await.map_err(ApiError::internal)?;
                 return Ok(HttpResponse::Ok().json(updated_task));
             }
             if !task.complete && building.is_tower && (task.dropped_off_second && task.dropped_off) {
-                let mut completed_task = task.clone();
-                completed_task.complete = true;
-                redis.set_key(&task_key, &completed_task).await.map_err(|e| {
-                    actix_web::error::ErrorInternalServerError("Unknown error")
-                })?;
-
-                updated_player.used_action = Some(tonk_shared_lib::ActionStatus::TaskComplete);
-                updated_player.last_round_action = Some(round);
-                redis.set_key(&player_key, &updated_player).await.map_err(|e| {
-                    error!("{:?}", e);
-                    actix_web::error::ErrorInternalServerError("Unknown error")
-                })?;
+                let completed_task = store.update_key(&task_key, |mut completed_task: Task| {
+                    if completed_task.complete {
+                        return None;
+                    }
+                    completed_task.complete = true;
+                    Some(completed_task)
+                }).await?;
+                let completed_task = completed_task.ok_or_else(|| ApiError::new(ErrorCode::TaskAlreadyComplete, "Task is already complete"))?;
+
+                update_player(store.get_ref(), player_id, |updated_player| {
+                    updated_player.used_action = Some(tonk_shared_lib::ActionStatus::TaskComplete);
+                    updated_player.last_round_action = Some(round);
+                }).await?;
+                record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::TaskCompleted {
+                    round
+                }).await.map_err(ApiError::internal)?;
 
                 return Ok(HttpResponse::Ok().json(completed_task));
             }
         }
-        return Err(actix_web::error::ErrorForbidden("Player is not near the task building"));
+        Err(ApiError::new(ErrorCode::NotNearTaskBuilding, "Player is not near the task building"))
     } else {
-        return Err(actix_web::error::ErrorForbidden("Player is not near any buildings"));
+        Err(ApiError::new(ErrorCode::NotNearAnyBuilding, "Player is not near any buildings"))
     }
 }
\ No newline at end of file
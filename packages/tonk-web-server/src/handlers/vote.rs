@@ -1,29 +1,24 @@
-use actix_web::{web, Error, HttpResponse, HttpRequest};
-use tonk_shared_lib::{Vote, Game, Player, GameStatus};
+use actix_web::{web, HttpResponse};
+use tonk_shared_lib::{Vote, Player, GameStatus};
 use serde::{Deserialize, Serialize};
-use tonk_shared_lib::redis_helper::*;
-use log::*;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::redis_helper::RedisHelperError;
+use tonk_shared_lib::events::{record_event, Actor, GameEventKind};
+use super::{load_game, update_player};
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct VoteQuery {
-    player_id: String,
-    secret_key: String 
+    player_id: String
 }
 
 // USED TO CONFIRM SUCCESSFUL COMPLETION OF TASK
-pub async fn post_vote(_id: web::Json<Vote>, _query: web::Query<VoteQuery>, req: HttpRequest) -> Result<HttpResponse, Error> {
-    let mut redis = RedisHelper::init().await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError(e)
-    })?;
-
-    let game: Game = redis.get_key("game").await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
+pub async fn post_vote(store: web::Data<dyn StateStore>, game_id: web::Path<String>, _id: web::Json<Vote>, _query: web::Query<VoteQuery>) -> Result<HttpResponse, ApiError> {
+    let game = load_game(store.get_ref(), &game_id).await?;
     let round = game.time.unwrap().round;
     if game.status != GameStatus::Vote {
-        return Err(actix_web::error::ErrorForbidden("The game is not in the voting round"));
+        return Err(ApiError::new(ErrorCode::GameNotInVoteRound, "The game is not in the voting round"));
     }
 
     let mut vote = _id.0.clone();
@@ -31,54 +26,55 @@ pub async fn post_vote(_id: web::Json<Vote>, _query: web::Query<VoteQuery>, req:
     let player_key = format!("player:{}", player_id);
 
     let index_key = format!("game:{}:player_index", game.id);
-    let player_keys: Vec<String> = redis.get_index_keys(&index_key).await.map_err(|e| { 
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
+    let player_keys: Vec<String> = store.get_index_keys(&index_key).await.map_err(ApiError::internal)?;
 
     if player_keys.iter().find(|k| **k == player_key).is_none() {
-        return Err(actix_web::error::ErrorForbidden("Player is not in the game"));
+        return Err(ApiError::new(ErrorCode::PlayerNotInGame, "Player is not in the game"));
     }
 
 
-    let candidate_key = format!("player:{}", vote.candidate.id);
     let vote_key = format!("vote:{}:{}:{}", game.id, round, player_id);
+    let votes_index_key = format!("game:{}:votes", game.id);
 
-    let candidate: Player = redis.get_key(&candidate_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
-
-    let mut player: Player = redis.get_key(&player_key).await.map_err(|e| {
-        error!("{:?}", e);
-        actix_web::error::ErrorInternalServerError("Unknown error")
-    })?;
-
-    let saved_vote: Result<Vote, _> = redis.get_key(&vote_key).await;
-    match saved_vote {
-        Err(RedisHelperError::MissingKey) => {
-            vote.candidate.display_name = candidate.display_name.clone();
-            vote.candidate.role = candidate.role.clone();
-            let _ = redis.set_key(&vote_key, &vote).await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError("Unknown error")
-            })?;
-            player.used_action = Some(tonk_shared_lib::ActionStatus::Voted);
-            player.last_round_action = Some(round);
-            redis.set_key(&player_key, &player).await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError("Unknown error")
-            })?;
-            redis.add_to_index("game:votes", &vote_key).await.map_err(|e| {
-                error!("{:?}", e);
-                actix_web::error::ErrorInternalServerError("Unknown error")
-            })?;
-        } Ok(_) => {
-            return Err(actix_web::error::ErrorForbidden("You have already made your vote this round"));
-        } _ => {
-            return Err(actix_web::error::ErrorInternalServerError("Unknown error"));
+    // a ballot without a candidate is an abstention
+    if let Some(vote_candidate) = vote.candidate.as_mut() {
+        if let Some(runoff) = game.runoff.as_ref() {
+            if !runoff.contains(&vote_candidate.id) {
+                return Err(ApiError::new(ErrorCode::CandidateNotInRunoff, "Only the tied candidates can be voted for in a runoff"));
+            }
         }
+        let candidate_key = format!("player:{}", vote_candidate.id);
+        if !player_keys.contains(&candidate_key) {
+            return Err(ApiError::new(ErrorCode::PlayerNotInGame, "The candidate is not in the game"));
+        }
+        let candidate: Player = match store.get_key(&candidate_key).await {
+            Ok(candidate) => candidate,
+            Err(RedisHelperError::MissingKey) => {
+                return Err(ApiError::new(ErrorCode::PlayerNotInGame, "The candidate is not in the game"));
+            }
+            Err(e) => return Err(ApiError::internal(e)),
+        };
+        if candidate.eliminated.unwrap_or(false) {
+            return Err(ApiError::new(ErrorCode::InvalidRequest, "The candidate has already been eliminated"));
+        }
+        vote_candidate.display_name = candidate.display_name.clone();
+        vote_candidate.role = candidate.role.clone();
+    }
+    vote.voter = Some(player_id.clone());
+    // the vote key is only ever written once, so two requests racing can't both count
+    let created = store.set_key_if_absent(&vote_key, &vote).await?;
+    if !created {
+        return Err(ApiError::new(ErrorCode::AlreadyVoted, "You have already made your vote this round"));
     }
+    update_player(store.get_ref(), player_id, |player| {
+        player.used_action = Some(tonk_shared_lib::ActionStatus::Voted);
+        player.last_round_action = Some(round);
+    }).await?;
+    store.add_to_index(&votes_index_key, &vote_key).await.map_err(ApiError::internal)?;
+    record_event(store.get_ref(), &game.id, Actor::Player(player_id.clone()), GameEventKind::Voted {
+        round,
+        vote: Box::new(vote)
+    }).await.map_err(ApiError::internal)?;
 
     Ok(HttpResponse::Ok().finish())
 }
\ No newline at end of file
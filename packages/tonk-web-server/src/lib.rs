@@ -1,37 +1,55 @@
-use actix_web::{http::header, get, App, HttpResponse, HttpServer, Responder};
-use actix_cors::Cors;
-use dotenv::dotenv;
+use actix_web::{web, App, HttpServer};
 use std::env;
+use std::sync::Arc;
+use tonk_shared_lib::redis_helper::RedisHelper;
+use tonk_shared_lib::store::StateStore;
+use tonk_shared_lib::settings::{Service, Settings};
 
 mod app_config;
+mod cors;
+mod error;
 mod handlers;
+mod middleware;
 
 pub async fn run() -> std::io::Result<()> {
     env_logger::init();
-    match env::var("TONK_SERVICES_STAGE") {
-        Ok(stage) => {
-            println!("Starting up tonk-web-server in stage: {}", stage);
-            dotenv::from_filename(".env.production").ok();
-        }
-        Err(_) => {
-            println!("Starting up tonk-web-server in stage: {}", "local");
-            dotenv::from_filename(".env.local").ok();
-        }
-    }
-    // let origin = env::var("ALLOWED_ORIGIN").unwrap();
-    HttpServer::new(move || {
+    // a bad or missing setting stops the server before it takes any requests
+    let args: Vec<String> = env::args().skip(1).collect();
+    let settings = Settings::load(Service::WebServer, &args).map_err(|e| {
+        eprintln!("tonk-web-server: {}", e);
+        std::io::Error::new(std::io::ErrorKind::InvalidInput, e)
+    })?;
+    println!("Starting up tonk-web-server in stage: {}", settings.stage);
+
+    let redis = RedisHelper::init(&settings.redis_url).await.map_err(|e| {
+        std::io::Error::other(e)
+    })?;
+    let store: Arc<dyn StateStore> = Arc::new(redis);
+    let store = web::Data::from(store);
+    let hub = web::Data::new(handlers::stream::StreamHub::start(settings.redis_url.clone()));
+    let cors = cors::CorsPolicy::new(&settings);
+    let address = (settings.bind_address, settings.port);
+    let metrics_address = (settings.bind_address, settings.metrics_port);
+    let settings = web::Data::new(settings);
+    // metrics get their own port so they can be kept off the public network
+    let metrics_server = HttpServer::new(|| {
+        App::new().route("/metrics", web::get().to(handlers::metrics::get_metrics))
+    })
+    .workers(1)
+    .bind(metrics_address)?
+    .run();
+    let server = HttpServer::new(move || {
+        let settings = settings.clone();
         App::new()
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allow_any_method()
-                    .allow_any_header()
-                    .send_wildcard()
-                    .max_age(3600)
-            )
-            .configure(app_config::config)
+            .app_data(store.clone())
+            .app_data(hub.clone())
+            .app_data(settings.clone())
+            .wrap(cors.build())
+            .wrap(middleware::metrics::RequestMetrics)
+            .configure(|cfg| app_config::config(cfg, &settings))
     })
-    .bind(("0.0.0.0", 8082))?
-    .run()
-    .await
+    .bind(address)?
+    .run();
+    futures_util::future::try_join(server, metrics_server).await?;
+    Ok(())
 }
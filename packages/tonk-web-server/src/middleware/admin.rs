@@ -0,0 +1,80 @@
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use actix_web::{web, Error};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use futures_util::future::LocalBoxFuture;
+use log::*;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use tonk_shared_lib::settings::Settings;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+pub const ADMIN_KEY_HEADER: &str = "X-Admin-Key";
+
+// compares the digests in constant time, so neither the key nor its length leaks through timing
+fn keys_match(provided: &str, admin_key: &str) -> bool {
+    Sha256::digest(provided.as_bytes()).ct_eq(&Sha256::digest(admin_key.as_bytes())).into()
+}
+
+fn check_admin_key(req: &ServiceRequest) -> Result<(), ApiError> {
+    let settings = req.app_data::<web::Data<Settings>>().ok_or_else(|| {
+        error!("No settings registered with the app");
+        ApiError::new(ErrorCode::InternalError, "Unknown error")
+    })?;
+    let admin_key = settings.admin_key.as_ref().ok_or_else(|| {
+        error!("ADMIN_KEY is not set, the admin API is disabled");
+        ApiError::new(ErrorCode::AdminDisabled, "The admin API is disabled")
+    })?;
+    let provided = req.headers().get(ADMIN_KEY_HEADER).and_then(|v| v.to_str().ok());
+    match provided {
+        Some(key) if keys_match(key, admin_key) => Ok(()),
+        Some(_) => Err(ApiError::new(ErrorCode::InvalidAdminKey, "Invalid admin key")),
+        None => Err(ApiError::new(ErrorCode::MissingAdminKey, "Missing admin key"))
+    }
+}
+
+// Guards the admin scope with the ADMIN_KEY credential from the settings
+pub struct AdminAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for AdminAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AdminAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AdminAuthMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct AdminAuthMiddleware<S> {
+    service: Rc<S>
+}
+
+impl<S, B> Service<ServiceRequest> for AdminAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            check_admin_key(&req)?;
+            service.call(req).await
+        })
+    }
+}
@@ -0,0 +1,126 @@
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::rc::Rc;
+use actix_web::{web, Error, HttpMessage};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use futures_util::future::LocalBoxFuture;
+use tonk_shared_lib::store::*;
+use tonk_shared_lib::settings::{Limit, Settings};
+use log::*;
+use crate::error::ApiError;
+use crate::middleware::session::{claimed_player_id, Session};
+use tonk_shared_lib::error::ErrorCode;
+
+// Limits how often a route can be called by one player and from one address. The
+// counters are kept in the state store so every server instance shares them.
+// The player comes from the session when SessionAuth has already run, otherwise
+// from the player_id in the path or query. The address is the peer's, unless the
+// peer is one of the TRUSTED_PROXIES and says who it forwarded the request for.
+#[derive(Clone)]
+pub struct RateLimit {
+    route: &'static str,
+    per_player: Option<Limit>,
+    per_ip: Option<Limit>,
+    trusted_proxies: Vec<IpAddr>
+}
+
+impl RateLimit {
+    // RATE_LIMIT_<ROUTE>_PLAYER and RATE_LIMIT_<ROUTE>_IP in the settings override the defaults
+    pub fn new(settings: &Settings, route: &'static str, per_player: Limit, per_ip: Limit) -> Self {
+        Self {
+            route,
+            per_player: settings.rate_limit(route, "player", per_player),
+            per_ip: settings.rate_limit(route, "ip", per_ip),
+            trusted_proxies: settings.trusted_proxies.clone()
+        }
+    }
+}
+
+async fn check_limit(store: &dyn StateStore, key: &str, limit: Limit) -> Result<(), ApiError> {
+    // a store outage shouldn't take the game down with it, so the call is let through
+    let (count, reset_in) = match store.increment_counter(key, limit.window).await {
+        Ok(counter) => counter,
+        Err(e) => {
+            error!("Couldn't count a hit for {}: {}", key, e);
+            return Ok(());
+        }
+    };
+    if count > limit.requests {
+        return Err(ApiError::new(ErrorCode::RateLimited, "Too many requests, slow down").with_retry_after(reset_in.max(1)));
+    }
+    Ok(())
+}
+
+// X-Forwarded-For can be set by anyone, so it is only read when a trusted proxy set it
+fn client_address(req: &ServiceRequest, trusted_proxies: &[IpAddr]) -> Option<String> {
+    let peer = req.peer_addr()?.ip();
+    if trusted_proxies.contains(&peer) {
+        return req.connection_info().realip_remote_addr().map(|ip| ip.to_string());
+    }
+    Some(peer.to_string())
+}
+
+async fn check_rate_limit(req: &ServiceRequest, limits: &RateLimit) -> Result<(), ApiError> {
+    let store = req.app_data::<web::Data<dyn StateStore>>().ok_or_else(|| {
+        error!("No state store registered with the app");
+        ApiError::new(ErrorCode::InternalError, "Unknown error")
+    })?.get_ref();
+
+    if let Some(limit) = limits.per_ip {
+        if let Some(ip) = client_address(req, &limits.trusted_proxies) {
+            check_limit(store, &format!("ratelimit:{}:ip:{}", limits.route, ip), limit).await?;
+        }
+    }
+    if let Some(limit) = limits.per_player {
+        let session_player = req.extensions().get::<Session>().map(|s| s.player_id.clone());
+        let player_id = session_player.or_else(|| claimed_player_id(req.request()));
+        if let Some(player_id) = player_id {
+            check_limit(store, &format!("ratelimit:{}:player:{}", limits.route, player_id), limit).await?;
+        }
+    }
+    Ok(())
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RateLimitMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware { service: Rc::new(service), limits: self.clone() }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limits: RateLimit
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limits = self.limits.clone();
+        Box::pin(async move {
+            check_rate_limit(&req, &limits).await?;
+            service.call(req).await
+        })
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::rc::Rc;
+use actix_web::{web, Error, HttpMessage, HttpRequest};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header;
+use futures_util::future::LocalBoxFuture;
+use tonk_shared_lib::redis_helper::*;
+use tonk_shared_lib::store::*;
+use log::*;
+use crate::error::ApiError;
+use tonk_shared_lib::error::ErrorCode;
+
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub player_id: String
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<String> {
+    let value = req.headers().get(header::AUTHORIZATION)?.to_str().ok()?;
+    value.strip_prefix("Bearer ").map(|t| t.trim().to_string())
+}
+
+// the player the request claims to act for, from the path or the query string
+pub fn claimed_player_id(req: &HttpRequest) -> Option<String> {
+    if let Some(player_id) = req.match_info().get("player_id") {
+        return Some(player_id.to_string());
+    }
+    let query = web::Query::<HashMap<String, String>>::from_query(req.query_string()).ok()?;
+    query.get("player_id").cloned()
+}
+
+pub async fn authenticate(req: &HttpRequest) -> Result<Session, ApiError> {
+    let token = bearer_token(req).ok_or_else(|| {
+        ApiError::new(ErrorCode::MissingSession, "Missing session token")
+    })?;
+    let store = req.app_data::<web::Data<dyn StateStore>>().ok_or_else(|| {
+        error!("No state store registered with the app");
+        ApiError::new(ErrorCode::InternalError, "Unknown error")
+    })?;
+    let session_key = format!("session:{}", token);
+    let player_id: String = store.get_key(&session_key).await.map_err(|e| {
+        match e {
+            RedisHelperError::MissingKey => ApiError::new(ErrorCode::InvalidSession, "Invalid session token"),
+            _ => ApiError::from(e)
+        }
+    })?;
+
+    if let Some(claimed) = claimed_player_id(req) {
+        if claimed != player_id {
+            error!("Session for player {} was used to act as player {}", player_id, claimed);
+            return Err(ApiError::new(ErrorCode::SessionMismatch, "Session does not belong to this player"));
+        }
+    }
+
+    Ok(Session { player_id })
+}
+
+// Rejects any request without a valid session token, and any request whose
+// player_id does not match the player the session was issued to
+pub struct SessionAuth;
+
+impl<S, B> Transform<S, ServiceRequest> for SessionAuth
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = SessionAuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(SessionAuthMiddleware { service: Rc::new(service) }))
+    }
+}
+
+pub struct SessionAuthMiddleware<S> {
+    service: Rc<S>
+}
+
+impl<S, B> Service<ServiceRequest> for SessionAuthMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        Box::pin(async move {
+            let session = authenticate(req.request()).await?;
+            req.extensions_mut().insert(session);
+            service.call(req).await
+        })
+    }
+}
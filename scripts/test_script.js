@@ -1,25 +1,41 @@
+const ENDPOINT = "http://0.0.0.0:8082";
+const ADMIN_KEY = process.env.ADMIN_KEY;
 
-async function getGame() {
+// session tokens issued at registration, keyed by player id
+const sessions = {};
+
+function authHeader(id) {
+    return { "Authorization": `Bearer ${sessions[id]}` };
+}
+
+function adminHeader() {
+    return { "X-Admin-Key": ADMIN_KEY };
+}
+
+async function createGame(id) {
     var requestOptions = {
-    method: 'GET',
+    method: 'POST',
+    headers: authHeader(id),
     redirect: 'follow'
     };
 
     try {
-        let response = await fetch("http://0.0.0.0:8082/game", requestOptions);
+        let response = await fetch(`${ENDPOINT}/game`, requestOptions);
         return await response.text();
     } catch (e) {
-        console.log(error);
+        console.log(e);
     }
 }
 
 async function addBuilding(isTower, id) {
-    var myHeaders = new Headers();
+    var myHeaders = new Headers(adminHeader());
     myHeaders.append("Content-Type", "application/json");
 
     var raw = JSON.stringify({
     "id": id,
-    "is_tower": isTower 
+    "readable_id": id,
+    "task_message": "",
+    "is_tower": isTower
     });
 
     var requestOptions = {
@@ -30,7 +46,7 @@ async function addBuilding(isTower, id) {
     };
 
     try {
-        let response = await fetch("http://0.0.0.0:8082/building", requestOptions);
+        let response = await fetch(`${ENDPOINT}/admin/building`, requestOptions);
         let text = await response.text();
         console.log(text);
     } catch (e) {
@@ -38,29 +54,34 @@ async function addBuilding(isTower, id) {
     }
 }
 
-async function registerPlayer(id, hash, secret) {
+async function registerPlayer(id, displayName) {
+    var raw = JSON.stringify({
+    "id": id,
+    "display_name": displayName
+    });
+
     var requestOptions = {
         method: 'POST',
+        headers: { "Content-Type": "application/json" },
+        body: raw,
         redirect: 'follow'
       };
-      
-      try {
 
-        // let response = await fetch(`http://0.0.0.0:8082/player/${id}?secret_key=${secret}&onchain_hash=${hash}`, requestOptions)
-        let response = await fetch(`http://0.0.0.0:8082/player/${id}`, requestOptions)
-        let text = await response.text();
+      try {
+        let response = await fetch(`${ENDPOINT}/player/${id}`, requestOptions)
+        let session = JSON.parse(await response.text());
+        sessions[id] = session.session_token;
       } catch (e) {
         console.log(e);
       }
 }
 
-async function joinGame(game_id, id, key) {
-    var myHeaders = new Headers();
+async function joinGame(game_id, id) {
+    var myHeaders = new Headers(authHeader(id));
     myHeaders.append("Content-Type", "application/json");
 
     var raw = JSON.stringify({
-    "id": id,
-    "secret_key": key
+    "id": id
     });
 
     var requestOptions = {
@@ -71,7 +92,7 @@ async function joinGame(game_id, id, key) {
     };
 
     try {
-        let response = await fetch(`http://0.0.0.0:8082/game/${game_id}/player`, requestOptions)
+        let response = await fetch(`${ENDPOINT}/game/${game_id}/player`, requestOptions)
         let text = await response.text();
         console.log(text);
     } catch (e) {
@@ -79,14 +100,15 @@ async function joinGame(game_id, id, key) {
     }
 }
 
-async function startGame() {
+async function startGame(game_id) {
     var requestOptions = {
         method: 'POST',
+        headers: adminHeader(),
         redirect: 'follow'
       };
-      
+
       try {
-        let response = await fetch("http://0.0.0.0:8082/game", requestOptions)
+        let response = await fetch(`${ENDPOINT}/admin/game/${game_id}/start`, requestOptions)
         let text = await response.text();
         console.log(text);
       } catch (e) {
@@ -96,37 +118,29 @@ async function startGame() {
 
 
 
-var key = "123456678901234567890";
 var id = "2846bc30d997410a97365419ce5145e7"
-var hash = "1369c4f2a54f859b73b198d1e24ac69088540444968df1480530a807211e0861";
-
 var id_2 = "af0j9w83j0a983jf0a9jw3f"
-var key_2 = "09876543210987654321";
-var hash_2 = "aee0cead9c5ff8864ed64b39c32d081111f45c3b8e4a4ef40cf944490081c589";
-
 var id_3 = "af0j9w8fja83jajfhf832h";
-var key_3 = "1337133713371337";
-var hash_3 = "52cb23e48906c3bf9f72f960295bb0c72a466c631ce813541753baeb853bee7b";
 
 async function test() {
-    var gameJSON = await getGame();
-    var game = JSON.parse(gameJSON);
-
     await addBuilding(false, "20389jr09q8j23f")
     await addBuilding(false, "09fj3049ujw4f")
     await addBuilding(false, "09awifj09asidjf0aw")
     await addBuilding(false, "djif09aisdjf09asijdf")
     await addBuilding(true, "a0s9idfj09asijdf")
 
-    await registerPlayer(id, hash, key)
-    await registerPlayer(id_2, hash_2, key_2)
-    await registerPlayer(id_3, hash_3, key_3)
+    await registerPlayer(id, "one")
+    await registerPlayer(id_2, "two")
+    await registerPlayer(id_3, "three")
+
+    var gameJSON = await createGame(id);
+    var game = JSON.parse(gameJSON);
 
-    await joinGame(game.id, id, key)
-    await joinGame(game.id, id_2, key_2)
-    await joinGame(game.id, id_3, key_3)
+    await joinGame(game.id, id)
+    await joinGame(game.id, id_2)
+    await joinGame(game.id, id_3)
 
-    await startGame()
+    await startGame(game.id)
 }
 
-test();
\ No newline at end of file
+test();
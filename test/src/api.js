@@ -1,26 +1,50 @@
 const ENDPOINT = "http://localhost:8082"
 
-async function getGame() {
+// session tokens issued at registration, keyed by player id
+const sessions = {};
+
+function authHeader(playerId) {
+    return { "Authorization": `Bearer ${sessions[playerId]}` };
+}
+
+async function getGames() {
     try {
         let response = await fetch(`${ENDPOINT}/game`);
         let raw = await response.text();
         return JSON.parse(raw);
+    } catch (e) {
+        console.log(e);
+        return [];
+    }
+}
+
+async function getLobby() {
+    let games = await getGames();
+    return games.find((g) => g.status == "Lobby");
+}
+
+async function getGame(gameId) {
+    try {
+        let response = await fetch(`${ENDPOINT}/game/${gameId}`);
+        let raw = await response.text();
+        return JSON.parse(raw);
     } catch (e) {
         console.log(e);
         return (`{ "status": "GameServerDown" }`)
     }
 }
 
-async function requestStart() {
+async function requestStart(gameId, playerId) {
     // var myHeaders = new Headers();
     // myHeaders.append("Content-Type", "application/json");
 
     var requestOptions = {
         method: 'POST',
+        headers: authHeader(playerId),
     };
 
     try {
-        let response = await fetch(`${ENDPOINT}/game`, requestOptions)
+        let response = await fetch(`${ENDPOINT}/game/${gameId}/start`, requestOptions)
         let text = await response.text();
         console.log(text);
     } catch (e) {
@@ -29,7 +53,7 @@ async function requestStart() {
 }
 
 
-async function requestJoin(gameId, playerId, secretKey) {
+async function requestJoin(gameId, playerId) {
     // var myHeaders = new Headers();
     // myHeaders.append("Content-Type", "application/json");
 
@@ -41,7 +65,8 @@ async function requestJoin(gameId, playerId, secretKey) {
         method: 'POST',
         headers: {
             "Content-Type": "application/json",
-            "Sec-Fetch-Mode": "cors"
+            "Sec-Fetch-Mode": "cors",
+            ...authHeader(playerId)
         },
         mode: "cors",
         body: raw,
@@ -56,7 +81,7 @@ async function requestJoin(gameId, playerId, secretKey) {
     }
 }
 
-async function sendVote(candidateId, player) {
+async function sendVote(game, candidateId, player) {
     var raw = JSON.stringify({
         candidate: {
             id: candidateId
@@ -67,14 +92,15 @@ async function sendVote(candidateId, player) {
         method: 'POST',
         headers: {
             "Content-Type": "application/json",
-            "Sec-Fetch-Mode": "cors"
+            "Sec-Fetch-Mode": "cors",
+            ...authHeader(player.id)
         },
         mode: "cors",
         body: raw,
     };
 
     try {
-        let response = await fetch(`${ENDPOINT}/vote?player_id=${player.id}&secret_key=fff`, requestOptions)
+        let response = await fetch(`${ENDPOINT}/game/${game.id}/vote?player_id=${player.id}`, requestOptions)
         let text = await response.text();
         console.log(text);
     } catch (e) {
@@ -98,7 +124,7 @@ function isInGame(players, playerId) {
 
 async function getPlayers(gameId, playerId) {
     try {
-        let response = await fetch(`${ENDPOINT}/game/${gameId}/player?player_id=${playerId}`);
+        let response = await fetch(`${ENDPOINT}/game/${gameId}/player?player_id=${playerId}`, { headers: authHeader(playerId) });
         let raw = await response.text();
         return JSON.parse(raw);
     } catch (e) {
@@ -113,7 +139,7 @@ async function getLastRoundResult(game) {
     console.log("tonk getLastRound", game.time.round-1)
     try {
         let lastRound = game.time.round - 1;
-        let response = await fetch(`${ENDPOINT}/game/result/${lastRound}`)
+        let response = await fetch(`${ENDPOINT}/game/${game.id}/result/${lastRound}`)
         let text = await response.text();
         return JSON.parse(text);
     } catch (e) {
@@ -121,7 +147,7 @@ async function getLastRoundResult(game) {
     }
 }
 
-async function registerPlayer(id, mobileUnitId, displayName, hash, secret) {
+async function registerPlayer(id, mobileUnitId, displayName) {
     var raw = JSON.stringify({
         id: id, 
         mobile_unit_id: mobileUnitId,
@@ -136,17 +162,17 @@ async function registerPlayer(id, mobileUnitId, displayName, hash, secret) {
       };
       
       try {
-        // let response = await fetch(`${ENDPOINT}/player/${id}?secret_key=${secret}&onchain_hash=${hash}`, requestOptions)
         let response = await fetch(`${ENDPOINT}/player/${id}`, requestOptions)
-        // let text = await response.text();
+        let session = JSON.parse(await response.text());
+        sessions[id] = session.session_token;
       } catch (e) {
         console.log(e);
       }
 }
 
-async function getTask(player) {
+async function getTask(game, player) {
     try {
-        let response = await fetch(`${ENDPOINT}/task?player_id=${player.id}&secret_key=fff`);
+        let response = await fetch(`${ENDPOINT}/game/${game.id}/task?player_id=${player.id}`, { headers: authHeader(player.id) });
         let text = await response.text();
         return JSON.parse(text);
     } catch (e) {
@@ -154,18 +180,19 @@ async function getTask(player) {
     }
 }
 
-async function postTask(task, player) {
+async function postTask(game, task, player) {
     var raw = JSON.stringify(task);
     var requestOptions = {
         method: 'POST',
         headers: {
             "Content-Type": "application/json",
+            ...authHeader(player.id)
         },
         body: raw
       };
       
       try {
-        let response = await fetch(`${ENDPOINT}/task?player_id=${player.id}&secret_key=fff`, requestOptions)
+        let response = await fetch(`${ENDPOINT}/game/${game.id}/task?player_id=${player.id}`, requestOptions)
       } catch (e) {
         console.log(e);
       }
@@ -182,12 +209,13 @@ async function postAction(target, game, player, confirmed) {
         method: 'POST',
         headers: {
             "Content-Type": "application/json",
+            ...authHeader(player.id)
         },
         body: raw
       };
       
       try {
-        let response = await fetch(`${ENDPOINT}/action?player_id=${player.id}&secret_key=fff`, requestOptions)
+        let response = await fetch(`${ENDPOINT}/game/${game.id}/action?player_id=${player.id}`, requestOptions)
         return response;
       } catch (e) {
         console.log(e);
@@ -195,6 +223,8 @@ async function postAction(target, game, player, confirmed) {
 }
 
 module.exports = {
+    getGames,
+    getLobby,
     getGame,
     requestStart,
     requestJoin,
@@ -4,10 +4,12 @@ const { createClient } = require('redis');
 const path = require('path');
 
 let ENDPOINT_LOCAL = "http://localhost:8082";
+let ADMIN_KEY = process.env.ADMIN_KEY || "local-admin-key";
 
 async function register_building(id, readable_id, is_tower, message, location) {
     var myHeaders = new Headers();
     myHeaders.append("Content-Type", "application/json");
+    myHeaders.append("X-Admin-Key", ADMIN_KEY);
 
     var raw = JSON.stringify({
         "id": id,
@@ -25,7 +27,7 @@ async function register_building(id, readable_id, is_tower, message, location) {
     };
 
     let endpoint = ENDPOINT_LOCAL; 
-    return fetch(`${endpoint}/building`, requestOptions)
+    return fetch(`${endpoint}/admin/building`, requestOptions)
         .then(response => response.text())
         .then(result => console.log(result))
         .catch(error => console.log('error', error))
@@ -1,4 +1,4 @@
-const { getGame, requestStart, requestJoin, sendVote, getPlayer, isInGame, getPlayers, registerPlayer, getTask, postTask, postAction, getLastRoundResult } = require('../api');
+const { getGame, getLobby, requestStart, requestJoin, sendVote, getPlayer, isInGame, getPlayers, registerPlayer, getTask, postTask, postAction, getLastRoundResult } = require('../api');
 const { i32ToHexTwosComplement, hexTwosComplementToI32, Cube, getRandomCoordinateAtDistance, cubeFromHex, sleep } = require('../utility');
 const { building_coords } = require('../setup');
 const { createClient } = require('redis');
@@ -177,7 +177,7 @@ async function clockToZero(game) {
     const client = await createClient()
         .on('error', err => console.log('Redis Client Error', err))
         .connect();
-    await client.set("clock", JSON.stringify({
+    await client.set(`game:${game.id}:clock`, JSON.stringify({
         status: game.status,
         time: {
             round: game.time.round,
@@ -188,7 +188,7 @@ async function clockToZero(game) {
 }
 
 async function run() {
-    let game = await getGame();
+    let game = await getLobby();
     await registerPlayer("0x0", "node0", "TheHackz0r");
     await registerPlayer("0x1", "node1", "GoblinOats");
     await registerPlayer("0x2", "node2", "Baz");
@@ -207,7 +207,7 @@ async function run() {
     await requestJoin(game.id, "0x6");
     await requestJoin(game.id, "0x7");
     await requestJoin(game.id, "0x8");
-    await requestStart();
+    await requestStart(game.id, "0x0");
 
     const player = await getPlayer("0x0");
     const player1 = await getPlayer("0x1");
@@ -231,11 +231,11 @@ async function run() {
     // TASK ROUND
     for (let i = 0; i < civilians.length; i++) {
         let civilian = civilians[i];
-        let task = await getTask({ id: civilian.id });
+        let task = await getTask(game, { id: civilian.id });
         tasks.push(task);
         await moveToTaskLocation(civilian, task, false);
         await sleep(2000);
-        await postTask(task, civilian);
+        await postTask(game, task, civilian);
 
         await moveToTaskLocation(civilian, task, true);
     }
@@ -274,22 +274,22 @@ async function run() {
 
     for (let i = 0; i < civilians.length; i++) {
         let civilian = civilians[i];
-        let task = await getTask({ id: civilian.id });
-        await postTask(task, civilian);
+        let task = await getTask(game, { id: civilian.id });
+        await postTask(game, task, civilian);
         await moveToTower(civilian);
     }
 
     await sleep(2000);
     for (let i = 0; i < civilians.length; i++) {
         let civilian = civilians[i];
-        let task = await getTask({ id: civilian.id });
-        await postTask(task, civilian);
+        let task = await getTask(game, { id: civilian.id });
+        await postTask(game, task, civilian);
     }
 
     // await clockToZero(game);
     await sleep(10000);
 
-    game = await getGame();
+    game = await getGame(game.id);
     console.log(JSON.stringify(game, null, 2));
     if (game.status == "Lobby" || game.status == "Task") {
         console.log("State didn't transition :/");
@@ -308,7 +308,7 @@ async function run() {
     // At this stage, one or two of the civilians are killed which leaves 1,7 or 2,6 remaining
     for (let i = 0; i < bugs.length; i++) {
         let bug = bugs[i];
-        await sendVote(civilians[0].id, bug);
+        await sendVote(game, civilians[0].id, bug);
     }
 
     for (let i = 0; i < civilians.length; i++) {
@@ -316,11 +316,11 @@ async function run() {
         if ( i == 0 ) {
             // await sendVote(civilians[1]);
         } else {
-            await sendVote(civilians[0].id, civilian);
+            await sendVote(game, civilians[0].id, civilian);
         }
     }
 
-    game = await getGame();
+    game = await getGame(game.id);
     await clockToZero(game);
 
     // let task_1 = await getTask({ id: civilians[0].id });